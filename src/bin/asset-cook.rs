@@ -0,0 +1,201 @@
+//Converts source assets into the formats the engine loads at runtime.
+//
+//usage: asset-cook <asset dir> [--optimize] [--quantize] [--manifest <file>]
+//
+//Every model of every OBJ file below the asset directory is cooked into the mesh cache next to it, which is where
+//load_obj_scene and the AssetLoader look first. A manifest listing every produced file with its hash is written at the end (by default
+//<asset dir>/cache/manifest.txt). The exit code is non-zero if any asset failed to convert.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use vkguide_erupt::engine::{
+    mesh,
+    mesh_cache::{self, CookedFile, CookedMesh},
+    mesh_optimize,
+};
+
+struct Options {
+    root: PathBuf,
+    manifest: PathBuf,
+    optimize: bool,
+    quantize: bool,
+}
+
+struct ManifestEntry {
+    source: PathBuf,
+    output: PathBuf,
+    size: u64,
+    hash: u64,
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: asset-cook <asset dir> [--optimize] [--quantize] [--manifest <file>]");
+            process::exit(2);
+        }
+    };
+
+    let mut sources = Vec::new();
+    if let Err(e) = collect_files(&options.root, &mut sources) {
+        eprintln!("failed to read {:?}: {}", options.root, e);
+        process::exit(1);
+    }
+    sources.sort();
+
+    let mut manifest = Vec::new();
+    let mut failures = 0;
+    for source in &sources {
+        match source.extension().and_then(|extension| extension.to_str()) {
+            Some("obj") => match cook_mesh(source, &options) {
+                Ok(entry) => {
+                    println!("cooked {:?} -> {:?} ({} bytes)", entry.source, entry.output, entry.size);
+                    manifest.push(entry);
+                }
+                Err(e) => {
+                    eprintln!("failed to cook {:?}: {}", source, e);
+                    failures += 1;
+                }
+            },
+            //glTF and images are still loaded from their source formats at runtime
+            _ => {}
+        }
+    }
+
+    if let Err(e) = write_manifest(&options, &manifest) {
+        eprintln!("failed to write manifest {:?}: {}", options.manifest, e);
+        failures += 1;
+    }
+
+    println!("{} assets cooked, {} failed", manifest.len(), failures);
+    if failures > 0 {
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut root = None;
+    let mut manifest = None;
+    let mut optimize = false;
+    let mut quantize = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--optimize" => optimize = true,
+            "--quantize" => quantize = true,
+            "--manifest" => {
+                manifest = Some(PathBuf::from(
+                    args.next().ok_or("--manifest needs a file name")?,
+                ))
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if root.is_none() => root = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let root = root.ok_or("no asset directory given")?;
+    let manifest = manifest.unwrap_or_else(|| root.join("cache").join("manifest.txt"));
+    Ok(Options {
+        root,
+        manifest,
+        optimize,
+        quantize,
+    })
+}
+
+//Recursively lists all files below `dir`, skipping the cache directories this tool writes to
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().map_or(false, |name| name == "cache") {
+                continue;
+            }
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn cook_mesh(source: &Path, options: &Options) -> Result<ManifestEntry, Box<dyn std::error::Error>> {
+    let mut cooked = mesh::cook_obj(source)?;
+    if cooked.submeshes.iter().all(|submesh| submesh.mesh.vertices.is_empty()) {
+        return Err("no geometry in file".into());
+    }
+
+    if options.optimize {
+        optimize(source, &mut cooked);
+    }
+
+    let output = mesh_cache::cache_path(source);
+    let size = mesh_cache::write_cooked(&output, source, &cooked, options.quantize)?;
+    let hash = mesh_cache::hash_file(&output).ok_or("cooked file could not be read back")?;
+
+    Ok(ManifestEntry {
+        source: relative(source, &options.root),
+        output: relative(&output, &options.root),
+        size,
+        hash,
+    })
+}
+
+fn optimize(source: &Path, cooked: &mut CookedFile) {
+    for submesh in &mut cooked.submeshes {
+        if submesh.mesh.vertices.is_empty() {
+            continue;
+        }
+        let mesh = &submesh.mesh;
+        let (vertices, indices) = mesh_optimize::weld(&mesh.vertices, &mesh.indices);
+        let before = mesh_optimize::average_cache_miss_ratio(&indices, 16);
+        let optimized = mesh_optimize::optimize_vertex_cache(&indices, vertices.len());
+        let after = mesh_optimize::average_cache_miss_ratio(&optimized, 16);
+        //some exporters already write a good order, keep it if the reordering does not help
+        let mut indices = if after < before { optimized } else { indices };
+        let vertices = mesh_optimize::optimize_vertex_fetch(&vertices, &mut indices);
+        println!(
+            "{:?} {:?}: {} -> {} vertices, cache misses per triangle {:.2} -> {:.2}",
+            source,
+            submesh.name,
+            mesh.vertices.len(),
+            vertices.len(),
+            before,
+            before.min(after)
+        );
+        submesh.mesh = CookedMesh::new(vertices, indices);
+    }
+}
+
+//One line per cooked asset: hash, size in bytes, output and source path relative to the asset directory
+fn write_manifest(options: &Options, manifest: &[ManifestEntry]) -> std::io::Result<()> {
+    let mut text = format!(
+        "# asset-cook manifest, mesh format version {}\n# hash\tsize\toutput\tsource\n",
+        mesh_cache::COOKED_MESH_VERSION
+    );
+    for entry in manifest {
+        text.push_str(&format!(
+            "{:016x}\t{}\t{}\t{}\n",
+            entry.hash,
+            entry.size,
+            entry.output.display(),
+            entry.source.display()
+        ));
+    }
+
+    if let Some(dir) = options.manifest.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&options.manifest, text)
+}
+
+fn relative(path: &Path, root: &Path) -> PathBuf {
+    path.strip_prefix(root).unwrap_or(path).to_path_buf()
+}
@@ -1,70 +1,280 @@
+mod assets;
 mod buffer;
 mod camera;
+pub mod compute;
+mod deferred;
+mod deletion;
 mod descriptors;
 mod device;
 mod frame;
-mod mesh;
+mod frame_allocator;
+mod gltf_loader;
+mod gpu_driven;
+mod handles;
+mod ibl;
+mod light;
+mod material;
+mod memory_stats;
+pub mod mesh;
+pub mod mesh_cache;
+pub mod mesh_optimize;
+pub mod mesh_pool;
+mod obj_loader;
 mod pipeline;
+mod postprocess;
+pub mod render_graph;
+mod render_queue;
 mod renderpass;
 mod scene;
+mod shadow;
+mod skybox;
 mod swapchain;
+mod texture;
+mod upload;
 extern crate nalgebra as na;
 extern crate nalgebra_glm as glm;
 
 use erupt::vk::{self};
 use nalgebra::Vector3;
-use std::{ffi::c_void, mem::size_of_val};
-
-use gpu_alloc::{Request, UsageFlags};
-use gpu_alloc_erupt::EruptMemoryDevice;
+use std::{ffi::c_void, mem::size_of_val, rc::Rc};
 
 use winit::window::Window;
 
 use crate::engine::{descriptors::Descriptors, device::Physical, frame::Frames, mesh::Vertex, renderpass::RenderPass, swapchain::Swapchain};
+use crate::engine::render_graph::{Access, CompiledGraph, External, ImageDesc, PassId, RenderGraph, ResourceId};
+
+use self::{
+    assets::AssetLoader,
+    compute::AsyncCompute,
+    deferred::{Deferred, GBUFFER_FORMATS, GBUFFER_NAMES},
+    deletion::DeletionQueue,
+    frame::{Frame, GPUCameraData},
+    gpu_driven::GpuScene,
+    ibl::EnvironmentLighting,
+    light::Light,
+    material::MaterialResources,
+    mesh::Mesh,
+    pipeline::{MeshPushConstants, PipelineStruct, PulledPushConstants, OPAQUE_PIPELINE},
+    render_queue::{BindStats, RenderItem, RenderQueue},
+    renderpass::{DEPTH_FORMAT, HDR_FORMAT},
+    scene::Scene,
+    postprocess::PostChain,
+    shadow::{Cascades, ShadowMap},
+    skybox::Skybox,
+    texture::Texture,
+    upload::{UploadManager, Uploaded},
+};
+
+pub use self::{
+    assets::LoadState,
+    deferred::{GBufferView, ShadingPath},
+    memory_stats::{CategoryStats, HeapBudget, HeapStats, MemoryCategory, MemoryStats},
+    postprocess::{PostEffect, TonemapOperator},
+    shadow::ShadowSettings,
+};
+
+//camera projection
+const FOV_Y: f32 = 3.14 / 1.5;
+const Z_NEAR: f32 = 0.1;
+const Z_FAR: f32 = 200.0;
+
+//threads parsing meshes and textures in the background
+const ASSET_LOADER_THREADS: usize = 2;
 
-use self::{frame::{Frame, GPUCameraData}, mesh::Mesh, pipeline::PipelineStruct, scene::{Material, Scene}};
+//texels per side of cubemaps made from equirectangular images
+const ENVIRONMENT_FACE_SIZE: u32 = 512;
+
+//Options that have to be chosen before the renderer is created
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    //MSAA samples per pixel, lowered to what the device supports. 1 turns it off. Ignored by the deferred path.
+    pub msaa_samples: u32,
+    pub shading: ShadingPath,
+    //what the deferred path shows, can be changed later with VulkanApp::set_gbuffer_view
+    pub gbuffer_view: GBufferView,
+    pub shadows: ShadowSettings,
+    //run in order on the hdr image, the last one writes to the screen
+    pub post_effects: Vec<PostEffect>,
+    //prints the compiled render graph at startup
+    pub dump_render_graph: bool,
+    //culls the opaque objects in a compute pass and draws them with indirect draws, forward path only
+    pub gpu_driven: bool,
+    //material pipelines read vertices from the mesh pool by device address, without fixed function vertex input
+    pub vertex_pulling: bool,
+    //sizes of the memory objects the allocator makes and suballocates from
+    pub allocator: gpu_alloc::Config,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            msaa_samples: 4,
+            shading: ShadingPath::Forward,
+            gbuffer_view: GBufferView::Lit,
+            shadows: ShadowSettings::default(),
+            post_effects: postprocess::default_post_effects(),
+            dump_render_graph: false,
+            gpu_driven: false,
+            vertex_pulling: false,
+            allocator: gpu_alloc::Config::i_am_potato(),
+        }
+    }
+}
+
+//Passes of the frame's render graph, matched against the graph's order while recording
+struct FramePasses {
+    shadow: PassId,
+    //deferred path only
+    gbuffer: Option<PassId>,
+    lighting: Option<PassId>,
+    //every object with the forward path, only transparent ones and the skybox with the deferred path
+    scene: PassId,
+    post: Vec<PassId>,
+}
+
+//Transient images of the frame's render graph the render passes need views of
+struct FrameTargets {
+    depth: ResourceId,
+    msaa_color: Option<ResourceId>,
+    hdr: ResourceId,
+    //empty with the forward path
+    gbuffer: Vec<ResourceId>,
+    post_intermediates: Vec<ResourceId>,
+}
+
+//Which objects of the render queue draw_objects records
+#[derive(Copy, Clone, PartialEq)]
+enum QueuePart {
+    Opaque,
+    Transparent,
+}
 
-//This needs to be in order of what needs to be destroyed first - The Drop trait destroys them in order of declaration, i.e the first item is destroyed first.
+//Every Vulkan object the app holds releases itself when dropped, physical has to stay the last field since it destroys
+//the device they were made with. Drop only waits for the device and stops the background threads before that.
 pub struct VulkanApp {
+    assets: AssetLoader,
+    render_queue: RenderQueue,
+    frame_stats: BindStats,
     scene: Scene,
+    material_resources: MaterialResources,
+    #[allow(dead_code)]
+    environment: EnvironmentLighting,
+    shadow_map: ShadowMap,
+    deferred: Option<Deferred>,
+    gpu_scene: Option<GpuScene>,
+    deletion: DeletionQueue,
+    //the frame draw was last called with, resources retired between draws are tagged with it
+    framenumber: i64,
+    post_chain: PostChain,
     descs: Descriptors,
+    async_compute: AsyncCompute,
+    uploads: UploadManager,
     frames: Frames,
     render_pass: RenderPass,
+    graph: CompiledGraph,
+    frame_passes: FramePasses,
     swapchain: Swapchain,
     physical: Physical,
 }
 
 impl VulkanApp {
-    pub fn new(window: &Window) -> Self {
+    pub fn new(window: &Window, settings: RenderSettings) -> Self {
         //window/wi
         //this needs to be mut because device and the allocator gets mutated when doing commands
-        let mut physical = Physical::new(window);
+        let mut physical = Physical::new(window, settings.allocator);
 
         let swapchain = Swapchain::new(&physical);
 
-        let render_pass = RenderPass::new(&mut physical, &swapchain);
+        let deferred = settings.shading == ShadingPath::Deferred;
+        //the G-buffer has one sample per pixel, and the forward pass on top has to match its depth
+        let samples = if deferred {
+            vk::SampleCountFlagBits::_1
+        } else {
+            physical.usable_sample_count(settings.msaa_samples)
+        };
 
-        let mut descs = Descriptors::new(&mut physical);
+        let shadow_map = ShadowMap::new(&mut physical, settings.shadows);
 
-        let frames = Frames::new(2, &mut physical, &mut descs);
+        let (graph, frame_passes, targets) = Self::build_render_graph(
+            &mut physical,
+            &swapchain,
+            &shadow_map,
+            samples,
+            deferred,
+            &settings.post_effects,
+        );
+        if settings.dump_render_graph {
+            print!("{}", graph.dump());
+        }
 
-        let pipeline = PipelineStruct::new(&physical, &render_pass, &descs);
+        //with the deferred path the lighting pass has already filled the hdr image
+        let render_pass = RenderPass::new(
+            &mut physical,
+            samples,
+            graph.image_view(targets.hdr),
+            graph.image_view(targets.depth),
+            targets.msaa_color.map(|color| graph.image_view(color)),
+            !deferred,
+        );
 
-        let mut scene = Scene::new();
-        let mesh = Mesh::new(
-            std::path::Path::new(
-                "D:/rustprogramming/vulkan-guide/vkguide-erupt/src/assets/monkey_smooth.obj",
-            ),
+        let intermediate_views: Vec<vk::ImageView> = targets
+            .post_intermediates
+            .iter()
+            .map(|intermediate| graph.image_view(*intermediate))
+            .collect();
+        let post_chain = PostChain::new(
             &mut physical,
+            &swapchain,
+            graph.image_view(targets.hdr),
+            &intermediate_views,
+            &settings.post_effects,
         );
+
+        let mut descs = Descriptors::new(&mut physical);
+
+        let deferred = if deferred {
+            let gbuffer_views: Vec<vk::ImageView> =
+                targets.gbuffer.iter().map(|image| graph.image_view(*image)).collect();
+            Some(Deferred::new(
+                &mut physical,
+                &descs,
+                &gbuffer_views,
+                graph.image_view(targets.depth),
+                graph.image_view(targets.hdr),
+                settings.gbuffer_view,
+            ))
+        } else {
+            None
+        };
+
+        let frames = Frames::new(2, &mut physical, &mut descs, &shadow_map);
+        let async_compute = AsyncCompute::new(&physical, 2);
+        let uploads = UploadManager::new(&physical);
+
+        let pipeline = Rc::new(PipelineStruct::pbr(&physical, &render_pass, &descs, settings.vertex_pulling));
+
+        let assets = AssetLoader::new(ASSET_LOADER_THREADS);
+        let mut scene = Scene::new();
+        scene.placeholder_mesh = Some(Mesh::placeholder(&mut physical, &mut scene.mesh_pool));
+
         let axisangle = Vector3::y() * std::f32::consts::FRAC_PI_2;
         let mesh_matrix: na::Isometry3<f32> = na::Isometry3::new(Vector3::x(), axisangle);
-        scene.add_render_object_with_mesh_material(
-            mesh,
+        scene.materials.insert(
+            scene::DEFAULT_MATERIAL.to_string(),
+            scene::Material {
+                pipeline,
+                params: scene::MaterialParams::default(),
+                descriptor_set: None,
+                uniform_buffer: None,
+            },
+        );
+        scene.add_render_object("monkey", scene::DEFAULT_MATERIAL, mesh_matrix);
+        assets.load_mesh(
+            &mut scene,
             "monkey",
-            scene::Material { pipeline },
-            "default",
-            mesh_matrix,
+            std::path::Path::new(
+                "D:/rustprogramming/vulkan-guide/vkguide-erupt/src/assets/monkey_smooth.obj",
+            ),
         );
 
         let triangle_data = vec![
@@ -72,180 +282,681 @@ impl VulkanApp {
                 pos: [0.5, -0.5, -0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [0.5, -0.5, 0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [-0.5, -0.5, 0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [-0.5, -0.5, -0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [0.5, 0.5, -0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [0.5, 0.5, 0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [-0.5, 0.5, 0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
             Vertex {
                 pos: [-0.5, 0.5, -0.5],
                 color: [0.0, 1.0, 0.0],
                 normal: [0.0, 0.0, 0.0],
+                uv: [0.0, 0.0],
+                tangent: [0.0, 0.0, 0.0, 0.0],
             },
         ];
-        let data: &[u8] = bytemuck::cast_slice(&triangle_data);
-        let size = size_of_val(data);
-
-        let buffer_info = vk::BufferCreateInfoBuilder::new()
-            .size(size as u64)
-            .usage(vk::BufferUsageFlags::VERTEX_BUFFER);
-
-        let mut block = unsafe {
-            physical.allocator.alloc(
-                EruptMemoryDevice::wrap(&physical.device),
-                Request {
-                    size: size as u64,
-                    align_mask: 1,
-                    usage: UsageFlags::HOST_ACCESS,
-                    memory_types: !0,
-                },
-            )
-        }
-        .unwrap();
-
-        unsafe {
-            block
-                .write_bytes(EruptMemoryDevice::wrap(&physical.device), 0, data)
-                .unwrap();
-        }
-
-        let buffer = unsafe { physical.device.create_buffer(&buffer_info, None, None) }.unwrap();
-
-        unsafe {
-            physical
-                .device
-                .bind_buffer_memory(buffer, *block.memory(), 0)
-                .unwrap();
-        }
-
-        let allocated_buff = mesh::AllocatedBuffer {
-            buffer,
-            allocation: Some(block),
-        };
-
-        let triangle = mesh::Mesh {
-            verticies: triangle_data,
-            vertex_buffer: (allocated_buff),
-        };
+        let triangle = Mesh::from_vertices(triangle_data, &mut physical, &mut scene.mesh_pool);
 
+        //the cube has no normals, so it stays unlit
         let cube_matrix: na::Isometry3<f32> =
             na::Isometry3::new(Vector3::new(5.0, 0.0, 0.0), na::zero());
-        scene.add_render_object_with_mesh(triangle, "cube", "default", cube_matrix);
+        scene.add_render_object_with_mesh_material(
+            triangle,
+            "cube",
+            scene::Material {
+                pipeline: Rc::new(PipelineStruct::new(&physical, &render_pass, &descs, settings.vertex_pulling)),
+                params: scene::MaterialParams::default(),
+                descriptor_set: None,
+                uniform_buffer: None,
+            },
+            "unlit",
+            cube_matrix,
+        );
 
-        let cube = Mesh::new(
+        let test: na::Isometry3<f32> =
+            na::Isometry3::new(Vector3::new(10.0, -3.0, 3.0), na::zero());
+        scene.materials.insert(
+            "phong".to_string(),
+            scene::Material {
+                pipeline: Rc::new(PipelineStruct::blinn_phong(
+                    &physical,
+                    &render_pass,
+                    &descs,
+                    settings.vertex_pulling,
+                )),
+                params: scene::MaterialParams::default(),
+                descriptor_set: None,
+                uniform_buffer: None,
+            },
+        );
+        scene.add_render_object("teapot", "phong", test);
+        assets.load_mesh(
+            &mut scene,
+            "teapot",
             std::path::Path::new(
                 "D:/rustprogramming/vulkan-guide/vkguide-erupt/src/assets/teapot.obj",
             ),
+        );
+
+        let default_pipeline = Rc::clone(&scene.materials.get(scene::DEFAULT_MATERIAL).unwrap().pipeline);
+        obj_loader::load_obj_scene(
+            std::path::Path::new(
+                "D:/rustprogramming/vulkan-guide/vkguide-erupt/src/assets/monkey_flat.obj",
+            ),
             &mut physical,
+            &mut scene,
+            &assets,
+            &default_pipeline,
+            na::Isometry3::new(Vector3::new(-5.0, 0.0, 0.0), na::zero()),
         );
-        let test: na::Isometry3<f32> =
-            na::Isometry3::new(Vector3::new(10.0, -3.0, 3.0), na::zero());
-        scene.add_render_object_with_mesh(cube, "teapot", "default", test);
+
+        scene.ambient_color = [0.05, 0.05, 0.08];
+        scene.clear_color = [0.02, 0.02, 0.03, 1.0];
+        scene.add_light(Light::directional(
+            Vector3::new(-0.3, -1.0, -0.5),
+            [1.0, 0.95, 0.85],
+            1.0,
+        ));
+        scene.add_light(Light::point(
+            na::Point3::new(2.5, 2.0, 2.0),
+            [0.3, 0.5, 1.0],
+            8.0,
+            10.0,
+        ));
+        scene.add_light(Light::spot(
+            na::Point3::new(10.0, 4.0, 3.0),
+            -Vector3::y(),
+            f32::to_radians(15.0),
+            f32::to_radians(25.0),
+            [1.0, 0.6, 0.3],
+            20.0,
+            12.0,
+        ));
+
+        //extra models can be passed on the command line, as well as an environment for the skybox and image based
+        //lighting: an equirectangular .hdr image or a directory with the six cube faces
+        let mut environment = None;
+        for arg in std::env::args().skip(1) {
+            //options are handled by whoever made the RenderSettings
+            if arg.starts_with("--") {
+                continue;
+            }
+            let path = std::path::Path::new(&arg);
+            if path.is_dir() {
+                let cubemap = Texture::load_cubemap_faces(path, &mut physical);
+                environment = Some(EnvironmentLighting::new(&mut physical, &cubemap, path));
+                scene.skybox = Some(Skybox::new(&mut physical, &render_pass, &descs, cubemap));
+                continue;
+            }
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("hdr") => {
+                    let cubemap = Texture::load_equirectangular(path, ENVIRONMENT_FACE_SIZE, &mut physical);
+                    environment = Some(EnvironmentLighting::new(&mut physical, &cubemap, path));
+                    scene.skybox = Some(Skybox::new(&mut physical, &render_pass, &descs, cubemap));
+                }
+                Some("gltf") | Some("glb") => {
+                    gltf_loader::load_gltf_scene(
+                        path,
+                        &mut physical,
+                        &mut scene,
+                        &default_pipeline,
+                        na::Matrix4::identity(),
+                    );
+                }
+                Some("obj") => {
+                    obj_loader::load_obj_scene(
+                        path,
+                        &mut physical,
+                        &mut scene,
+                        &assets,
+                        &default_pipeline,
+                        na::Matrix4::identity(),
+                    );
+                }
+                _ => println!("unknown model format {:?}", path),
+            }
+        }
+
+        //without an environment map the ambient color lights the scene from every direction
+        let environment = environment
+            .unwrap_or_else(|| EnvironmentLighting::uniform(&mut physical, scene.ambient_color));
+        frames.bind_environment(&physical, &environment);
+
+        let material_resources = MaterialResources::new(&mut physical);
+        scene.create_material_descriptors(&mut physical, &descs, &material_resources);
+
+        let mut deletion = DeletionQueue::new();
+        let gpu_scene = if settings.gpu_driven && deferred.is_some() {
+            println!("the gpu driven path only works with forward shading, ignoring it");
+            None
+        } else if settings.gpu_driven && !physical.indirect_draws {
+            println!("the device doesn't support the indirect draws the gpu driven path needs, ignoring it");
+            None
+        } else if settings.gpu_driven {
+            let mut gpu_scene = GpuScene::new(&physical, &render_pass, &descs, Rc::clone(&default_pipeline), 2);
+            gpu_scene.rebuild(&mut physical, &scene, &mut deletion, 0);
+            Some(gpu_scene)
+        } else {
+            None
+        };
 
         VulkanApp {
+            assets,
+            render_queue: RenderQueue::new(),
+            frame_stats: BindStats::default(),
             scene,
+            material_resources,
+            environment,
+            shadow_map,
+            deferred,
+            gpu_scene,
+            deletion,
+            framenumber: 0,
+            post_chain,
             descs,
+            async_compute,
+            uploads,
             frames,
             render_pass,
+            graph,
+            frame_passes,
             swapchain,
             physical,
         }
     }
 
-    fn draw_objects(&mut self, framenumber: i64, eye: na::Point3<f32>) {
-        //compute push constant
+    //Declares the frame: shadow cascades, the scene into the hdr target, then the post chain ending in the swapchain image.
+    //The deferred path fills the G-buffer and lights it into the hdr target before the scene pass.
+    fn build_render_graph(
+        physical: &mut Physical,
+        swapchain: &Swapchain,
+        shadow_map: &ShadowMap,
+        samples: vk::SampleCountFlagBits,
+        deferred: bool,
+        post_effects: &[PostEffect],
+    ) -> (CompiledGraph, FramePasses, FrameTargets) {
+        let extent = physical.surface_caps.current_extent;
+        let mut graph = RenderGraph::new();
+
+        let shadow_resolution = shadow_map.settings.resolution;
+        let shadow = graph.import_image(
+            "shadow map",
+            ImageDesc {
+                layers: shadow_map.settings.cascade_count as u32,
+                ..ImageDesc::new(
+                    DEPTH_FORMAT,
+                    vk::Extent2D {
+                        width: shadow_resolution,
+                        height: shadow_resolution,
+                    },
+                )
+            },
+            vec![shadow_map.image.image],
+            None,
+        );
+        let depth = graph.create_image(
+            "depth",
+            ImageDesc {
+                samples,
+                ..ImageDesc::new(DEPTH_FORMAT, extent)
+            },
+        );
+        let msaa_color = if samples != vk::SampleCountFlagBits::_1 {
+            Some(graph.create_image(
+                "msaa color",
+                ImageDesc {
+                    samples,
+                    ..ImageDesc::new(HDR_FORMAT, extent)
+                },
+            ))
+        } else {
+            None
+        };
+        let hdr = graph.create_image("hdr", ImageDesc::new(HDR_FORMAT, extent));
+        let gbuffer: Vec<ResourceId> = if deferred {
+            GBUFFER_FORMATS
+                .iter()
+                .zip(GBUFFER_NAMES.iter())
+                .map(|(format, name)| graph.create_image(name, ImageDesc::new(*format, extent)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let post_intermediates: Vec<_> = (0..postprocess::intermediate_count(post_effects))
+            .map(|i| graph.create_image(&format!("post intermediate {}", i), ImageDesc::new(HDR_FORMAT, extent)))
+            .collect();
+        //the acquire semaphore is waited on at color attachment output
+        let swapchain_image = graph.import_image(
+            "swapchain",
+            ImageDesc::new(physical.format.format, extent),
+            swapchain.images.clone(),
+            Some(External {
+                wait_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                final_access: Access::Present,
+            }),
+        );
+
+        let shadow_pass = graph.add_pass("shadow", &[], &[(shadow, Access::DepthAttachment)]);
+        let mut scene_reads = vec![(shadow, Access::SampledDepthFragment)];
+        let (gbuffer_pass, lighting_pass) = if deferred {
+            let mut gbuffer_writes: Vec<_> = gbuffer
+                .iter()
+                .map(|image| (*image, Access::ColorAttachment))
+                .collect();
+            gbuffer_writes.push((depth, Access::DepthAttachment));
+            let gbuffer_pass = graph.add_pass("gbuffer", &[], &gbuffer_writes);
+
+            let mut lighting_reads: Vec<_> = gbuffer
+                .iter()
+                .map(|image| (*image, Access::SampledFragment))
+                .collect();
+            lighting_reads.push((depth, Access::SampledDepthFragment));
+            lighting_reads.push((shadow, Access::SampledDepthFragment));
+            let lighting_pass = graph.add_pass("lighting", &lighting_reads, &[(hdr, Access::ColorAttachment)]);
+
+            //the forward pass draws on top of the lit image, depth tested against the G-buffer's depth
+            scene_reads.push((depth, Access::DepthAttachment));
+            scene_reads.push((hdr, Access::ColorAttachment));
+            (Some(gbuffer_pass), Some(lighting_pass))
+        } else {
+            (None, None)
+        };
+        let mut scene_writes = vec![(depth, Access::DepthAttachment), (hdr, Access::ColorAttachment)];
+        if let Some(color) = msaa_color {
+            scene_writes.push((color, Access::ColorAttachment));
+        }
+        let scene_pass = graph.add_pass("scene", &scene_reads, &scene_writes);
+        let post_count = postprocess::pass_count(post_effects);
+        let post_passes = (0..post_count)
+            .map(|i| {
+                let input = if i == 0 { hdr } else { post_intermediates[(i - 1) % 2] };
+                let output = if i + 1 == post_count {
+                    swapchain_image
+                } else {
+                    post_intermediates[i % 2]
+                };
+                graph.add_pass(
+                    &format!("post {}", i),
+                    &[(input, Access::SampledFragment)],
+                    &[(output, Access::ColorAttachment)],
+                )
+            })
+            .collect();
+
+        (
+            graph.compile(physical),
+            FramePasses {
+                shadow: shadow_pass,
+                gbuffer: gbuffer_pass,
+                lighting: lighting_pass,
+                scene: scene_pass,
+                post: post_passes,
+            },
+            FrameTargets {
+                depth,
+                msaa_color,
+                hdr,
+                gbuffer,
+                post_intermediates,
+            },
+        )
+    }
+
+    //View and projection of the camera at `eye`
+    fn camera(&self, eye: &na::Point3<f32>) -> (na::Isometry3<f32>, na::Matrix4<f32>) {
         let target = na::Point3::<f32>::new(1.0, 0.0, 0.0);
-        let view = na::Isometry3::<f32>::look_at_rh(&eye, &target, &Vector3::y());
+        let view = na::Isometry3::<f32>::look_at_rh(eye, &target, &Vector3::y());
         let camera_angle =
             na::Isometry3::<f32>::new(Vector3::zeros(), Vector3::y() * f32::to_radians(0.0));
-        let projection = na::Perspective3::<f32>::new(
-            self.physical.surface_caps.current_extent.width as f32
-                / self.physical.surface_caps.current_extent.height as f32,
-            3.14 / 1.5,
-            0.1,
-            200.0,
-        )
-        .into_inner();
+        let projection =
+            na::Perspective3::<f32>::new(self.aspect_ratio(), FOV_Y, Z_NEAR, Z_FAR).into_inner();
+        (camera_angle * view, projection)
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.physical.surface_caps.current_extent.width as f32
+            / self.physical.surface_caps.current_extent.height as f32
+    }
 
+    //Writes this frame's uniforms and sorts the objects, returning the shadow cascades for the shadow pass
+    fn prepare_frame(&mut self, framenumber: i64, eye: na::Point3<f32>) -> Cascades {
+        self.render_queue.build(&self.scene, &eye);
+        self.frame_stats = BindStats::default();
 
-        let cam_data  = GPUCameraData {
+        let (view, projection) = self.camera(&eye);
+        let cam_data = GPUCameraData {
             view: view.to_homogeneous(),
             projection: projection,
             viewproj: projection * view.to_homogeneous(),
         };
 
-        
-    
-        let mut last_material: Option<&Material> = None;
-        for (a, b, c) in self.scene.objects.iter() {
-            let material = self.scene.materials.get(b);
-            if material != last_material {
+        let shadow_light = self.scene.shadow_light();
+        let light_direction = shadow_light
+            .map(|light| self.scene.lights[light].direction)
+            .unwrap_or_else(|| -Vector3::y());
+        let cascades = self.shadow_map.cascades(
+            &light_direction,
+            &view,
+            FOV_Y,
+            self.aspect_ratio(),
+            Z_NEAR,
+            Z_FAR,
+        );
+        let mut scene_data = self.scene.gpu_scene_data(&eye);
+        cascades.write_to(&mut scene_data, &self.shadow_map.settings, shadow_light);
+        self.frames.frames[framenumber as usize % 2].write_uniforms(&cam_data, &scene_data);
+
+        cascades
+    }
+
+    //Records `part` of the render queue into the current render pass, returning what it bound and drew.
+    //`pipeline_override` replaces the materials' own pipelines, it needs the same layout.
+    fn draw_objects(
+        &self,
+        framenumber: i64,
+        eye: na::Point3<f32>,
+        part: QueuePart,
+        pipeline_override: Option<&PipelineStruct>,
+    ) -> BindStats {
+        let (view, projection) = self.camera(&eye);
+
+        let (opaque, transparent): (&[RenderItem], &[RenderItem]) = match part {
+            QueuePart::Opaque => (&self.render_queue.opaque, &[]),
+            QueuePart::Transparent => (&[], &self.render_queue.transparent),
+        };
+
+        let command_buffer = self.get_frame(framenumber).command_buffer;
+        let global_descriptor = self.get_frame(framenumber).global_descriptor;
+        let global_offsets = self.get_frame(framenumber).global_offsets;
+        let mut stats = BindStats::default();
+        let mut last_pipeline: Option<vk::Pipeline> = None;
+        let mut last_descriptor: Option<vk::DescriptorSet> = None;
+        let mut last_vertex_buffer: Option<vk::Buffer> = None;
+        let mut last_index_buffer: Option<vk::Buffer> = None;
+
+        for item in opaque.iter().chain(transparent.iter()) {
+            let (a, b, c) = &self.scene.objects[item.object];
+            let material = self.scene.materials.get(b).unwrap();
+            if self.gpu_scene.as_ref().is_some_and(|gpu_scene| gpu_scene.draws(material)) {
+                continue;
+            }
+            let mesh = self.scene.mesh(a).unwrap();
+
+            let (pipeline, pipeline_layout, vertex_pulling) = match pipeline_override {
+                Some(pipeline) => (
+                    pipeline.pipelines[OPAQUE_PIPELINE],
+                    pipeline.pipeline_layout,
+                    pipeline.vertex_pulling,
+                ),
+                None => (
+                    material.pipeline(),
+                    material.pipeline.pipeline_layout,
+                    material.pipeline.vertex_pulling,
+                ),
+            };
+            if last_pipeline != Some(pipeline) {
                 unsafe {
                     self.physical.device.cmd_bind_pipeline(
-                        self.get_frame(framenumber).command_buffer,
+                        command_buffer,
                         vk::PipelineBindPoint::GRAPHICS,
-                        material.unwrap().pipeline.pipelines[0],
+                        pipeline,
+                    );
+                    //all pipelines share the global set layout, so it stays bound across pipeline changes
+                    if last_pipeline.is_none() {
+                        self.physical.device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline_layout,
+                            0,
+                            &[global_descriptor],
+                            &global_offsets,
+                        );
+                        stats.descriptor_binds += 1;
+                    }
+                }
+                last_pipeline = Some(pipeline);
+                stats.pipeline_binds += 1;
+            }
+
+            if let Some(descriptor_set) = self.scene.material_descriptor(material) {
+                if last_descriptor != Some(descriptor_set) {
+                    unsafe {
+                        self.physical.device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline_layout,
+                            1,
+                            &[descriptor_set],
+                            &[],
+                        );
+                    }
+                    last_descriptor = Some(descriptor_set);
+                    stats.descriptor_binds += 1;
+                }
+            }
+
+            if vertex_pulling {
+                let constants = PulledPushConstants {
+                    model: *c,
+                    vertices: self.scene.mesh_pool.vertex_address(mesh.vertex_range.chunk),
+                };
+                unsafe {
+                    self.physical.device.cmd_push_constants(
+                        command_buffer,
+                        pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        size_of_val(&constants) as u32,
+                        &constants as *const PulledPushConstants as *const c_void,
+                    );
+                }
+            } else {
+                let constants = MeshPushConstants {
+                    render_matrix: projection * view.to_homogeneous() * c,
+                    model: *c,
+                };
+                unsafe {
+                    self.physical.device.cmd_push_constants(
+                        command_buffer,
+                        pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        size_of_val(&constants) as u32,
+                        &constants as *const MeshPushConstants as *const c_void,
                     );
                 }
-                last_material = material;
             }
-            let mesh = self.scene.meshes.get(a).unwrap();
-            let offset: u64 = 0;
-            let model_view_projection: na::Matrix4<f32> =
-                projection * (camera_angle * view * c).to_homogeneous();
 
+            //meshes share the pool's buffers, so these only change when a mesh is in another chunk.
+            //Pulling pipelines have no vertex input, the index buffer is still used.
+            let vertex_buffer = self.scene.mesh_pool.vertex_buffer(mesh.vertex_range.chunk);
+            if !vertex_pulling && last_vertex_buffer != Some(vertex_buffer) {
+                unsafe {
+                    self.physical
+                        .device
+                        .cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+                }
+                last_vertex_buffer = Some(vertex_buffer);
+                stats.vertex_buffer_binds += 1;
+            }
+            let index_buffer = self.scene.mesh_pool.index_buffer(mesh.index_range.chunk);
+            if last_index_buffer != Some(index_buffer) {
+                unsafe {
+                    self.physical.device.cmd_bind_index_buffer(
+                        command_buffer,
+                        index_buffer,
+                        0,
+                        vk::IndexType::UINT32,
+                    );
+                }
+                last_index_buffer = Some(index_buffer);
+                stats.index_buffer_binds += 1;
+            }
             unsafe {
-                self.physical.device.cmd_push_constants(
-                    self.get_frame(framenumber).command_buffer,
-                    material.unwrap().pipeline.pipeline_layout,
-                    vk::ShaderStageFlags::VERTEX,
-                    0,
-                    size_of_val(model_view_projection.as_slice()) as u32,
-                    model_view_projection.as_slice().as_ptr() as *mut c_void,
-                );
-                self.physical.device.cmd_bind_vertex_buffers(
-                    self.get_frame(framenumber).command_buffer,
-                    0,
-                    &[mesh.vertex_buffer.buffer],
-                    &[offset],
-                );
-                self.physical.device.cmd_draw(
-                    self.get_frame(framenumber).command_buffer,
-                    mesh.verticies.len() as u32,
+                self.physical.device.cmd_draw_indexed(
+                    command_buffer,
+                    mesh.index_range.count,
                     1,
+                    mesh.index_range.offset,
+                    mesh.vertex_range.offset as i32,
                     0,
-                    0,
                 );
             }
+            stats.draws += 1;
+        }
+
+        stats
+    }
+
+    //bind and draw counts recorded for the last frame
+    pub fn frame_stats(&self) -> BindStats {
+        self.frame_stats
+    }
+
+    //GPU memory in use by heap, usage and category, printing it gives a report
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.physical.memory_stats()
+    }
+
+    //Records compute work for `framenumber` on the compute queue, before that frame is drawn. The frame's graphics
+    //work waits for it at `wait_stage`, so its results can be used from there on.
+    pub fn submit_compute<F: FnOnce(&Physical, vk::CommandBuffer)>(
+        &mut self,
+        framenumber: i64,
+        wait_stage: vk::PipelineStageFlags,
+        record: F,
+    ) {
+        //the command buffer is reused once the frame that waited on it is done
+        unsafe {
+            self.physical
+                .device
+                .wait_for_fences(&[self.get_frame(framenumber).render_fence.handle], false, u64::MAX)
+                .unwrap();
+        }
+        let physical = &self.physical;
+        self.async_compute
+            .submit(physical, framenumber as usize % 2, wait_stage, |cmd| record(physical, cmd));
+    }
+
+    //Starts uploading a mesh in the background, it shows up in the scene's meshes under `name` a few frames later.
+    //Render objects using it draw the placeholder until then.
+    pub fn upload_mesh(&mut self, name: &str, vertices: Vec<Vertex>, indices: Vec<u32>) {
+        self.scene.mesh_states.insert(name.to_string(), LoadState::Pending);
+        self.uploads
+            .upload_mesh(&mut self.physical, &mut self.scene.mesh_pool, name, vertices, indices);
+    }
+
+    //Starts uploading a 2D texture from its mip levels, it shows up in the scene's textures under `name` when done
+    pub fn upload_texture(&mut self, name: &str, levels: &[&[u8]], format: vk::Format, width: u32, height: u32) {
+        self.scene.texture_states.insert(name.to_string(), LoadState::Pending);
+        self.uploads
+            .upload_texture(&mut self.physical, name, levels, format, width, height);
+    }
+
+    //Objects using a removed mesh or material stop drawing, the GPU resources go once no frame in flight uses them
+    pub fn remove_mesh(&mut self, name: &str) {
+        self.scene.remove_mesh(name, &mut self.deletion, self.framenumber);
+        self.rebuild_gpu_scene();
+    }
+
+    pub fn remove_material(&mut self, name: &str) {
+        self.scene
+            .remove_material(name, &self.descs, &mut self.deletion, self.framenumber);
+        self.rebuild_gpu_scene();
+    }
+
+    //None for meshes that were never loaded in the background
+    pub fn mesh_state(&self, name: &str) -> Option<&LoadState> {
+        self.scene.mesh_states.get(name)
+    }
+
+    pub fn texture_state(&self, name: &str) -> Option<&LoadState> {
+        self.scene.texture_states.get(name)
+    }
+
+    //Points the materials using newly arrived textures at them instead of the defaults
+    fn update_material_textures(&mut self, framenumber: i64, textures: &[String]) {
+        //the other frame may still be drawing with the descriptor sets
+        unsafe {
+            self.physical
+                .device
+                .wait_for_fences(&[self.get_frame(framenumber + 1).render_fence.handle], false, u64::MAX)
+                .unwrap();
+        }
+        for material in self.scene.materials.values() {
+            let descriptor_set = match material.descriptor_set {
+                Some(descriptor_set) => descriptor_set,
+                None => continue,
+            };
+            if textures.iter().any(|texture| material.params.uses_texture(texture)) {
+                self.material_resources.write_textures(
+                    &self.physical,
+                    descriptor_set,
+                    &self.scene.textures,
+                    &material.params,
+                );
+            }
+        }
+    }
+
+    //Merges the meshes again for the gpu driven path, so objects stop drawing the placeholder
+    fn rebuild_gpu_scene(&mut self) {
+        if let Some(gpu_scene) = self.gpu_scene.as_mut() {
+            gpu_scene.rebuild(&mut self.physical, &self.scene, &mut self.deletion, self.framenumber);
+        }
+    }
+
+    //Picks what the deferred path shows from the next frame on, does nothing with the forward path
+    pub fn set_gbuffer_view(&mut self, view: GBufferView) {
+        if let Some(deferred) = self.deferred.as_mut() {
+            deferred.view = view;
         }
     }
 
+    pub fn gbuffer_view(&self) -> Option<GBufferView> {
+        self.deferred.as_ref().map(|deferred| deferred.view)
+    }
+
     //Present semaphore - 0
     //render - 1
 
@@ -253,18 +964,23 @@ impl VulkanApp {
         unsafe {
             self.physical
                 .device
-                .wait_for_fences(&[self.get_frame(framenumber).render_fence], false, u64::MAX)
+                .wait_for_fences(&[self.get_frame(framenumber).render_fence.handle], false, u64::MAX)
                 .unwrap();
             self.physical
                 .device
-                .reset_fences(&[self.get_frame(framenumber).render_fence])
+                .reset_fences(&[self.get_frame(framenumber).render_fence.handle])
         }
         .unwrap();
+        //the fence of this frame's slot means everything up to two frames ago is done
+        self.framenumber = framenumber;
+        self.deletion
+            .flush(framenumber - 2, &mut self.physical, &mut self.scene.mesh_pool);
+        self.frames.frames[framenumber as usize % 2].uniforms.reset();
         let swapchain_image_index = unsafe {
             self.physical.device.acquire_next_image_khr(
                 self.swapchain.swapchain,
                 u64::MAX,
-                Some(self.get_frame(framenumber).present_semaphore),
+                Some(self.get_frame(framenumber).present_semaphore.handle),
                 Some(vk::Fence::null()),
                 None,
             )
@@ -288,46 +1004,53 @@ impl VulkanApp {
                 .begin_command_buffer(self.get_frame(framenumber).command_buffer, &cmd_begin_info)
                 .unwrap();
         }
-        //make a clear-color from frame number. This will flash with a 120*pi frame period.
-        let flashdiv120 = framenumber as f32 / 120 as f32;
-        let flash: f32 = flashdiv120.sin().abs();
-        let clear_value = vk::ClearValue {
-            color: {
-                vk::ClearColorValue {
-                    float32: [0.0, 0.0, flash, 1.0],
-                }
-            },
-        };
-        let depth_stencil = vk::ClearDepthStencilValueBuilder::new().depth(1.0);
-        let depth_clear = vk::ClearValue {
-            depth_stencil: *depth_stencil,
-        };
-        let clear_values = vec![clear_value, depth_clear];
+        let command_buffer = self.get_frame(framenumber).command_buffer;
+        self.assets
+            .update(&mut self.physical, &mut self.scene, &mut self.uploads);
+        let mut textures = Vec::new();
+        let mut meshes_arrived = false;
+        for uploaded in self.uploads.poll(&mut self.physical, command_buffer) {
+            meshes_arrived |= matches!(uploaded, Uploaded::Mesh(..));
+            textures.extend(self.scene.finish_upload(uploaded, &mut self.deletion, framenumber));
+        }
+        if !textures.is_empty() {
+            self.update_material_textures(framenumber, &textures);
+        }
+        if meshes_arrived {
+            self.rebuild_gpu_scene();
+        }
+        let cascades = self.prepare_frame(framenumber, camera_pos);
 
-        //start the main renderpass
-        let rp_info = vk::RenderPassBeginInfoBuilder::new()
-            .render_pass(self.render_pass.render_pass)
-            .framebuffer(self.render_pass.framebuffers[swapchain_image_index as usize])
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: self.physical.surface_caps.current_extent,
-            })
-            .clear_values(&clear_values);
-        unsafe {
-            self.physical.device.cmd_begin_render_pass(
-                self.get_frame(framenumber).command_buffer,
-                &rp_info,
-                vk::SubpassContents::INLINE,
-            )
-        };
+        if let Some(gpu_scene) = &self.gpu_scene {
+            let (view, projection) = self.camera(&camera_pos);
+            gpu_scene.cull(
+                &self.physical,
+                command_buffer,
+                framenumber as usize % 2,
+                &(projection * view.to_homogeneous()),
+            );
+        }
 
-        self.draw_objects(framenumber, camera_pos);
+        let image_index = swapchain_image_index as usize;
+        for step in 0..self.graph.steps() {
+            let pass = self.graph.begin_step(&self.physical, command_buffer, step, image_index);
+            if pass == self.frame_passes.shadow {
+                self.shadow_map
+                    .record(&self.physical, command_buffer, &self.scene, &cascades);
+            } else if Some(pass) == self.frame_passes.gbuffer {
+                self.record_gbuffer_pass(framenumber, camera_pos);
+            } else if Some(pass) == self.frame_passes.lighting {
+                self.record_lighting_pass(framenumber, camera_pos);
+            } else if pass == self.frame_passes.scene {
+                self.record_scene_pass(framenumber, camera_pos);
+            } else if let Some(index) = self.frame_passes.post.iter().position(|post| *post == pass) {
+                self.post_chain
+                    .record_pass(&self.physical, command_buffer, index, swapchain_image_index);
+            }
+        }
+        self.graph.end_frame(&self.physical, command_buffer, image_index);
 
         unsafe {
-            //end renderpass
-            self.physical
-                .device
-                .cmd_end_render_pass(self.get_frame(framenumber).command_buffer);
             self.physical
                 .device
                 .end_command_buffer(self.get_frame(framenumber).command_buffer)
@@ -336,22 +1059,28 @@ impl VulkanApp {
 
         let swapchains = vec![self.swapchain.swapchain];
         let swapchain_index_indices = vec![swapchain_image_index];
-        let render_semaphore = [self.get_frame(framenumber).render_semaphore];
-        let present_semaphore = [self.get_frame(framenumber).present_semaphore];
+        let render_semaphore = [self.get_frame(framenumber).render_semaphore.handle];
+        let mut wait_semaphores = vec![self.get_frame(framenumber).present_semaphore.handle];
+        let mut wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        if let Some((semaphore, stage)) = self.async_compute.take_wait(framenumber as usize % 2) {
+            wait_semaphores.push(semaphore);
+            wait_stages.push(stage);
+        }
         let command_buffer = [self.get_frame(framenumber).command_buffer];
 
         //we can now submit the render pass to the GPU
         let submit_info = vk::SubmitInfoBuilder::new()
-            .wait_semaphores(&present_semaphore)
+            .wait_semaphores(&wait_semaphores)
             .signal_semaphores(&render_semaphore)
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+            .wait_dst_stage_mask(&wait_stages)
             .command_buffers(&command_buffer);
         let submit = vec![submit_info];
+        self.get_frame(framenumber).uniforms.flush(&self.physical);
         unsafe {
             self.physical.device.queue_submit(
                 self.physical.graphics_queue,
                 &submit,
-                Some(self.get_frame(framenumber).render_fence),
+                Some(self.get_frame(framenumber).render_fence.handle),
             )
         }
         .unwrap();
@@ -368,6 +1097,110 @@ impl VulkanApp {
         .unwrap();
     }
 
+    //The opaque objects of the deferred path into the G-buffer
+    fn record_gbuffer_pass(&mut self, framenumber: i64, camera_pos: na::Point3<f32>) {
+        let deferred = self.deferred.as_ref().unwrap();
+        let command_buffer = self.get_frame(framenumber).command_buffer;
+        deferred.begin_gbuffer_pass(&self.physical, command_buffer);
+        let stats = self.draw_objects(
+            framenumber,
+            camera_pos,
+            QueuePart::Opaque,
+            Some(&deferred.gbuffer_pipeline),
+        );
+        self.frame_stats += stats;
+        unsafe { self.physical.device.cmd_end_render_pass(command_buffer) };
+    }
+
+    fn record_lighting_pass(&self, framenumber: i64, camera_pos: na::Point3<f32>) {
+        let (view, projection) = self.camera(&camera_pos);
+        let inverse_viewproj = (projection * view.to_homogeneous())
+            .try_inverse()
+            .unwrap_or_else(na::Matrix4::identity);
+        let frame = self.get_frame(framenumber);
+        self.deferred.as_ref().unwrap().record_lighting(
+            &self.physical,
+            frame.command_buffer,
+            frame.global_descriptor,
+            &frame.global_offsets,
+            inverse_viewproj,
+            self.scene.clear_color,
+            Z_FAR,
+        );
+    }
+
+    //The main render pass with every scene object. With the deferred path it only adds the transparent
+    //objects and the skybox to the lit image.
+    fn record_scene_pass(&mut self, framenumber: i64, camera_pos: na::Point3<f32>) {
+        let clear_value = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: self.scene.clear_color,
+            },
+        };
+        let depth_stencil = vk::ClearDepthStencilValueBuilder::new().depth(1.0);
+        let depth_clear = vk::ClearValue {
+            depth_stencil: *depth_stencil,
+        };
+        let clear_values = vec![clear_value, depth_clear];
+
+        //start the main renderpass
+        let rp_info = vk::RenderPassBeginInfoBuilder::new()
+            .render_pass(self.render_pass.render_pass.handle)
+            .framebuffer(self.render_pass.framebuffer.handle)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.physical.surface_caps.current_extent,
+            })
+            .clear_values(&clear_values);
+        unsafe {
+            self.physical.device.cmd_begin_render_pass(
+                self.get_frame(framenumber).command_buffer,
+                &rp_info,
+                vk::SubpassContents::INLINE,
+            )
+        };
+
+        //the gpu driven path already culled the opaque pbr objects and the deferred path lit the opaque objects,
+        //the transparent ones still need sorting. draw_objects skips what the gpu driven path draws.
+        if let Some(gpu_scene) = &self.gpu_scene {
+            let frame = self.get_frame(framenumber);
+            let stats = gpu_scene.draw(
+                &self.physical,
+                frame.command_buffer,
+                framenumber as usize % 2,
+                frame.global_descriptor,
+                &frame.global_offsets,
+            );
+            self.frame_stats += stats;
+        }
+        if self.deferred.is_none() {
+            let stats = self.draw_objects(framenumber, camera_pos, QueuePart::Opaque, None);
+            self.frame_stats += stats;
+        }
+
+        //behind the opaque objects, so it only shades what they didn't cover. Transparent objects don't write depth,
+        //so they have to come after it or the skybox would cover them.
+        if let Some(skybox) = &self.scene.skybox {
+            let frame = self.get_frame(framenumber);
+            skybox.record(
+                &self.physical,
+                frame.command_buffer,
+                frame.global_descriptor,
+                &frame.global_offsets,
+            );
+        }
+
+        let stats = self.draw_objects(framenumber, camera_pos, QueuePart::Transparent, None);
+        self.frame_stats += stats;
+
+        unsafe {
+            //end renderpass
+            self.physical
+                .device
+                .cmd_end_render_pass(self.get_frame(framenumber).command_buffer);
+        }
+    }
+
     fn get_frame(&self, framenumber: i64) -> &Frame {
         let frame_count: usize = framenumber as usize % 2 as usize;
         return &self.frames.frames[frame_count];
@@ -379,17 +1212,14 @@ impl Drop for VulkanApp {
         unsafe {
             self.physical.device.device_wait_idle().unwrap();
 
-            self.scene.cleanup(&mut self.physical);
-
-            self.descs.cleanup(&mut self.physical);
-
-            self.frames.cleanup(&mut self.physical);
-
-            self.render_pass.cleanup(&mut self.physical);
+            self.assets.cleanup();
 
-            self.swapchain.cleanup(&self.physical);
+            //the upload thread may still be copying into the scene's mesh pool
+            self.uploads.cleanup();
 
-            self.physical.cleanup();
+            //frees descriptor sets and mesh ranges while their pools are still there
+            self.deletion
+                .flush_all(&mut self.physical, &mut self.scene.mesh_pool);
 
             println!("exited");
         }
@@ -0,0 +1,201 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use erupt::vk;
+
+use super::{
+    device::Physical,
+    mesh::cook_obj,
+    mesh_cache::{self, CookedMesh},
+    scene::Scene,
+    upload::UploadManager,
+};
+
+//Where a mesh or texture requested from the AssetLoader is at. Scene keeps one per requested name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadState {
+    //parsing on a worker thread or uploading, the scene draws a placeholder meanwhile
+    Pending,
+    Loaded,
+    Failed(String),
+}
+
+enum AssetRequest {
+    Mesh { name: String, path: PathBuf },
+    Texture { name: String, path: PathBuf, srgb: bool },
+}
+
+//CPU side data made by a worker, uploaded on the render thread
+enum ParsedAsset {
+    Mesh(CookedMesh),
+    Texture {
+        pixels: Vec<u8>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    },
+}
+
+struct Parsed {
+    name: String,
+    //false for a texture
+    mesh: bool,
+    result: Result<ParsedAsset, String>,
+}
+
+//Loads meshes and textures from disk without blocking the render loop. Files are parsed on worker threads,
+//the render thread then hands the data to the UploadManager in update. Requested names are Pending in the
+//Scene until the upload is done, see Scene::finish_upload.
+pub struct AssetLoader {
+    //None once cleanup has run
+    requests: Option<Sender<AssetRequest>>,
+    parsed: Receiver<Parsed>,
+    //set by cleanup, workers check it before starting on a request
+    stop: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl AssetLoader {
+    pub fn new(threads: usize) -> Self {
+        let (requests, request_receiver) = mpsc::channel();
+        let (parsed_sender, parsed) = mpsc::channel();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..threads.max(1))
+            .map(|index| {
+                let requests = Arc::clone(&request_receiver);
+                let parsed = parsed_sender.clone();
+                let stop = Arc::clone(&stop);
+                std::thread::Builder::new()
+                    .name(format!("asset loader {}", index))
+                    .spawn(move || run_worker(&requests, &parsed, &stop))
+                    .unwrap()
+            })
+            .collect();
+
+        AssetLoader {
+            requests: Some(requests),
+            parsed,
+            stop,
+            workers,
+        }
+    }
+
+    //Starts loading all models of an OBJ file as one mesh, going through the mesh cache like load_obj_scene
+    pub fn load_mesh(&self, scene: &mut Scene, name: &str, path: &Path) {
+        scene.mesh_states.insert(name.to_string(), LoadState::Pending);
+        self.request(AssetRequest::Mesh {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+        });
+    }
+
+    //Starts loading an image file as a texture. Color textures (albedo, emissive) should be srgb, data textures
+    //(normals, roughness) linear.
+    pub fn load_texture(&self, scene: &mut Scene, name: &str, path: &Path, srgb: bool) {
+        scene.texture_states.insert(name.to_string(), LoadState::Pending);
+        self.request(AssetRequest::Texture {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            srgb,
+        });
+    }
+
+    fn request(&self, request: AssetRequest) {
+        self.requests
+            .as_ref()
+            .expect("asset loader was cleaned up")
+            .send(request)
+            .expect("asset loader threads exited");
+    }
+
+    //Starts the uploads of everything parsed since the last call, failed files are marked in the scene
+    pub fn update(&mut self, physical: &mut Physical, scene: &mut Scene, uploads: &mut UploadManager) {
+        for parsed in self.parsed.try_iter() {
+            match parsed.result {
+                Ok(ParsedAsset::Mesh(cooked)) => {
+                    uploads.upload_mesh(physical, &mut scene.mesh_pool, &parsed.name, cooked.vertices, cooked.indices)
+                }
+                Ok(ParsedAsset::Texture {
+                    pixels,
+                    width,
+                    height,
+                    format,
+                }) => uploads.upload_texture(physical, &parsed.name, &[&pixels], format, width, height),
+                Err(error) => {
+                    println!("failed to load {}: {}", parsed.name, error);
+                    let states = if parsed.mesh {
+                        &mut scene.mesh_states
+                    } else {
+                        &mut scene.texture_states
+                    };
+                    states.insert(parsed.name, LoadState::Failed(error));
+                }
+            }
+        }
+    }
+
+    //Waits for the workers to finish the file they are on. Each worker exits at the next request it takes instead of
+    //parsing it, so the queued requests are dropped unparsed.
+    pub fn cleanup(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.requests = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+fn run_worker(requests: &Mutex<Receiver<AssetRequest>>, parsed: &Sender<Parsed>, stop: &AtomicBool) {
+    loop {
+        //the lock is only held while waiting, so the other workers parse in the meantime
+        let request = match requests.lock().unwrap().recv() {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let result = match request {
+            AssetRequest::Mesh { name, path } => Parsed {
+                name,
+                mesh: true,
+                result: mesh_cache::try_load_or_cook(&path, cook_obj)
+                    .map(|cooked| ParsedAsset::Mesh(cooked.into_merged()))
+                    .map_err(|e| e.to_string()),
+            },
+            AssetRequest::Texture { name, path, srgb } => Parsed {
+                name,
+                mesh: false,
+                result: parse_texture(&path, srgb),
+            },
+        };
+        if parsed.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+fn parse_texture(path: &Path, srgb: bool) -> Result<ParsedAsset, String> {
+    let image = image::open(path).map_err(|e| e.to_string())?.to_rgba8();
+    let (width, height) = image.dimensions();
+    println!("texture {:?}, {}x{}", path, width, height);
+    Ok(ParsedAsset::Texture {
+        pixels: image.into_raw(),
+        width,
+        height,
+        format: if srgb {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        },
+    })
+}
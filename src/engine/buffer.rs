@@ -1,7 +1,7 @@
-use super::{device::Physical, mesh::AllocatedBuffer};
+use super::{device::Physical, memory_stats::MemoryCategory, mesh::AllocatedBuffer};
 
-use erupt::vk::{self, DeviceMemory};
-use gpu_alloc::{MemoryBlock, Request, UsageFlags};
+use erupt::vk;
+use gpu_alloc::{Request, UsageFlags};
 use gpu_alloc_erupt::EruptMemoryDevice;
 
 pub fn create_buffer(
@@ -9,35 +9,63 @@ pub fn create_buffer(
     alloc_size: u64,
     usage: vk::BufferUsageFlags,
     memory_usage: gpu_alloc::UsageFlags,
+    category: MemoryCategory,
 ) -> AllocatedBuffer {
     let buffer_info = vk::BufferCreateInfoBuilder::new()
         .size(alloc_size)
         .usage(usage);
-
-    let mut block = unsafe {
-        physical.allocator.alloc(
-            EruptMemoryDevice::wrap(&physical.device),
-            Request {
-                size: alloc_size as u64,
-                align_mask: 1,
-                usage: memory_usage,
-                memory_types: !0,
-            },
-        )
-    }
-    .unwrap();
+    //the memory has to be allocated with the device address flag for buffer_address to work
+    let memory_usage = if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+        memory_usage | UsageFlags::DEVICE_ADDRESS
+    } else {
+        memory_usage
+    };
 
     let buffer = unsafe { physical.device.create_buffer(&buffer_info, None, None) }.unwrap();
 
+    //blocks may be suballocated from a larger memory object, depending on the allocator config
+    let mem_requirements = unsafe { physical.device.get_buffer_memory_requirements(buffer, None) };
+    let block = physical.device.alloc(
+        Request {
+            size: mem_requirements.size,
+            align_mask: mem_requirements.alignment - 1,
+            usage: memory_usage,
+            memory_types: mem_requirements.memory_type_bits,
+        },
+        category,
+    );
+
     unsafe {
         physical
             .device
-            .bind_buffer_memory(buffer, *block.memory(), 0)
+            .bind_buffer_memory(buffer, *block.memory(), block.offset())
             .unwrap();
     }
 
-    AllocatedBuffer {
-        buffer,
-        allocation: Some(block),
+    AllocatedBuffer::new(&physical.device, buffer, block)
+}
+
+//Host visible buffer filled with `data`, for data written once from the CPU
+pub fn create_buffer_with_data(
+    physical: &mut Physical,
+    usage: vk::BufferUsageFlags,
+    data: &[u8],
+    category: MemoryCategory,
+) -> AllocatedBuffer {
+    let mut buffer = create_buffer(physical, data.len().max(1) as u64, usage, UsageFlags::HOST_ACCESS, category);
+    unsafe {
+        buffer
+            .allocation
+            .as_mut()
+            .unwrap()
+            .write_bytes(EruptMemoryDevice::wrap(&physical.device), 0, data)
+            .unwrap();
     }
+    buffer
+}
+
+//Address of a buffer created with SHADER_DEVICE_ADDRESS usage, for shaders reading it through a buffer reference
+pub fn buffer_address(physical: &Physical, buffer: vk::Buffer) -> vk::DeviceAddress {
+    let address_info = vk::BufferDeviceAddressInfoBuilder::new().buffer(buffer);
+    unsafe { physical.device.get_buffer_device_address(&address_info) }
 }
@@ -0,0 +1,317 @@
+use std::{mem::size_of_val, path::Path};
+
+use erupt::vk;
+
+use super::{
+    descriptors::DescriptorSetLayout,
+    device::Physical,
+    handles::{CommandPool, Semaphore},
+    pipeline::PipelineStruct,
+};
+
+//Kind of resource bound at a binding of a kernel's descriptor set, in binding order
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KernelBinding {
+    StorageBuffer,
+    //read and written in the GENERAL layout
+    StorageImage,
+    UniformBuffer,
+    SampledImage,
+}
+
+impl KernelBinding {
+    fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            KernelBinding::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+            KernelBinding::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            KernelBinding::UniformBuffer => vk::DescriptorType::UNIFORM_BUFFER,
+            KernelBinding::SampledImage => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        }
+    }
+}
+
+//Number of work groups of `group_size` invocations needed to cover `size` items
+pub fn group_count(size: u32, group_size: u32) -> u32 {
+    size.div_ceil(group_size)
+}
+
+//A compute pipeline with a single descriptor set described by its bindings and optional push constants
+pub struct ComputeKernel {
+    pub pipeline: PipelineStruct,
+    pub set_layout: DescriptorSetLayout,
+    bindings: Vec<KernelBinding>,
+    push_constant_size: u32,
+}
+
+impl ComputeKernel {
+    pub fn new(physical: &Physical, spirv: &[u32], bindings: &[KernelBinding], push_constant_size: u32) -> Self {
+        let layout_bindings: Vec<_> = bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, kind)| {
+                vk::DescriptorSetLayoutBindingBuilder::new()
+                    .binding(binding as u32)
+                    .descriptor_count(1)
+                    .descriptor_type(kind.descriptor_type())
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect();
+        let set_layout = DescriptorSetLayout::new(physical, &layout_bindings);
+
+        let pipeline = PipelineStruct::compute(physical, spirv, &[set_layout.layout], push_constant_size);
+
+        ComputeKernel {
+            pipeline,
+            set_layout,
+            bindings: bindings.to_vec(),
+            push_constant_size,
+        }
+    }
+
+    //Loads the kernel from a compiled .spv file instead of a shader built into the binary
+    pub fn from_file(
+        physical: &Physical,
+        path: &Path,
+        bindings: &[KernelBinding],
+        push_constant_size: u32,
+    ) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let spirv = erupt::utils::decode_spv(&bytes)?;
+        Ok(Self::new(physical, &spirv, bindings, push_constant_size))
+    }
+
+    //Descriptor pool sizes for `sets` sets of this kernel
+    pub fn pool_sizes(&self, sets: u32) -> Vec<vk::DescriptorPoolSizeBuilder<'static>> {
+        let mut sizes: Vec<vk::DescriptorPoolSizeBuilder<'static>> = Vec::new();
+        for binding in &self.bindings {
+            let descriptor_type = binding.descriptor_type();
+            match sizes.iter().position(|size| size._type == descriptor_type) {
+                Some(index) => sizes[index].descriptor_count += sets,
+                None => sizes.push(
+                    vk::DescriptorPoolSizeBuilder::new()
+                        ._type(descriptor_type)
+                        .descriptor_count(sets),
+                ),
+            }
+        }
+        sizes
+    }
+
+    pub fn allocate_set(&self, physical: &Physical, pool: vk::DescriptorPool) -> vk::DescriptorSet {
+        let set_layouts = [self.set_layout.layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0]
+    }
+
+    //Points a StorageBuffer or UniformBuffer binding at `range` bytes of `buffer` starting at `offset`
+    pub fn write_buffer(
+        &self,
+        physical: &Physical,
+        set: vk::DescriptorSet,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    ) {
+        let buffer_info = [vk::DescriptorBufferInfoBuilder::new()
+            .buffer(buffer)
+            .offset(offset)
+            .range(range)];
+        let write = vk::WriteDescriptorSetBuilder::new()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(self.bindings[binding as usize].descriptor_type())
+            .buffer_info(&buffer_info);
+        unsafe { physical.device.update_descriptor_sets(&[write], &[]) }
+    }
+
+    //Points a StorageImage or SampledImage binding at `view`, `sampler` is only used by SampledImage
+    pub fn write_image(
+        &self,
+        physical: &Physical,
+        set: vk::DescriptorSet,
+        binding: u32,
+        view: vk::ImageView,
+        sampler: Option<vk::Sampler>,
+    ) {
+        let kind = self.bindings[binding as usize];
+        let layout = match kind {
+            KernelBinding::StorageImage => vk::ImageLayout::GENERAL,
+            _ => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let mut image_info = vk::DescriptorImageInfoBuilder::new()
+            .image_view(view)
+            .image_layout(layout);
+        if let Some(sampler) = sampler {
+            image_info = image_info.sampler(sampler);
+        }
+        let image_infos = [image_info];
+        let write = vk::WriteDescriptorSetBuilder::new()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(kind.descriptor_type())
+            .image_info(&image_infos);
+        unsafe { physical.device.update_descriptor_sets(&[write], &[]) }
+    }
+
+    //Binds the kernel with `set` and dispatches `groups` work groups. `push_constants` has to be the size given to new.
+    pub fn dispatch<T: bytemuck::Pod>(
+        &self,
+        physical: &Physical,
+        cmd: vk::CommandBuffer,
+        set: vk::DescriptorSet,
+        push_constants: Option<&T>,
+        groups: [u32; 3],
+    ) {
+        unsafe {
+            physical.device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipelines[0],
+            );
+            physical.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.pipeline_layout,
+                0,
+                &[set],
+                &[],
+            );
+            if let Some(constants) = push_constants {
+                debug_assert_eq!(size_of_val(constants) as u32, self.push_constant_size);
+                physical.device.cmd_push_constants(
+                    cmd,
+                    self.pipeline.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    size_of_val(constants) as u32,
+                    constants as *const T as *const std::ffi::c_void,
+                );
+            }
+            physical.device.cmd_dispatch(cmd, groups[0], groups[1], groups[2]);
+        }
+    }
+
+    //One dimensional dispatch covering `size` items with `group_size` invocations per group
+    pub fn dispatch_linear<T: bytemuck::Pod>(
+        &self,
+        physical: &Physical,
+        cmd: vk::CommandBuffer,
+        set: vk::DescriptorSet,
+        push_constants: Option<&T>,
+        size: u32,
+        group_size: u32,
+    ) {
+        self.dispatch(physical, cmd, set, push_constants, [group_count(size, group_size), 1, 1]);
+    }
+}
+
+struct ComputeFrame {
+    command_buffer: vk::CommandBuffer,
+    //signalled when the frame's compute work is done, waited on by the graphics submit of the same frame
+    semaphore: Semaphore,
+    //stage of the graphics frame that consumes the results, set when work was submitted
+    pending: Option<vk::PipelineStageFlags>,
+}
+
+//Per frame compute work on Physical::compute_queue, which runs next to the graphics queue when the device has
+//a compute only family. Results are handed to the graphics frame with a semaphore. Because every submission is
+//waited on by the frame's graphics submit, the frame's render fence also covers the compute command buffer.
+//Resources shared with the graphics queue should use Physical::graphics_compute_families.
+pub struct AsyncCompute {
+    frames: Vec<ComputeFrame>,
+    //frees the command buffers when dropped
+    #[allow(dead_code)]
+    command_pool: CommandPool,
+}
+
+impl AsyncCompute {
+    pub fn new(physical: &Physical, frame_count: usize) -> Self {
+        let command_pool_info = vk::CommandPoolCreateInfoBuilder::new()
+            .queue_family_index(physical.compute_queue_family)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = unsafe {
+            physical
+                .device
+                .create_command_pool(&command_pool_info, None, None)
+        }
+        .unwrap();
+        let command_pool = CommandPool::new(&physical.device, command_pool);
+
+        let command_buffer_info = vk::CommandBufferAllocateInfoBuilder::new()
+            .command_pool(command_pool.handle)
+            .command_buffer_count(frame_count as u32)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let command_buffers = unsafe {
+            physical
+                .device
+                .allocate_command_buffers(&command_buffer_info)
+        }
+        .unwrap();
+
+        let frames = command_buffers
+            .into_iter()
+            .map(|command_buffer| {
+                let semaphore_info = vk::SemaphoreCreateInfoBuilder::new();
+                let semaphore = unsafe { physical.device.create_semaphore(&semaphore_info, None, None) }.unwrap();
+                let semaphore = Semaphore::new(&physical.device, semaphore);
+                ComputeFrame {
+                    command_buffer,
+                    semaphore,
+                    pending: None,
+                }
+            })
+            .collect();
+
+        AsyncCompute { frames, command_pool }
+    }
+
+    //Records compute work for `frame` and submits it. The graphics submit of that frame waits for it at `wait_stage`.
+    //Only call after the frame's render fence has been waited on.
+    pub fn submit<F: FnOnce(vk::CommandBuffer)>(
+        &mut self,
+        physical: &Physical,
+        frame: usize,
+        wait_stage: vk::PipelineStageFlags,
+        record: F,
+    ) {
+        let frame = &mut self.frames[frame];
+        //one submission per frame, the semaphore can't be signalled twice
+        assert!(frame.pending.is_none(), "compute was already submitted for this frame");
+        let cmd = frame.command_buffer;
+        let cmd_begin_info = vk::CommandBufferBeginInfoBuilder::new()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            physical
+                .device
+                .reset_command_buffer(cmd, Some(vk::CommandBufferResetFlags::empty()))
+                .unwrap();
+            physical.device.begin_command_buffer(cmd, &cmd_begin_info).unwrap();
+        }
+
+        record(cmd);
+
+        unsafe { physical.device.end_command_buffer(cmd) }.unwrap();
+
+        let command_buffers = [cmd];
+        let signal_semaphores = [frame.semaphore.handle];
+        let submit_info = vk::SubmitInfoBuilder::new()
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        unsafe {
+            physical
+                .device
+                .queue_submit(physical.compute_queue, &[submit_info], None)
+        }
+        .unwrap();
+        frame.pending = Some(wait_stage);
+    }
+
+    //The semaphore and stage the graphics submit of `frame` has to wait on, if compute work was submitted for it
+    pub fn take_wait(&mut self, frame: usize) -> Option<(vk::Semaphore, vk::PipelineStageFlags)> {
+        let frame = &mut self.frames[frame];
+        frame.pending.take().map(|stage| (frame.semaphore.handle, stage))
+    }
+}
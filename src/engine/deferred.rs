@@ -0,0 +1,380 @@
+use std::mem::size_of;
+
+use bytemuck_derive::{Pod, Zeroable};
+use erupt::vk;
+use vk_shader_macros::include_glsl;
+
+extern crate nalgebra as na;
+
+use super::{
+    descriptors::{DescriptorSetLayout, Descriptors},
+    device::Physical,
+    handles::{DescriptorPool, Framebuffer, RenderPass, Sampler},
+    pipeline::PipelineStruct,
+    postprocess::{create_framebuffer, create_render_pass},
+    renderpass::{DEPTH_FORMAT, HDR_FORMAT},
+};
+
+const LIGHTING_FRAG: &[u32] = include_glsl!("src/shaders/deferred-lighting.frag", kind: frag);
+
+//Color attachments of the G-buffer pass, in the order of the gbuffer.frag outputs:
+//albedo with occlusion in a, world space normal, metallic and roughness, emissive
+pub const GBUFFER_ATTACHMENTS: usize = 4;
+pub const GBUFFER_FORMATS: [vk::Format; GBUFFER_ATTACHMENTS] = [
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+pub const GBUFFER_NAMES: [&str; GBUFFER_ATTACHMENTS] = [
+    "gbuffer albedo",
+    "gbuffer normal",
+    "gbuffer material",
+    "gbuffer emissive",
+];
+
+//How the scene's opaque objects are shaded, chosen when the renderer is created
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShadingPath {
+    //every object is lit while it is drawn
+    Forward,
+    //opaque objects write a G-buffer that one full screen pass lights with every scene light.
+    //Transparent objects are still drawn forward on top. MSAA isn't supported.
+    Deferred,
+}
+
+//What the deferred lighting pass writes, everything except Lit shows a G-buffer channel as is
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GBufferView {
+    Lit,
+    Albedo,
+    Normals,
+    //metallic in red, roughness in green, occlusion in blue
+    Material,
+    Emissive,
+    //linear view distance, white at the far plane
+    Depth,
+}
+
+impl GBufferView {
+    //the next view, wrapping around to Lit
+    pub fn next(self) -> Self {
+        match self {
+            GBufferView::Lit => GBufferView::Albedo,
+            GBufferView::Albedo => GBufferView::Normals,
+            GBufferView::Normals => GBufferView::Material,
+            GBufferView::Material => GBufferView::Emissive,
+            GBufferView::Emissive => GBufferView::Depth,
+            GBufferView::Depth => GBufferView::Lit,
+        }
+    }
+
+    fn index(self) -> f32 {
+        match self {
+            GBufferView::Lit => 0.0,
+            GBufferView::Albedo => 1.0,
+            GBufferView::Normals => 2.0,
+            GBufferView::Material => 3.0,
+            GBufferView::Emissive => 4.0,
+            GBufferView::Depth => 5.0,
+        }
+    }
+}
+
+//Push constants of deferred-lighting.frag
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct LightingPushConstants {
+    //screen uv and depth back to world space
+    pub inverse_viewproj: na::Matrix4<f32>,
+    //written where nothing was drawn
+    pub clear_color: [f32; 4],
+    //x the GBufferView, y the far plane distance
+    pub params: [f32; 4],
+}
+
+//The two passes of the deferred path. The G-buffer and depth images belong to the render graph,
+//the lighting pass writes the graph's hdr image.
+pub struct Deferred {
+    gbuffer_pass: RenderPass,
+    gbuffer_framebuffer: Framebuffer,
+    //drawn with instead of the material's own pipeline
+    pub gbuffer_pipeline: PipelineStruct,
+    lighting_pass: RenderPass,
+    lighting_framebuffer: Framebuffer,
+    lighting_pipeline: PipelineStruct,
+    #[allow(dead_code)]
+    descriptor_pool: DescriptorPool,
+    //the G-buffer images and depth, sampled by the lighting pass
+    descriptor_set: vk::DescriptorSet,
+    #[allow(dead_code)]
+    sampler: Sampler,
+    extent: vk::Extent2D,
+    pub view: GBufferView,
+}
+
+impl Deferred {
+    pub fn new(
+        physical: &mut Physical,
+        descs: &Descriptors,
+        gbuffer_views: &[vk::ImageView],
+        depth_view: vk::ImageView,
+        hdr_view: vk::ImageView,
+        view: GBufferView,
+    ) -> Self {
+        let extent = physical.surface_caps.current_extent;
+
+        let gbuffer_pass = create_gbuffer_pass(physical);
+        let mut attachments = gbuffer_views.to_vec();
+        attachments.push(depth_view);
+        let framebuffer_info = vk::FramebufferCreateInfoBuilder::new()
+            .render_pass(gbuffer_pass.handle)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let gbuffer_framebuffer = unsafe {
+            physical
+                .device
+                .create_framebuffer(&framebuffer_info, None, None)
+        }
+        .unwrap();
+        let gbuffer_framebuffer = Framebuffer::new(&physical.device, gbuffer_framebuffer);
+        let gbuffer_pipeline = PipelineStruct::gbuffer(physical, gbuffer_pass.handle, descs);
+
+        //the G-buffer channels followed by depth
+        let bindings: Vec<_> = (0..=GBUFFER_ATTACHMENTS as u32)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBindingBuilder::new()
+                    .binding(binding)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            })
+            .collect();
+        let set_layout = DescriptorSetLayout::new(physical, &bindings);
+
+        let sizes = [vk::DescriptorPoolSizeBuilder::new()
+            ._type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(GBUFFER_ATTACHMENTS as u32 + 1)];
+        let pool_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .max_sets(1)
+            .pool_sizes(&sizes);
+        let descriptor_pool = unsafe {
+            physical
+                .device
+                .create_descriptor_pool(&pool_info, None, None)
+        }
+        .unwrap();
+        let descriptor_pool = DescriptorPool::new(&physical.device, descriptor_pool);
+
+        //the lighting pass reads exactly one texel per pixel
+        let sampler_info = vk::SamplerCreateInfoBuilder::new()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = Sampler::new(
+            &physical.device,
+            unsafe { physical.device.create_sampler(&sampler_info, None, None) }.unwrap(),
+        );
+
+        let set_layouts = [set_layout.layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(descriptor_pool.handle)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0];
+        let image_infos: Vec<[vk::DescriptorImageInfoBuilder; 1]> = gbuffer_views
+            .iter()
+            .map(|view| (*view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))
+            .chain(std::iter::once((
+                depth_view,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            )))
+            .map(|(view, layout)| {
+                [vk::DescriptorImageInfoBuilder::new()
+                    .sampler(sampler.handle)
+                    .image_view(view)
+                    .image_layout(layout)]
+            })
+            .collect();
+        let writes: Vec<_> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, image_info)| {
+                vk::WriteDescriptorSetBuilder::new()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(image_info)
+            })
+            .collect();
+        unsafe { physical.device.update_descriptor_sets(&writes, &[]) }
+
+        let lighting_pass = create_render_pass(physical, HDR_FORMAT);
+        let lighting_framebuffer = create_framebuffer(physical, lighting_pass.handle, hdr_view, extent);
+        let lighting_pipeline = PipelineStruct::fullscreen(
+            physical,
+            lighting_pass.handle,
+            &[descs.global_set_layout.layout, set_layout.layout],
+            size_of::<LightingPushConstants>() as u32,
+            LIGHTING_FRAG,
+        );
+
+        Deferred {
+            gbuffer_pass,
+            gbuffer_framebuffer,
+            gbuffer_pipeline,
+            lighting_pass,
+            lighting_framebuffer,
+            lighting_pipeline,
+            descriptor_pool,
+            descriptor_set,
+            sampler,
+            extent,
+            view,
+        }
+    }
+
+    //Begins the G-buffer render pass with everything cleared, the caller draws the opaque objects with
+    //`gbuffer_pipeline` and ends the pass
+    pub fn begin_gbuffer_pass(&self, physical: &Physical, cmd: vk::CommandBuffer) {
+        let mut clear_values = vec![
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0.0; 4] }
+            };
+            GBUFFER_ATTACHMENTS
+        ];
+        clear_values.push(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        });
+        let rp_info = vk::RenderPassBeginInfoBuilder::new()
+            .render_pass(self.gbuffer_pass.handle)
+            .framebuffer(self.gbuffer_framebuffer.handle)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values);
+        unsafe {
+            physical
+                .device
+                .cmd_begin_render_pass(cmd, &rp_info, vk::SubpassContents::INLINE)
+        };
+    }
+
+    //Lights every pixel of the G-buffer with all scene lights into the hdr image, or shows the selected channel
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_lighting(
+        &self,
+        physical: &Physical,
+        cmd: vk::CommandBuffer,
+        global_descriptor: vk::DescriptorSet,
+        global_offsets: &[u32],
+        inverse_viewproj: na::Matrix4<f32>,
+        clear_color: [f32; 4],
+        z_far: f32,
+    ) {
+        let rp_info = vk::RenderPassBeginInfoBuilder::new()
+            .render_pass(self.lighting_pass.handle)
+            .framebuffer(self.lighting_framebuffer.handle)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            });
+        let constants = LightingPushConstants {
+            inverse_viewproj,
+            clear_color,
+            params: [self.view.index(), z_far, 0.0, 0.0],
+        };
+        unsafe {
+            physical
+                .device
+                .cmd_begin_render_pass(cmd, &rp_info, vk::SubpassContents::INLINE);
+            physical.device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lighting_pipeline.pipelines[0],
+            );
+            physical.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lighting_pipeline.pipeline_layout,
+                0,
+                &[global_descriptor, self.descriptor_set],
+                global_offsets,
+            );
+            physical.device.cmd_push_constants(
+                cmd,
+                self.lighting_pipeline.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                size_of::<LightingPushConstants>() as u32,
+                &constants as *const LightingPushConstants as *const std::ffi::c_void,
+            );
+            physical.device.cmd_draw(cmd, 3, 1, 0, 0);
+            physical.device.cmd_end_render_pass(cmd);
+        }
+    }
+}
+
+//G-buffer channels and depth, all cleared. Like the other passes it leaves layouts and synchronization to the render graph.
+fn create_gbuffer_pass(physical: &Physical) -> RenderPass {
+    let mut attachments: Vec<_> = GBUFFER_FORMATS
+        .iter()
+        .map(|format| {
+            vk::AttachmentDescription2Builder::new()
+                .format(*format)
+                .samples(vk::SampleCountFlagBits::_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        })
+        .collect();
+    attachments.push(
+        vk::AttachmentDescription2Builder::new()
+            .format(DEPTH_FORMAT)
+            .samples(vk::SampleCountFlagBits::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+    );
+
+    let color_refs: Vec<_> = (0..GBUFFER_ATTACHMENTS as u32)
+        .map(|attachment| {
+            vk::AttachmentReference2Builder::new()
+                .attachment(attachment)
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        })
+        .collect();
+    let depth_ref = vk::AttachmentReference2Builder::new()
+        .attachment(GBUFFER_ATTACHMENTS as u32)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    let subpass = vk::SubpassDescription2Builder::new()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_refs)
+        .depth_stencil_attachment(&depth_ref);
+
+    let subpasses = [subpass];
+    let render_pass_info = vk::RenderPassCreateInfo2Builder::new()
+        .attachments(&attachments)
+        .subpasses(&subpasses);
+    let render_pass = unsafe {
+        physical
+            .device
+            .create_render_pass2(&render_pass_info, None, None)
+    }
+    .unwrap();
+    RenderPass::new(&physical.device, render_pass)
+}
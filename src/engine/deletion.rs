@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+
+use erupt::vk;
+
+use super::{device::Physical, mesh::Mesh, mesh_pool::MeshPool};
+
+type Destroy = Box<dyn FnOnce(&mut Physical, &mut MeshPool)>;
+
+//Destroys resources once no frame in flight can use them anymore. Every closure is tagged with the frame that was
+//being recorded when it was queued, and runs after that frame's render fence has signalled.
+pub struct DeletionQueue {
+    //in the order they were queued, so the frame numbers never decrease
+    pending: VecDeque<(i64, Destroy)>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        DeletionQueue {
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn push<F: FnOnce(&mut Physical, &mut MeshPool) + 'static>(&mut self, framenumber: i64, destroy: F) {
+        self.pending.push_back((framenumber, Box::new(destroy)));
+    }
+
+    //Keeps an owned resource (buffer, texture, descriptor pool, or anything holding them) alive until then and drops it
+    pub fn release<T: 'static>(&mut self, framenumber: i64, resource: T) {
+        self.push(framenumber, move |_, _| drop(resource));
+    }
+
+    //`pool` has to be created with FREE_DESCRIPTOR_SET
+    pub fn free_descriptor_set(&mut self, framenumber: i64, pool: vk::DescriptorPool, set: vk::DescriptorSet) {
+        self.push(framenumber, move |physical, _| unsafe {
+            physical.device.free_descriptor_sets(pool, &[set]).unwrap();
+        });
+    }
+
+    //Returns the mesh's ranges to the pool, they could be overwritten by the next upload otherwise
+    pub fn free_mesh(&mut self, framenumber: i64, mut mesh: Mesh) {
+        self.push(framenumber, move |_, pool| mesh.cleanup(pool));
+    }
+
+    //Runs everything queued in frames up to `completed`, call it once the render fence of that frame was waited on
+    pub fn flush(&mut self, completed: i64, physical: &mut Physical, pool: &mut MeshPool) {
+        while let Some((framenumber, _)) = self.pending.front() {
+            if *framenumber > completed {
+                break;
+            }
+            let (_, destroy) = self.pending.pop_front().unwrap();
+            destroy(physical, pool);
+        }
+    }
+
+    //At shutdown, once the device is idle
+    pub fn flush_all(&mut self, physical: &mut Physical, pool: &mut MeshPool) {
+        for (_, destroy) in self.pending.drain(..) {
+            destroy(physical, pool);
+        }
+    }
+}
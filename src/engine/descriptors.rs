@@ -1,37 +1,120 @@
+use std::sync::Arc;
+
 use erupt::vk;
 
-use super::device::Physical;
+use super::{
+    device::{Device, Physical, ResourceKind},
+    handles::DescriptorPool,
+};
+
+//Upper limit for materials with a descriptor set, the pool is sized for it
+pub const MAX_MATERIALS: u32 = 256;
+//Combined image samplers in a material set, see MaterialResources::create_descriptor_set
+const MATERIAL_TEXTURES: u32 = 5;
+
+//Destroyed when dropped, pipelines and sets made with the layout don't need it to stay alive
+pub struct DescriptorSetLayout {
+    pub layout: vk::DescriptorSetLayout,
+    device: Arc<Device>,
+}
+
+impl DescriptorSetLayout {
+    pub fn new(physical: &Physical, bindings: &[vk::DescriptorSetLayoutBindingBuilder]) -> Self {
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfoBuilder::new().bindings(bindings);
+        let layout = unsafe {
+            physical
+                .device
+                .create_descriptor_set_layout(&set_layout_info, None, None)
+        }
+        .unwrap();
+        physical.device.created(ResourceKind::DescriptorSetLayout);
+        DescriptorSetLayout {
+            layout,
+            device: Arc::clone(&physical.device),
+        }
+    }
+}
+
+impl Drop for DescriptorSetLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .destroy_descriptor_set_layout(Some(self.layout), None);
+        }
+        self.device.released(ResourceKind::DescriptorSetLayout);
+    }
+}
+
 pub struct Descriptors {
-    pub global_set_layout: vk::DescriptorSetLayout,
-    pub descriptor_pool: vk::DescriptorPool,
+    pub global_set_layout: DescriptorSetLayout,
+    pub material_set_layout: DescriptorSetLayout,
+    pub descriptor_pool: DescriptorPool,
 }
 impl Descriptors {
     pub fn new(physical: &Physical) -> Descriptors {
+        //camera and scene data live in the frame's FrameAllocator, at offsets given when the set is bound
         let cam_buff_binding = vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        //lights are needed by the fragment shader, the camera position by both stages
+        let scene_buff_binding = vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(1)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        let shadow_map_binding = vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(2)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let mut bindings = vec![cam_buff_binding, scene_buff_binding, shadow_map_binding];
+        //image based lighting: irradiance, prefiltered specular and the brdf lut
+        for binding in 3..=5 {
+            bindings.push(
+                vk::DescriptorSetLayoutBindingBuilder::new()
+                    .binding(binding)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+            );
+        }
+        let set = DescriptorSetLayout::new(physical, &bindings);
+
+        //set 1: material parameters followed by its textures
+        let mut material_bindings = vec![vk::DescriptorSetLayoutBindingBuilder::new()
             .binding(0)
             .descriptor_count(1)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .stage_flags(vk::ShaderStageFlags::VERTEX);
-        let bindings = vec![cam_buff_binding];
-        let set_info = vk::DescriptorSetLayoutCreateInfoBuilder::new()
-            .bindings(&bindings)
-            .flags(vk::DescriptorSetLayoutCreateFlags::empty());
-        let set = unsafe {
-            physical
-                .device
-                .create_descriptor_set_layout(&set_info, None, None)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+        for binding in 1..=MATERIAL_TEXTURES {
+            material_bindings.push(
+                vk::DescriptorSetLayoutBindingBuilder::new()
+                    .binding(binding)
+                    .descriptor_count(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+            );
         }
-        .unwrap();
+        let material_set_layout = DescriptorSetLayout::new(physical, &material_bindings);
 
         let sizes: Vec<vk::DescriptorPoolSizeBuilder> = vec![
             vk::DescriptorPoolSizeBuilder::new()
                 ._type(vk::DescriptorType::UNIFORM_BUFFER)
-                .descriptor_count(10);
-            10
+                .descriptor_count(20 + MAX_MATERIALS),
+            vk::DescriptorPoolSizeBuilder::new()
+                ._type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .descriptor_count(20),
+            vk::DescriptorPoolSizeBuilder::new()
+                ._type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(20 + MATERIAL_TEXTURES * MAX_MATERIALS),
         ];
 
+        //removed materials give their set back, see DeletionQueue::free_descriptor_set
         let pool_info = vk::DescriptorPoolCreateInfoBuilder::new()
-            .max_sets(10)
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(10 + MAX_MATERIALS)
             .pool_sizes(&sizes);
 
         let descriptor_pool = unsafe {
@@ -44,19 +127,9 @@ impl Descriptors {
         return {
             Descriptors {
                 global_set_layout: set,
-                descriptor_pool: descriptor_pool,
+                material_set_layout,
+                descriptor_pool: DescriptorPool::new(&physical.device, descriptor_pool),
             }
         };
     }
-
-    pub fn cleanup(&mut self, physical: &mut Physical) {
-        unsafe {
-            physical
-            .device
-            .destroy_descriptor_pool(Some(self.descriptor_pool), None);
-            physical
-                .device
-                .destroy_descriptor_set_layout(Some(self.global_set_layout), None);
-        }
-    }
 }
@@ -3,7 +3,12 @@ const VALIDATION_LAYERS_WANTED: bool = true;
 
 use std::{
     ffi::{c_void, CStr, CString},
+    ops::Deref,
     os::raw::c_char,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
 use erupt::{
@@ -12,10 +17,12 @@ use erupt::{
     vk::{self, DeviceMemory},
     DeviceLoader, EntryLoader, ExtendableFrom, InstanceLoader,
 };
-use gpu_alloc::{Config, GpuAllocator};
+use gpu_alloc::{Config, GpuAllocator, MemoryBlock, Request};
 use gpu_alloc_erupt::{device_properties as device_properties_alloc, EruptMemoryDevice};
 use winit::window::Window;
 
+use super::memory_stats::{MemoryCategory, MemoryStats, MemoryTracker};
+
 //debug_callback for the validation layers
 unsafe extern "system" fn debug_callback(
     _message_severity: vk::DebugUtilsMessageSeverityFlagBitsEXT,
@@ -31,14 +38,122 @@ unsafe extern "system" fn debug_callback(
     vk::FALSE
 }
 
+//Resources that count themselves in Device, so the ones still alive at shutdown can be reported
+#[derive(Copy, Clone, Debug)]
+pub enum ResourceKind {
+    Buffer,
+    Image,
+    Pipeline,
+    DescriptorSetLayout,
+    ImageView,
+    Sampler,
+    Framebuffer,
+    RenderPass,
+    DescriptorPool,
+    Semaphore,
+    Fence,
+    CommandPool,
+    Swapchain,
+}
+
+const RESOURCE_KINDS: [ResourceKind; 13] = [
+    ResourceKind::Buffer,
+    ResourceKind::Image,
+    ResourceKind::Pipeline,
+    ResourceKind::DescriptorSetLayout,
+    ResourceKind::ImageView,
+    ResourceKind::Sampler,
+    ResourceKind::Framebuffer,
+    ResourceKind::RenderPass,
+    ResourceKind::DescriptorPool,
+    ResourceKind::Semaphore,
+    ResourceKind::Fence,
+    ResourceKind::CommandPool,
+    ResourceKind::Swapchain,
+];
+
+//The logical device and its memory allocator, `memory` counts the blocks it hands out. Owned resources
+//(AllocatedBuffer, AllocatedImage, PipelineStruct, DescriptorSetLayout, Swapchain and the types in handles.rs)
+//keep an Arc of it and release themselves when they are dropped, Physical destroys the device once it is dropped
+//itself. Objects created and destroyed within one call, like the command pool of immediate_submit, stay raw handles.
+pub struct Device {
+    loader: DeviceLoader,
+    allocator: Mutex<GpuAllocator<DeviceMemory>>,
+    memory: Mutex<MemoryTracker>,
+    live: [AtomicUsize; RESOURCE_KINDS.len()],
+}
+
+impl Deref for Device {
+    type Target = DeviceLoader;
+
+    fn deref(&self) -> &DeviceLoader {
+        &self.loader
+    }
+}
+
+impl Device {
+    pub fn alloc(&self, request: Request, category: MemoryCategory) -> MemoryBlock<DeviceMemory> {
+        let block = unsafe {
+            self.allocator
+                .lock()
+                .unwrap()
+                .alloc(EruptMemoryDevice::wrap(&self.loader), request)
+        }
+        .unwrap();
+        self.memory
+            .lock()
+            .unwrap()
+            .allocated(&block, request.usage, category);
+        block
+    }
+
+    //`block` must not be used by the GPU anymore
+    pub unsafe fn dealloc(&self, block: MemoryBlock<DeviceMemory>) {
+        self.memory.lock().unwrap().freed(&block);
+        self.allocator
+            .lock()
+            .unwrap()
+            .dealloc(EruptMemoryDevice::wrap(&self.loader), block);
+    }
+
+    //Called by the owned resource types when they are created and dropped
+    pub fn created(&self, kind: ResourceKind) {
+        self.live[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn released(&self, kind: ResourceKind) {
+        self.live[kind as usize].fetch_sub(1, Ordering::Relaxed);
+    }
+
+    //Prints the resources that are still alive
+    fn report_leaks(&self) {
+        for kind in RESOURCE_KINDS {
+            let live = self.live[kind as usize].load(Ordering::Relaxed);
+            if live > 0 {
+                println!("{} {:?} resources were not released before the device was destroyed", live, kind);
+            }
+        }
+    }
+}
+
 pub struct Physical {
     pub surface_caps: vk::SurfaceCapabilitiesKHR,
-    pub allocator: GpuAllocator<DeviceMemory>,
     pub format: vk::SurfaceFormatKHR,
     pub graphics_queue: vk::Queue,
     pub graphics_queue_family: u32,
+    //a compute only family if the device has one, so compute work can overlap the frame. Otherwise the graphics queue.
+    pub compute_queue: vk::Queue,
+    pub compute_queue_family: u32,
+    //a transfer only family if the device has one, used by the UploadManager's background thread. Otherwise the graphics queue.
+    pub transfer_queue: vk::Queue,
+    pub transfer_queue_family: u32,
     pub physical_device: vk::PhysicalDevice,
-    pub device: DeviceLoader,
+    pub device: Arc<Device>,
+    //VK_EXT_memory_budget is enabled, memory_stats reports the heaps' budgets
+    pub memory_budget: bool,
+    //multi draw indirect, first instance in indirect draws and indirect count draws are all enabled, the GPU driven
+    //path needs them
+    pub indirect_draws: bool,
     pub messenger: vk::DebugUtilsMessengerEXT,
     pub surface: vk::SurfaceKHR,
     pub instance: InstanceLoader,
@@ -46,7 +161,7 @@ pub struct Physical {
 }
 
 impl Physical {
-    pub fn new(window: &Window) -> Self {
+    pub fn new(window: &Window, allocator_config: Config) -> Self {
         let entry = EntryLoader::new().unwrap();
 
         let application_name = CString::new("Renderupt").unwrap();
@@ -69,7 +184,7 @@ impl Physical {
         }
 
         // swapchian extension wanted as well
-        let device_extensions = vec![
+        let mut device_extensions = vec![
             vk::KHR_SWAPCHAIN_EXTENSION_NAME,
             vk::KHR_BUFFER_DEVICE_ADDRESS_EXTENSION_NAME,
         ];
@@ -201,16 +316,79 @@ impl Physical {
             CStr::from_ptr(device_properties.device_name.as_ptr())
         });
 
-        let queue_info = vec![vk::DeviceQueueCreateInfoBuilder::new()
+        //optional, only used for memory_stats
+        let memory_budget = unsafe { instance.enumerate_device_extension_properties(physical_device, None, None) }
+            .unwrap()
+            .iter()
+            .any(|properties| unsafe {
+                CStr::from_ptr(properties.extension_name.as_ptr())
+                    == CStr::from_ptr(vk::EXT_MEMORY_BUDGET_EXTENSION_NAME)
+            });
+        if memory_budget {
+            device_extensions.push(vk::EXT_MEMORY_BUDGET_EXTENSION_NAME);
+        }
+
+        let family_properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device, None) };
+        let compute_family = family_properties
+            .iter()
+            .position(|properties| {
+                properties.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !properties.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|family| family as u32);
+        //usually backed by the copy engines, which run alongside graphics work
+        let transfer_family = family_properties
+            .iter()
+            .position(|properties| {
+                properties.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !properties
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|family| family as u32);
+
+        let mut queue_info = vec![vk::DeviceQueueCreateInfoBuilder::new()
             .queue_family_index(queue_family)
             .queue_priorities(&[1.0])];
-        let features = vk::PhysicalDeviceFeaturesBuilder::new();
+        if let Some(compute_family) = compute_family {
+            queue_info.push(
+                vk::DeviceQueueCreateInfoBuilder::new()
+                    .queue_family_index(compute_family)
+                    .queue_priorities(&[1.0]),
+            );
+        }
+        if let Some(transfer_family) = transfer_family {
+            queue_info.push(
+                vk::DeviceQueueCreateInfoBuilder::new()
+                    .queue_family_index(transfer_family)
+                    .queue_priorities(&[1.0]),
+            );
+        }
+        //the indirect draw features are optional, enabling one the device lacks fails device creation
+        let mut supported12 = vk::PhysicalDeviceVulkan12FeaturesBuilder::new();
+        let supported = unsafe {
+            let query = vk::PhysicalDeviceFeatures2Builder::new().extend_from(&mut supported12);
+            instance.get_physical_device_features2(physical_device, Some(*query))
+        }
+        .features;
+        let multi_draw_indirect = supported.multi_draw_indirect == vk::TRUE;
+        let draw_indirect_first_instance = supported.draw_indirect_first_instance == vk::TRUE;
+        let draw_indirect_count = supported12.draw_indirect_count == vk::TRUE;
+        //indirect draws of the GPU driven path pass the object index as the first instance
+        let indirect_draws = multi_draw_indirect && draw_indirect_first_instance && draw_indirect_count;
 
-        let mut test2 =
-            vk::PhysicalDeviceVulkan12FeaturesBuilder::new().buffer_device_address(true);
+        let features = vk::PhysicalDeviceFeaturesBuilder::new()
+            .multi_draw_indirect(multi_draw_indirect)
+            .draw_indirect_first_instance(draw_indirect_first_instance);
 
-        let mut device_features2_builder =
-            vk::PhysicalDeviceFeatures2Builder::new().extend_from(&mut test2);
+        let mut test2 = vk::PhysicalDeviceVulkan12FeaturesBuilder::new()
+            .buffer_device_address(true)
+            .draw_indirect_count(draw_indirect_count);
+
+        let mut device_features2_builder = vk::PhysicalDeviceFeatures2Builder::new()
+            .features(*features)
+            .extend_from(&mut test2);
 
         //set features and extensions enabled in the device
         let device_info = vk::DeviceCreateInfoBuilder::new()
@@ -225,10 +403,19 @@ impl Physical {
         //finally have a device and queue
         let device = DeviceLoader::new(&instance, physical_device, &device_info, None).unwrap();
         let queue = unsafe { device.get_device_queue(queue_family, 0, None) };
+        let compute_queue_family = compute_family.unwrap_or(queue_family);
+        let compute_queue = unsafe { device.get_device_queue(compute_queue_family, 0, None) };
+        if compute_family.is_some() {
+            println!("Using async compute queue family {}", compute_queue_family);
+        }
+        let transfer_queue_family = transfer_family.unwrap_or(queue_family);
+        let transfer_queue = unsafe { device.get_device_queue(transfer_queue_family, 0, None) };
+        if transfer_family.is_some() {
+            println!("Using transfer queue family {}", transfer_queue_family);
+        }
 
-        let config = Config::i_am_potato();
-
-        let gpu_alloc = GpuAllocator::new(config, device_properties_alloc);
+        let gpu_alloc = GpuAllocator::new(allocator_config, device_properties_alloc);
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device, None) };
 
         //create a swapchain
         let surface_caps = unsafe {
@@ -236,24 +423,107 @@ impl Physical {
         }
         .unwrap();
 
+        let device = Arc::new(Device {
+            loader: device,
+            allocator: Mutex::new(gpu_alloc),
+            memory: Mutex::new(MemoryTracker::new(&memory_properties)),
+            live: Default::default(),
+        });
+
         Physical {
             surface_caps,
-            allocator: gpu_alloc,
             format,
             physical_device,
             graphics_queue_family: queue_family,
             graphics_queue: queue,
+            compute_queue_family,
+            compute_queue,
+            transfer_queue_family,
+            transfer_queue,
             device,
+            memory_budget,
+            indirect_draws,
             messenger,
             surface,
             instance,
             entry,
         }
     }
-    pub fn cleanup(&mut self) {
+    //Highest sample count up to `requested` that color and depth framebuffers both support
+    pub fn usable_sample_count(&self, requested: u32) -> vk::SampleCountFlagBits {
+        let limits = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device, None)
+        }
+        .limits;
+        let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        let counts = [
+            (64, vk::SampleCountFlagBits::_64, vk::SampleCountFlags::_64),
+            (32, vk::SampleCountFlagBits::_32, vk::SampleCountFlags::_32),
+            (16, vk::SampleCountFlagBits::_16, vk::SampleCountFlags::_16),
+            (8, vk::SampleCountFlagBits::_8, vk::SampleCountFlags::_8),
+            (4, vk::SampleCountFlagBits::_4, vk::SampleCountFlags::_4),
+            (2, vk::SampleCountFlagBits::_2, vk::SampleCountFlags::_2),
+        ];
+        let samples = counts
+            .iter()
+            .find(|(count, _, flag)| *count <= requested && supported.contains(*flag))
+            .map_or(vk::SampleCountFlagBits::_1, |(_, bits, _)| *bits);
+        if samples.0 != requested.max(1) {
+            println!("{}x MSAA is not supported, using {:?}", requested, samples);
+        }
+        samples
+    }
+
+    //true when compute_queue is separate from the graphics queue
+    pub fn has_async_compute(&self) -> bool {
+        self.compute_queue_family != self.graphics_queue_family
+    }
+
+    //true when transfer_queue is separate from the graphics queue, resources written on it need ownership transfers
+    pub fn has_transfer_queue(&self) -> bool {
+        self.transfer_queue_family != self.graphics_queue_family
+    }
+
+    //Queue families for resources used by both the graphics and the compute queue, created with
+    //vk::SharingMode::CONCURRENT if there is more than one so no ownership transfers are needed
+    pub fn graphics_compute_families(&self) -> Vec<u32> {
+        if self.has_async_compute() {
+            vec![self.graphics_queue_family, self.compute_queue_family]
+        } else {
+            vec![self.graphics_queue_family]
+        }
+    }
+
+    //Memory allocated through the device so far, with the heaps' budgets if VK_EXT_memory_budget is enabled
+    pub fn memory_stats(&self) -> MemoryStats {
+        let budget = if self.memory_budget {
+            let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXTBuilder::new();
+            let properties = vk::PhysicalDeviceMemoryProperties2Builder::new().extend_from(&mut budget);
+            unsafe {
+                self.instance
+                    .get_physical_device_memory_properties2(self.physical_device, Some(*properties))
+            };
+            Some(*budget)
+        } else {
+            None
+        };
+        self.device.memory.lock().unwrap().stats(budget.as_ref())
+    }
+}
+
+//Dropped after everything that was created with the device, see VulkanApp
+impl Drop for Physical {
+    fn drop(&mut self) {
+        //anything still holding the device at this point was leaked
+        self.device.report_leaks();
         unsafe {
-            self.allocator
-                .cleanup(EruptMemoryDevice::wrap(&self.device));
+            self.device
+                .allocator
+                .lock()
+                .unwrap()
+                .cleanup(EruptMemoryDevice::wrap(&self.device.loader));
             self.device.destroy_device(None);
             self.instance.destroy_surface_khr(Some(self.surface), None);
             if !self.messenger.is_null() {
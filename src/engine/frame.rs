@@ -1,23 +1,37 @@
 use std::mem::size_of;
 
 use erupt::vk;
-use gpu_alloc_erupt::EruptMemoryDevice;
 
 extern crate nalgebra as na;
 
-use super::{buffer::create_buffer, descriptors::Descriptors, device::Physical, mesh::AllocatedBuffer};
+use super::{
+    descriptors::Descriptors,
+    device::Physical,
+    frame_allocator::FrameAllocator,
+    handles::{CommandPool, Fence, Semaphore},
+    ibl::EnvironmentLighting,
+    light::{GPULight, MAX_LIGHTS},
+    shadow::{ShadowMap, MAX_CASCADES},
+};
 
 use bytemuck_derive::{Pod, Zeroable};
 
 pub struct Frame {
-    pub present_semaphore: vk::Semaphore,
-    pub render_semaphore: vk::Semaphore,
-    pub render_fence: vk::Fence,
-    pub command_pool: vk::CommandPool,
+    pub present_semaphore: Semaphore,
+    pub render_semaphore: Semaphore,
+    pub render_fence: Fence,
+    #[allow(dead_code)]
+    pub command_pool: CommandPool,
     pub command_buffer: vk::CommandBuffer,
-    pub camera_buffer: AllocatedBuffer,
+    //holds the camera and scene data of the global set, and whatever else is pushed during the frame
+    pub uniforms: FrameAllocator,
     pub global_descriptor: vk::DescriptorSet,
+    //dynamic offsets of the camera and scene data, to bind global_descriptor with
+    pub global_offsets: [u32; 2],
 }
+
+//Bytes of dynamic uniform data a frame can push
+const FRAME_UNIFORM_SIZE: u64 = 256 * 1024;
 #[repr(C)]
 #[derive(Copy, Clone, Zeroable, Pod)]
 pub struct GPUCameraData {
@@ -26,12 +40,42 @@ pub struct GPUCameraData {
     pub viewproj: na::Matrix4<f32>,
 }
 
+//Lighting for the whole frame, std140 layout matching SceneData in the lit shaders
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct GPUSceneData {
+    //w is unused
+    pub ambient_color: [f32; 4],
+    pub camera_position: [f32; 4],
+    //only x is used, padded to a vec4
+    pub light_count: [u32; 4],
+    //x cascade count, y normal bias in texels, z size of a shadow map texel in uv, w index of the shadowed light or -1
+    pub shadow_params: [f32; 4],
+    //far end of each cascade in view space depth
+    pub cascade_splits: [f32; MAX_CASCADES],
+    pub cascade_texel_sizes: [f32; MAX_CASCADES],
+    pub light_viewproj: [na::Matrix4<f32>; MAX_CASCADES],
+    pub lights: [GPULight; MAX_LIGHTS],
+}
+
+impl Frame {
+    //Pushes this frame's uniform data, uniforms has to be reset since the frame's fence signalled
+    pub fn write_uniforms(&mut self, camera: &GPUCameraData, scene: &GPUSceneData) {
+        self.global_offsets = [self.uniforms.push(camera), self.uniforms.push(scene)];
+    }
+}
+
 pub struct Frames {
     pub frames: Vec<Frame>,
 }
 
 impl Frames {
-    pub fn new(frame_count: u32, physical: &mut Physical, descs: &mut Descriptors) -> Self {
+    pub fn new(
+        frame_count: u32,
+        physical: &mut Physical,
+        descs: &mut Descriptors,
+        shadow_map: &ShadowMap,
+    ) -> Self {
         let mut frames: Vec<Frame> = Vec::with_capacity(2);
         for i in 0..frame_count {
             let fence_info =
@@ -47,15 +91,18 @@ impl Frames {
                     .create_semaphore(&semaphore_create_info, None, None)
             }
             .unwrap();
+            let render_semaphore = Semaphore::new(&physical.device, render_semaphore);
             let present_semaphore = unsafe {
                 physical
                     .device
                     .create_semaphore(&semaphore_create_info, None, None)
             }
             .unwrap();
+            let present_semaphore = Semaphore::new(&physical.device, present_semaphore);
 
             let render_fence =
                 unsafe { physical.device.create_fence(&fence_info, None, None) }.unwrap();
+            let render_fence = Fence::new(&physical.device, render_fence);
 
             let command_pool_info = vk::CommandPoolCreateInfoBuilder::new()
                 .queue_family_index(physical.graphics_queue_family)
@@ -67,9 +114,10 @@ impl Frames {
                     .create_command_pool(&command_pool_info, None, None)
             }
             .unwrap();
+            let command_pool = CommandPool::new(&physical.device, command_pool);
 
             let command_buffer_info = vk::CommandBufferAllocateInfoBuilder::new()
-                .command_pool(command_pool)
+                .command_pool(command_pool.handle)
                 .command_buffer_count(1)
                 .level(vk::CommandBufferLevel::PRIMARY);
 
@@ -80,17 +128,12 @@ impl Frames {
             }
             .unwrap();
 
-            let buffer = create_buffer(
-                physical,
-                size_of::<GPUCameraData>() as u64,
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
-                gpu_alloc::UsageFlags::UPLOAD,
-            );
+            let uniforms = FrameAllocator::new(physical, FRAME_UNIFORM_SIZE);
 
-            let global_set_layout = &[descs.global_set_layout];
+            let global_set_layout = &[descs.global_set_layout.layout];
 
             let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
-                .descriptor_pool(descs.descriptor_pool)
+                .descriptor_pool(descs.descriptor_pool.handle)
                 .set_layouts(global_set_layout);
 
             let global_descriptor = * unsafe {
@@ -98,7 +141,7 @@ impl Frames {
             };
             //descriptor buffe for camera r
             let buffer_info = vk::DescriptorBufferInfoBuilder::new()
-                .buffer(buffer.buffer)
+                .buffer(uniforms.buffer())
                 .offset(0)
                 .range(size_of::<GPUCameraData>() as u64);
 
@@ -107,15 +150,37 @@ impl Frames {
             let write_info = vk::WriteDescriptorSetBuilder::new()
                 .dst_binding(0)
                 .dst_set(global_descriptor)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
                 .buffer_info(&buffers_info);           
 
+            let scene_buffer_info = [vk::DescriptorBufferInfoBuilder::new()
+                .buffer(uniforms.buffer())
+                .offset(0)
+                .range(size_of::<GPUSceneData>() as u64)];
+
+            let scene_write_info = vk::WriteDescriptorSetBuilder::new()
+                .dst_binding(1)
+                .dst_set(global_descriptor)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                .buffer_info(&scene_buffer_info);
+
+            let shadow_image_info = [vk::DescriptorImageInfoBuilder::new()
+                .sampler(shadow_map.sampler.handle)
+                .image_view(shadow_map.array_view.handle)
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)];
+
+            let shadow_write_info = vk::WriteDescriptorSetBuilder::new()
+                .dst_binding(2)
+                .dst_set(global_descriptor)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&shadow_image_info);
+
             let copy_desc_info = vk::CopyDescriptorSetBuilder::new()
                 .dst_set(global_descriptor)
                 .src_set(global_descriptor);
                 
             unsafe {
-                physical.device.update_descriptor_sets(&[write_info], &[copy_desc_info])
+                physical.device.update_descriptor_sets(&[write_info, scene_write_info, shadow_write_info], &[copy_desc_info])
             }
                 
 
@@ -125,38 +190,39 @@ impl Frames {
                 render_fence,
                 command_pool,
                 command_buffer: command_buffer[0],
-                camera_buffer: buffer,
+                uniforms,
                 global_descriptor: global_descriptor,
+                global_offsets: [0; 2],
             })
         };
         Frames { frames } 
     }
-    pub fn cleanup(&mut self, physical: &mut Physical) {
-        for frame in &mut self.frames {
-            unsafe {
-                physical
-                    .device
-                    .destroy_semaphore(Some(frame.render_semaphore), None);
-                physical
-                    .device
-                    .destroy_semaphore(Some(frame.present_semaphore), None);
-                physical
-                    .device
-                    .destroy_fence(Some(frame.render_fence), None);
-                physical
-                    .device
-                    .destroy_command_pool(Some(frame.command_pool), None);
-                physical
-                    .device
-                    .destroy_buffer(Some(frame.camera_buffer.buffer), None);
-
-                    
-                
-                physical.allocator.dealloc(
-                    EruptMemoryDevice::wrap(&physical.device),
-                    frame.camera_buffer.allocation.take().unwrap(),
-                );
-            }
+    //Points bindings 3 to 5 of every global set at the scene's image based lighting
+    pub fn bind_environment(&self, physical: &Physical, lighting: &EnvironmentLighting) {
+        let image_infos = [
+            &lighting.irradiance,
+            &lighting.prefiltered,
+            &lighting.brdf_lut,
+        ]
+        .map(|texture| {
+            [vk::DescriptorImageInfoBuilder::new()
+                .sampler(lighting.sampler.handle)
+                .image_view(texture.image_view.handle)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+        });
+        for frame in &self.frames {
+            let writes: Vec<vk::WriteDescriptorSetBuilder> = image_infos
+                .iter()
+                .enumerate()
+                .map(|(i, image_info)| {
+                    vk::WriteDescriptorSetBuilder::new()
+                        .dst_binding(3 + i as u32)
+                        .dst_set(frame.global_descriptor)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(image_info)
+                })
+                .collect();
+            unsafe { physical.device.update_descriptor_sets(&writes, &[]) }
         }
     }
 }
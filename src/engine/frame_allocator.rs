@@ -0,0 +1,102 @@
+use std::ptr::NonNull;
+
+use bytemuck::Pod;
+use erupt::vk;
+use gpu_alloc::{MemoryPropertyFlags, UsageFlags};
+use gpu_alloc_erupt::EruptMemoryDevice;
+
+use super::{buffer::create_buffer, device::Physical, memory_stats::MemoryCategory, mesh::AllocatedBuffer};
+
+//Linear allocator over one persistently mapped upload buffer, each frame in flight has its own. Data that changes every
+//frame is written at increasing offsets aligned to min_uniform_buffer_offset_alignment and bound with dynamic uniform
+//buffer descriptors pointing at the whole buffer, the offsets go into cmd_bind_descriptor_sets.
+//reset hands everything back at once, after the frame's fence has signalled.
+pub struct FrameAllocator {
+    //the memory stays mapped until it is freed, which unmaps it
+    buffer: AllocatedBuffer,
+    mapped: NonNull<u8>,
+    size: u64,
+    alignment: u64,
+    //memory that isn't host coherent is flushed in whole atoms
+    coherent: bool,
+    atom_size: u64,
+    offset: u64,
+}
+
+impl FrameAllocator {
+    pub fn new(physical: &mut Physical, size: u64) -> Self {
+        let limits = unsafe {
+            physical
+                .instance
+                .get_physical_device_properties(physical.physical_device, None)
+        }
+        .limits;
+        let mut buffer = create_buffer(
+            physical,
+            size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            UsageFlags::UPLOAD,
+            MemoryCategory::Uniform,
+        );
+        let block = buffer.allocation.as_mut().unwrap();
+        let coherent = block.props().contains(MemoryPropertyFlags::HOST_COHERENT);
+        let mapped = unsafe { block.map(EruptMemoryDevice::wrap(&physical.device), 0, size as usize) }.unwrap();
+
+        FrameAllocator {
+            buffer,
+            mapped,
+            size,
+            alignment: limits.min_uniform_buffer_offset_alignment.max(1),
+            coherent,
+            atom_size: limits.non_coherent_atom_size.max(1),
+            offset: 0,
+        }
+    }
+
+    //For the descriptors, which use offset 0 and the size of what is pushed through them
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer.buffer
+    }
+
+    //Copies `value` into the buffer, returns the dynamic offset to bind it with
+    pub fn push<T: Pod>(&mut self, value: &T) -> u32 {
+        let bytes = bytemuck::bytes_of(value);
+        let offset = align_up(self.offset, self.alignment);
+        assert!(
+            offset + bytes.len() as u64 <= self.size,
+            "frame allocator is out of space, it has {} bytes",
+            self.size
+        );
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                self.mapped.as_ptr().add(offset as usize),
+                bytes.len(),
+            );
+        }
+        self.offset = offset + bytes.len() as u64;
+        offset as u32
+    }
+
+    //Makes the writes since the last reset visible to the device, call before the frame is submitted
+    pub fn flush(&self, physical: &Physical) {
+        if self.coherent || self.offset == 0 {
+            return;
+        }
+        let block = self.buffer.allocation.as_ref().unwrap();
+        let range = vk::MappedMemoryRangeBuilder::new()
+            .memory(*block.memory())
+            .offset(block.offset())
+            .size(align_up(self.offset, self.atom_size).min(block.size()));
+        unsafe { physical.device.flush_mapped_memory_ranges(&[range]) }.unwrap();
+    }
+
+    //Only once the frame's fence has signalled, the GPU may still be reading the previous data otherwise
+    pub fn reset(&mut self) {
+        self.offset = 0;
+    }
+}
+
+fn align_up(offset: u64, alignment: u64) -> u64 {
+    offset.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,294 @@
+use std::{collections::HashSet, path::Path, rc::Rc};
+
+use super::{
+    device::Physical,
+    mesh::{self, Mesh, Vertex},
+    pipeline::PipelineStruct,
+    scene::{unique_name, AlphaMode, Material, MaterialParams, Scene},
+    texture::Texture,
+};
+
+extern crate nalgebra as na;
+
+//Loads a .gltf (with external or embedded buffers) or .glb file into the scene.
+//Every image becomes a texture, every material an engine material, every primitive a mesh,
+//and the node hierarchy of the default scene is recreated under one root node placed at `transform`.
+//Returns the index of that root node.
+pub fn load_gltf_scene(
+    path: &Path,
+    physical: &mut Physical,
+    scene: &mut Scene,
+    pipeline: &Rc<PipelineStruct>,
+    transform: impl Into<na::Matrix4<f32>>,
+) -> usize {
+    let (document, buffers, images) = gltf::import(path).expect("Failed to load glTF file");
+
+    let file_name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    //images holding colors need an srgb format, everything else is linear data
+    let mut srgb_images = HashSet::new();
+    for material in document.materials() {
+        if let Some(info) = material.pbr_metallic_roughness().base_color_texture() {
+            srgb_images.insert(info.texture().source().index());
+        }
+        if let Some(info) = material.emissive_texture() {
+            srgb_images.insert(info.texture().source().index());
+        }
+    }
+
+    let texture_names: Vec<String> = document
+        .images()
+        .zip(images.iter())
+        .map(|(image, data)| {
+            let name = match image.name() {
+                Some(name) => name.to_string(),
+                None => format!("image{}", image.index()),
+            };
+            let name = unique_name(&scene.textures, &file_name, &name);
+            let pixels = rgba8_pixels(data);
+            let texture = Texture::from_rgba8(
+                &pixels,
+                data.width,
+                data.height,
+                srgb_images.contains(&image.index()),
+                physical,
+            );
+            scene.textures.insert(name.clone(), texture);
+            name
+        })
+        .collect();
+
+    let material_names: Vec<String> = document
+        .materials()
+        .map(|material| {
+            let name = match material.name() {
+                Some(name) => name.to_string(),
+                None => format!("material{}", material.index().unwrap_or(0)),
+            };
+            let name = unique_name(&scene.materials, &file_name, &name);
+            let texture_name = |texture: gltf::Texture| texture_names[texture.source().index()].clone();
+
+            let pbr = material.pbr_metallic_roughness();
+            let base_color = pbr.base_color_factor();
+            let params = MaterialParams {
+                diffuse: [base_color[0], base_color[1], base_color[2]],
+                opacity: base_color[3],
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                emissive: material.emissive_factor(),
+                alpha_mode: match material.alpha_mode() {
+                    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    //0.5 is glTF's default when the material leaves the cutoff out
+                    gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+                    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                },
+                diffuse_texture: pbr.base_color_texture().map(|info| texture_name(info.texture())),
+                metallic_roughness_texture: pbr
+                    .metallic_roughness_texture()
+                    .map(|info| texture_name(info.texture())),
+                normal_texture: material.normal_texture().map(|info| texture_name(info.texture())),
+                occlusion_texture: material
+                    .occlusion_texture()
+                    .map(|info| texture_name(info.texture())),
+                emissive_texture: material
+                    .emissive_texture()
+                    .map(|info| texture_name(info.texture())),
+                ..Default::default()
+            };
+
+            scene.materials.insert(
+                name.clone(),
+                Material {
+                    pipeline: Rc::clone(pipeline),
+                    params,
+                    descriptor_set: None,
+                    uniform_buffer: None,
+                },
+            );
+            name
+        })
+        .collect();
+
+    //primitives without a material use the glTF default material
+    let mut default_material: Option<String> = None;
+
+    //(mesh name, material name) for every primitive of every glTF mesh
+    let mut mesh_primitives: Vec<Vec<(String, String)>> = Vec::new();
+    for gltf_mesh in document.meshes() {
+        let mesh_name = match gltf_mesh.name() {
+            Some(name) => name.to_string(),
+            None => format!("mesh{}", gltf_mesh.index()),
+        };
+        let mut primitives = Vec::new();
+
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                println!("skipping non triangle primitive in {}", mesh_name);
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let normals: Vec<[f32; 3]> = reader
+                .read_normals()
+                .map(|normals| normals.collect())
+                .unwrap_or_default();
+            let uvs: Vec<[f32; 2]> = reader
+                .read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().collect())
+                .unwrap_or_default();
+            let tangents: Vec<[f32; 4]> = reader
+                .read_tangents()
+                .map(|tangents| tangents.collect())
+                .unwrap_or_default();
+            let colors: Vec<[f32; 3]> = reader
+                .read_colors(0)
+                .map(|colors| colors.into_rgb_f32().collect())
+                .unwrap_or_default();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let mut vertices: Vec<Vertex> = positions
+                .iter()
+                .enumerate()
+                .map(|(i, pos)| {
+                    let normal = normals.get(i).copied().unwrap_or([0.0, 0.0, 0.0]);
+                    Vertex {
+                        pos: *pos,
+                        normal,
+                        color: colors.get(i).copied().unwrap_or([1.0, 1.0, 1.0]),
+                        uv: uvs.get(i).copied().unwrap_or([0.0, 0.0]),
+                        tangent: tangents.get(i).copied().unwrap_or([0.0, 0.0, 0.0, 0.0]),
+                    }
+                })
+                .collect();
+            if tangents.is_empty() && !normals.is_empty() && !uvs.is_empty() {
+                mesh::compute_tangents(&mut vertices, &indices);
+            }
+
+            let name = if gltf_mesh.primitives().len() == 1 {
+                mesh_name.clone()
+            } else {
+                format!("{}/{}", mesh_name, primitive.index())
+            };
+            let name = unique_name(&scene.meshes, &file_name, &name);
+            let mesh = Mesh::from_indexed(vertices, indices, physical, &mut scene.mesh_pool);
+            scene.meshes.insert(name.clone(), mesh);
+
+            let material_name = match primitive.material().index() {
+                Some(index) => material_names[index].clone(),
+                None => default_material
+                    .get_or_insert_with(|| {
+                        let name = unique_name(&scene.materials, &file_name, "default");
+                        scene.materials.insert(
+                            name.clone(),
+                            Material {
+                                pipeline: Rc::clone(pipeline),
+                                params: MaterialParams::default(),
+                                descriptor_set: None,
+                                uniform_buffer: None,
+                            },
+                        );
+                        name
+                    })
+                    .clone(),
+            };
+            primitives.push((name, material_name));
+        }
+        mesh_primitives.push(primitives);
+    }
+
+    let root = scene.add_node(None, transform.into());
+    let gltf_scene = match document.default_scene() {
+        Some(gltf_scene) => Some(gltf_scene),
+        None => document.scenes().next(),
+    };
+    if let Some(gltf_scene) = gltf_scene {
+        for node in gltf_scene.nodes() {
+            add_node(&node, root, &mesh_primitives, scene);
+        }
+    }
+
+    println!(
+        "path {:?}, {} meshes, {} materials, {} textures",
+        path,
+        mesh_primitives.iter().map(|primitives| primitives.len()).sum::<usize>(),
+        material_names.len(),
+        texture_names.len()
+    );
+
+    root
+}
+
+fn add_node(
+    node: &gltf::Node,
+    parent: usize,
+    mesh_primitives: &[Vec<(String, String)>],
+    scene: &mut Scene,
+) {
+    let index = scene.add_node(Some(parent), na::Matrix4::from(node.transform().matrix()));
+
+    if let Some(gltf_mesh) = node.mesh() {
+        let world = scene.world_transform(index);
+        for (mesh_name, material_name) in &mesh_primitives[gltf_mesh.index()] {
+            scene.add_render_object(mesh_name, material_name, world);
+            let object = scene.objects.len() - 1;
+            scene.nodes[index].objects.push(object);
+        }
+    }
+
+    for child in node.children() {
+        add_node(&child, index, mesh_primitives, scene);
+    }
+}
+
+//Textures are always uploaded as 4 channel 8 bit images
+fn rgba8_pixels(data: &gltf::image::Data) -> Vec<u8> {
+    use gltf::image::Format;
+
+    //(channels, bytes per channel, stored as bgr)
+    let (channels, channel_size, bgr) = match data.format {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::B8G8R8 => (3, 1, true),
+        Format::B8G8R8A8 => (4, 1, true),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false),
+    };
+    if channels == 4 && channel_size == 1 && !bgr {
+        return data.pixels.clone();
+    }
+
+    let pixel_count = (data.width * data.height) as usize;
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+    for pixel in data.pixels.chunks_exact(channels * channel_size).take(pixel_count) {
+        //16 bit channels are little endian, keep the high byte
+        let channel = |c: usize| pixel[c * channel_size + channel_size - 1];
+        let mut rgba = [0, 0, 0, 255];
+        for (c, value) in rgba.iter_mut().enumerate().take(channels) {
+            *value = channel(c);
+        }
+        if channels == 1 {
+            //single channel images are grayscale
+            rgba[1] = rgba[0];
+            rgba[2] = rgba[0];
+        }
+        if bgr {
+            rgba.swap(0, 2);
+        }
+        pixels.extend_from_slice(&rgba);
+    }
+    pixels
+}
@@ -0,0 +1,472 @@
+use std::{collections::HashMap, mem::size_of, rc::Rc};
+
+use bytemuck_derive::{Pod, Zeroable};
+use erupt::vk;
+use gpu_alloc::UsageFlags;
+use vk_shader_macros::include_glsl;
+
+use super::{
+    buffer::{create_buffer, create_buffer_with_data},
+    compute::{ComputeKernel, KernelBinding},
+    deletion::DeletionQueue,
+    descriptors::{DescriptorSetLayout, Descriptors},
+    device::Physical,
+    handles::DescriptorPool,
+    memory_stats::MemoryCategory,
+    mesh::{AllocatedBuffer, Bounds, Mesh},
+    pipeline::{PipelineStruct, OPAQUE_PIPELINE},
+    render_queue::BindStats,
+    renderpass::RenderPass,
+    scene::{Material, Scene},
+};
+
+extern crate nalgebra as na;
+
+const CULL_COMP: &[u32] = include_glsl!("src/shaders/cull.comp", kind: comp);
+const CULL_GROUP_SIZE: u32 = 64;
+
+//std430 layout of ObjectData in cull.comp and gpu-driven.vert
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+struct GPUObjectData {
+    model: na::Matrix4<f32>,
+    //bounding sphere in mesh space, w is the radius
+    sphere: [f32; 4],
+    //first index, index count, vertex offset, batch
+    draw: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+struct CullPushConstants {
+    planes: [[f32; 4]; 6],
+    //x is the object count
+    object_count: [u32; 4],
+}
+
+//Objects of one material and mesh pool chunk while a rebuild sorts them into batches
+struct BatchObjects<'a> {
+    descriptor_set: vk::DescriptorSet,
+    vertex_buffer: vk::Buffer,
+    index_buffer: vk::Buffer,
+    objects: Vec<(&'a Mesh, [f32; 4], na::Matrix4<f32>)>,
+}
+
+//Objects sharing a material and pool buffers, drawn with one indirect count call
+struct Batch {
+    descriptor_set: vk::DescriptorSet,
+    vertex_buffer: vk::Buffer,
+    index_buffer: vk::Buffer,
+    first_command: u32,
+    max_count: u32,
+}
+
+//Indirect commands and their counts, written by the culling pass of each frame in flight
+struct FrameCommands {
+    commands: AllocatedBuffer,
+    counts: AllocatedBuffer,
+}
+
+//Everything that depends on the scene's content, rebuilt together. The descriptor sets get a pool of their own,
+//since the sets of the previous build may still be in use by a frame in flight.
+struct SceneBuffers {
+    #[allow(dead_code)]
+    descriptor_pool: DescriptorPool,
+    object_set: vk::DescriptorSet,
+    cull_sets: Vec<vk::DescriptorSet>,
+    objects: AllocatedBuffer,
+    //first command of every batch
+    batch_starts: AllocatedBuffer,
+    frames: Vec<FrameCommands>,
+    batches: Vec<Batch>,
+    object_count: u32,
+}
+
+//GPU driven drawing of the opaque objects: object transforms, bounds and mesh pool ranges live in a storage buffer,
+//a compute pass culls them against the camera frustum and writes the indirect commands, and each material is drawn
+//with a single cmd_draw_indexed_indirect_count per mesh pool chunk.
+//Only opaque pbr materials are drawn here, the shaders of the other materials read the model matrix from push constants.
+//Those and the transparent objects, which need sorting, stay on the CPU path.
+pub struct GpuScene {
+    pipeline: PipelineStruct,
+    //the pipeline of the materials the gpu driven pipeline stands in for
+    pbr: Rc<PipelineStruct>,
+    cull: ComputeKernel,
+    object_set_layout: DescriptorSetLayout,
+    frame_count: usize,
+    buffers: Option<SceneBuffers>,
+}
+
+impl GpuScene {
+    pub fn new(
+        physical: &Physical,
+        render_pass: &RenderPass,
+        descs: &Descriptors,
+        pbr: Rc<PipelineStruct>,
+        frame_count: usize,
+    ) -> Self {
+        let bindings = [vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)];
+        let object_set_layout = DescriptorSetLayout::new(physical, &bindings);
+
+        let pipeline = PipelineStruct::gpu_driven(physical, render_pass, descs, object_set_layout.layout);
+        let cull = ComputeKernel::new(
+            physical,
+            CULL_COMP,
+            &[
+                KernelBinding::StorageBuffer,
+                KernelBinding::StorageBuffer,
+                KernelBinding::StorageBuffer,
+                KernelBinding::StorageBuffer,
+            ],
+            size_of::<CullPushConstants>() as u32,
+        );
+
+        GpuScene {
+            pipeline,
+            pbr,
+            cull,
+            object_set_layout,
+            frame_count,
+            buffers: None,
+        }
+    }
+
+    //Whether objects with `material` are drawn by the GpuScene instead of the CPU path
+    pub fn draws(&self, material: &Material) -> bool {
+        Rc::ptr_eq(&material.pipeline, &self.pbr) && !material.transparent()
+    }
+
+    //Uploads the scene's opaque pbr objects. Objects whose mesh is loading use the placeholder.
+    //Has to be called again when meshes or objects change, the previous build is destroyed once the frames
+    //in flight are done with it.
+    pub fn rebuild(
+        &mut self,
+        physical: &mut Physical,
+        scene: &Scene,
+        deletion: &mut DeletionQueue,
+        framenumber: i64,
+    ) {
+        if let Some(buffers) = self.buffers.take() {
+            deletion.release(framenumber, buffers);
+        }
+
+        //objects grouped by material and pool chunk, so each batch is a contiguous range of commands
+        let mut batch_objects: Vec<BatchObjects> = Vec::new();
+        let mut batch_ids: HashMap<(&str, usize, usize), usize> = HashMap::new();
+        let mut spheres: HashMap<&str, [f32; 4]> = HashMap::new();
+        for (mesh_name, material_name, transform) in &scene.objects {
+            let material = match scene.materials.get(material_name) {
+                Some(material) if self.draws(material) => material,
+                _ => continue,
+            };
+            let descriptor_set = match scene.material_descriptor(material) {
+                Some(descriptor_set) => descriptor_set,
+                None => continue,
+            };
+            let mesh = match scene.mesh(mesh_name) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let sphere = *spheres
+                .entry(mesh_name.as_str())
+                .or_insert_with(|| bounding_sphere(&Bounds::from_vertices(&mesh.verticies)));
+            let key = (material_name.as_str(), mesh.vertex_range.chunk, mesh.index_range.chunk);
+            let batch = *batch_ids.entry(key).or_insert_with(|| {
+                batch_objects.push(BatchObjects {
+                    descriptor_set,
+                    vertex_buffer: scene.mesh_pool.vertex_buffer(mesh.vertex_range.chunk),
+                    index_buffer: scene.mesh_pool.index_buffer(mesh.index_range.chunk),
+                    objects: Vec::new(),
+                });
+                batch_objects.len() - 1
+            });
+            batch_objects[batch].objects.push((mesh, sphere, *transform));
+        }
+
+        let mut objects = Vec::new();
+        let mut batches = Vec::new();
+        for (batch, members) in batch_objects.iter().enumerate() {
+            batches.push(Batch {
+                descriptor_set: members.descriptor_set,
+                vertex_buffer: members.vertex_buffer,
+                index_buffer: members.index_buffer,
+                first_command: objects.len() as u32,
+                max_count: members.objects.len() as u32,
+            });
+            for (mesh, sphere, transform) in &members.objects {
+                objects.push(GPUObjectData {
+                    model: *transform,
+                    sphere: *sphere,
+                    draw: [
+                        mesh.index_range.offset,
+                        mesh.index_range.count,
+                        mesh.vertex_range.offset,
+                        batch as u32,
+                    ],
+                });
+            }
+        }
+        if objects.is_empty() {
+            return;
+        }
+
+        let mut sizes = self.cull.pool_sizes(self.frame_count as u32);
+        sizes.push(
+            vk::DescriptorPoolSizeBuilder::new()
+                ._type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1),
+        );
+        let pool_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .max_sets(self.frame_count as u32 + 1)
+            .pool_sizes(&sizes);
+        let descriptor_pool = unsafe {
+            physical
+                .device
+                .create_descriptor_pool(&pool_info, None, None)
+        }
+        .unwrap();
+        let descriptor_pool = DescriptorPool::new(&physical.device, descriptor_pool);
+        let set_layouts = [self.object_set_layout.layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(descriptor_pool.handle)
+            .set_layouts(&set_layouts);
+        let object_set = unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0];
+        let cull_sets = (0..self.frame_count)
+            .map(|_| self.cull.allocate_set(physical, descriptor_pool.handle))
+            .collect();
+
+        let batch_starts: Vec<u32> = batches.iter().map(|batch| batch.first_command).collect();
+        let buffers = SceneBuffers {
+            descriptor_pool,
+            object_set,
+            cull_sets,
+            objects: create_buffer_with_data(
+                physical,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                bytemuck::cast_slice(&objects),
+                MemoryCategory::Storage,
+            ),
+            batch_starts: create_buffer_with_data(
+                physical,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                bytemuck::cast_slice(&batch_starts),
+                MemoryCategory::Storage,
+            ),
+            frames: (0..self.frame_count)
+                .map(|_| FrameCommands {
+                    commands: create_buffer(
+                        physical,
+                        (objects.len() * size_of::<vk::DrawIndexedIndirectCommand>()) as u64,
+                        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+                        UsageFlags::FAST_DEVICE_ACCESS,
+                        MemoryCategory::Storage,
+                    ),
+                    counts: create_buffer(
+                        physical,
+                        (batches.len() * size_of::<u32>()) as u64,
+                        vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::INDIRECT_BUFFER
+                            | vk::BufferUsageFlags::TRANSFER_DST,
+                        UsageFlags::FAST_DEVICE_ACCESS,
+                        MemoryCategory::Storage,
+                    ),
+                })
+                .collect(),
+            batches,
+            object_count: objects.len() as u32,
+        };
+
+        let objects_size = (objects.len() * size_of::<GPUObjectData>()) as u64;
+        let buffer_info = [vk::DescriptorBufferInfoBuilder::new()
+            .buffer(buffers.objects.buffer)
+            .offset(0)
+            .range(objects_size)];
+        let write = vk::WriteDescriptorSetBuilder::new()
+            .dst_set(buffers.object_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_info);
+        unsafe { physical.device.update_descriptor_sets(&[write], &[]) }
+
+        for (set, frame) in buffers.cull_sets.iter().zip(buffers.frames.iter()) {
+            self.cull
+                .write_buffer(physical, *set, 0, buffers.objects.buffer, 0, objects_size);
+            self.cull
+                .write_buffer(physical, *set, 1, buffers.batch_starts.buffer, 0, vk::WHOLE_SIZE);
+            self.cull
+                .write_buffer(physical, *set, 2, frame.commands.buffer, 0, vk::WHOLE_SIZE);
+            self.cull
+                .write_buffer(physical, *set, 3, frame.counts.buffer, 0, vk::WHOLE_SIZE);
+        }
+
+        println!(
+            "gpu driven scene: {} objects in {} batches",
+            buffers.object_count,
+            buffers.batches.len()
+        );
+        self.buffers = Some(buffers);
+    }
+
+    //Records the culling pass for `frame` outside of a render pass. The commands it writes are ready for draw
+    //from the draw indirect stage on.
+    pub fn cull(&self, physical: &Physical, cmd: vk::CommandBuffer, frame: usize, viewproj: &na::Matrix4<f32>) {
+        let buffers = match &self.buffers {
+            Some(buffers) => buffers,
+            None => return,
+        };
+        let counts = buffers.frames[frame].counts.buffer;
+        let constants = CullPushConstants {
+            planes: frustum_planes(viewproj),
+            object_count: [buffers.object_count, 0, 0, 0],
+        };
+        unsafe {
+            physical.device.cmd_fill_buffer(cmd, counts, 0, vk::WHOLE_SIZE, 0);
+            let cleared = vk::BufferMemoryBarrierBuilder::new()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(counts)
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+            physical.device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                None,
+                &[],
+                &[cleared],
+                &[],
+            );
+        }
+
+        self.cull.dispatch_linear(
+            physical,
+            cmd,
+            buffers.cull_sets[frame],
+            Some(&constants),
+            buffers.object_count,
+            CULL_GROUP_SIZE,
+        );
+
+        let written = vk::MemoryBarrierBuilder::new()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ);
+        unsafe {
+            physical.device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                None,
+                &[written],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    //Draws what the culling pass of `frame` left visible into the current render pass
+    pub fn draw(
+        &self,
+        physical: &Physical,
+        cmd: vk::CommandBuffer,
+        frame: usize,
+        global_descriptor: vk::DescriptorSet,
+        global_offsets: &[u32],
+    ) -> BindStats {
+        let mut stats = BindStats::default();
+        let buffers = match &self.buffers {
+            Some(buffers) => buffers,
+            None => return stats,
+        };
+        let layout = self.pipeline.pipeline_layout;
+        let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        unsafe {
+            physical.device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipelines[OPAQUE_PIPELINE],
+            );
+            physical.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                0,
+                &[global_descriptor],
+                global_offsets,
+            );
+            physical.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                2,
+                &[buffers.object_set],
+                &[],
+            );
+            stats.pipeline_binds += 1;
+            stats.descriptor_binds += 2;
+
+            let frame = &buffers.frames[frame];
+            let mut last_vertex_buffer: Option<vk::Buffer> = None;
+            let mut last_index_buffer: Option<vk::Buffer> = None;
+            for (index, batch) in buffers.batches.iter().enumerate() {
+                if last_vertex_buffer != Some(batch.vertex_buffer) {
+                    physical
+                        .device
+                        .cmd_bind_vertex_buffers(cmd, 0, &[batch.vertex_buffer], &[0]);
+                    last_vertex_buffer = Some(batch.vertex_buffer);
+                    stats.vertex_buffer_binds += 1;
+                }
+                if last_index_buffer != Some(batch.index_buffer) {
+                    physical
+                        .device
+                        .cmd_bind_index_buffer(cmd, batch.index_buffer, 0, vk::IndexType::UINT32);
+                    last_index_buffer = Some(batch.index_buffer);
+                    stats.index_buffer_binds += 1;
+                }
+                physical.device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    layout,
+                    1,
+                    &[batch.descriptor_set],
+                    &[],
+                );
+                physical.device.cmd_draw_indexed_indirect_count(
+                    cmd,
+                    frame.commands.buffer,
+                    (batch.first_command * stride) as u64,
+                    frame.counts.buffer,
+                    (index * size_of::<u32>()) as u64,
+                    batch.max_count,
+                    stride,
+                );
+                stats.descriptor_binds += 1;
+                stats.draws += 1;
+            }
+        }
+        stats
+    }
+}
+
+fn bounding_sphere(bounds: &Bounds) -> [f32; 4] {
+    let min = na::Vector3::from(bounds.min);
+    let max = na::Vector3::from(bounds.max);
+    let center = (min + max) * 0.5;
+    [center.x, center.y, center.z, (max - min).norm() * 0.5]
+}
+
+//Planes of the clip volume of `viewproj` with normals pointing inside, normalized so the distance is in world units
+fn frustum_planes(viewproj: &na::Matrix4<f32>) -> [[f32; 4]; 6] {
+    let row = |i: usize| viewproj.row(i).transpose();
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+    let planes = [w + x, w - x, w + y, w - y, w + z, w - z];
+    planes.map(|plane| {
+        let plane = plane / plane.xyz().norm();
+        [plane.x, plane.y, plane.z, plane.w]
+    })
+}
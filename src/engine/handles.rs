@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use erupt::vk;
+
+use super::device::{Device, ResourceKind};
+
+//Vulkan objects without memory of their own, destroyed when dropped like AllocatedBuffer.
+//The GPU must be done with them by then.
+macro_rules! owned_handle {
+    ($name:ident, $handle:ty, $destroy:ident) => {
+        pub struct $name {
+            pub handle: $handle,
+            device: Arc<Device>,
+        }
+
+        impl $name {
+            pub fn new(device: &Arc<Device>, handle: $handle) -> Self {
+                device.created(ResourceKind::$name);
+                $name {
+                    handle,
+                    device: Arc::clone(device),
+                }
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                unsafe {
+                    self.device.$destroy(Some(self.handle), None);
+                }
+                self.device.released(ResourceKind::$name);
+            }
+        }
+    };
+}
+
+owned_handle!(ImageView, vk::ImageView, destroy_image_view);
+owned_handle!(Sampler, vk::Sampler, destroy_sampler);
+owned_handle!(Framebuffer, vk::Framebuffer, destroy_framebuffer);
+owned_handle!(RenderPass, vk::RenderPass, destroy_render_pass);
+//sets allocated from it are freed with it
+owned_handle!(DescriptorPool, vk::DescriptorPool, destroy_descriptor_pool);
+owned_handle!(Semaphore, vk::Semaphore, destroy_semaphore);
+owned_handle!(Fence, vk::Fence, destroy_fence);
+//command buffers allocated from it are freed with it
+owned_handle!(CommandPool, vk::CommandPool, destroy_command_pool);
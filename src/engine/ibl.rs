@@ -0,0 +1,568 @@
+use std::{
+    error::Error,
+    fs,
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use bytemuck_derive::{Pod, Zeroable};
+use erupt::vk;
+use gpu_alloc::UsageFlags;
+use gpu_alloc_erupt::EruptMemoryDevice;
+use serde::{Deserialize, Serialize};
+use vk_shader_macros::include_glsl;
+
+use super::{
+    buffer::create_buffer,
+    descriptors::DescriptorSetLayout,
+    device::Physical,
+    handles::{DescriptorPool, Sampler},
+    memory_stats::MemoryCategory,
+    mesh_cache::{fnv1a, hash_file, modified_nanos},
+    pipeline::PipelineStruct,
+    texture::{color_subresource_range, create_image, create_image_view, f32_to_f16, transition_image, Texture},
+};
+
+const IRRADIANCE_COMP: &[u32] = include_glsl!("src/shaders/ibl-irradiance.comp", kind: comp);
+const PREFILTER_COMP: &[u32] = include_glsl!("src/shaders/ibl-prefilter.comp", kind: comp);
+const BRDF_COMP: &[u32] = include_glsl!("src/shaders/ibl-brdf.comp", kind: comp);
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+//the shaders pick the mip from roughness, the last level is fully rough
+const PREFILTERED_MIPS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const PREFILTER_SAMPLES: u32 = 512;
+const BRDF_SAMPLES: u32 = 1024;
+//storage support for this format is required everywhere
+const IBL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const TEXEL_SIZE: u64 = 8;
+//matches local_size in the ibl shaders
+const GROUP_SIZE: u32 = 8;
+
+//Bump whenever the sizes above or the shaders change, old cache files are then recomputed
+const IBL_CACHE_VERSION: u32 = 1;
+const IBL_CACHE_MAGIC: [u8; 4] = *b"RIBL";
+
+//Push constants of the ibl shaders
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+struct IblPushConstants {
+    //x output size, y roughness, z sample count
+    params: [f32; 4],
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IblHeader {
+    magic: [u8; 4],
+    version: u32,
+    source_modified: u64,
+    source_hash: u64,
+    checksum: u64,
+}
+
+//Raw IBL_FORMAT texels, the prefiltered map one entry per mip level
+#[derive(Serialize, Deserialize, Debug)]
+struct IblData {
+    irradiance: Vec<u8>,
+    prefiltered: Vec<Vec<u8>>,
+    brdf_lut: Vec<u8>,
+}
+
+//Image based lighting precomputed from an environment cubemap: diffuse irradiance, specular radiance prefiltered
+//for increasing roughness along the mip chain, and the split sum BRDF lookup table. Bound in the global set.
+pub struct EnvironmentLighting {
+    pub irradiance: Texture,
+    pub prefiltered: Texture,
+    pub brdf_lut: Texture,
+    pub sampler: Sampler,
+}
+
+impl EnvironmentLighting {
+    //`source` is the file or cube face directory `environment` was loaded from, the results are cached next to it
+    pub fn new(physical: &mut Physical, environment: &Texture, source: &Path) -> Self {
+        let cache = cache_path(source);
+        if let Some(data) = read_cache(&cache, source) {
+            println!("environment lighting from {:?}", cache);
+            return Self::from_data(physical, &data);
+        }
+
+        let (lighting, data) = Self::compute(physical, environment, true);
+        if let Err(e) = write_cache(&cache, source, &data.unwrap()) {
+            println!("failed to write environment lighting cache {:?}: {}", cache, e);
+        }
+        lighting
+    }
+
+    //Lighting from a constant color in every direction, for scenes without an environment map
+    pub fn uniform(physical: &mut Physical, color: [f32; 3]) -> Self {
+        let [r, g, b] = color.map(f32_to_f16);
+        let texels = [[r, g, b, f32_to_f16(1.0)]; 6];
+        let environment = Texture::from_levels(
+            &[bytemuck::cast_slice(&texels)],
+            IBL_FORMAT,
+            1,
+            1,
+            6,
+            physical,
+        );
+        let (lighting, _) = Self::compute(physical, &environment, false);
+        lighting
+    }
+
+    //Runs the three compute passes, reading the results back if `read_back`
+    fn compute(physical: &mut Physical, environment: &Texture, read_back: bool) -> (Self, Option<IblData>) {
+        let sampler = create_sampler(physical);
+
+        let storage_usage = vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC;
+        let irradiance = create_texture(physical, IRRADIANCE_SIZE, 1, 6, storage_usage);
+        let prefiltered = create_texture(physical, PREFILTERED_SIZE, PREFILTERED_MIPS, 6, storage_usage);
+        let brdf_lut = create_texture(physical, BRDF_LUT_SIZE, 1, 1, storage_usage);
+
+        //binding 0 the environment, binding 1 the output
+        let bindings = [
+            vk::DescriptorSetLayoutBindingBuilder::new()
+                .binding(0)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+            vk::DescriptorSetLayoutBindingBuilder::new()
+                .binding(1)
+                .descriptor_count(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE),
+        ];
+        let set_layout = DescriptorSetLayout::new(physical, &bindings);
+
+        let set_count = 2 + PREFILTERED_MIPS;
+        let sizes = [
+            vk::DescriptorPoolSizeBuilder::new()
+                ._type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(set_count),
+            vk::DescriptorPoolSizeBuilder::new()
+                ._type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(set_count),
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .max_sets(set_count)
+            .pool_sizes(&sizes);
+        let descriptor_pool = unsafe {
+            physical
+                .device
+                .create_descriptor_pool(&pool_info, None, None)
+        }
+        .unwrap();
+        let descriptor_pool = DescriptorPool::new(&physical.device, descriptor_pool);
+
+        //storage views see the cube faces as array layers, one view per written mip level
+        let mut storage_views = vec![create_image_view(
+            physical,
+            irradiance.image.image,
+            IBL_FORMAT,
+            vk::ImageViewType::_2D_ARRAY,
+            color_subresource_range(1, 6),
+        )];
+        for mip in 0..PREFILTERED_MIPS {
+            storage_views.push(create_image_view(
+                physical,
+                prefiltered.image.image,
+                IBL_FORMAT,
+                vk::ImageViewType::_2D_ARRAY,
+                vk::ImageSubresourceRange {
+                    base_mip_level: mip,
+                    ..color_subresource_range(1, 6)
+                },
+            ));
+        }
+
+        //the brdf lut's sampled view is also its storage view
+        let descriptor_sets: Vec<vk::DescriptorSet> = storage_views
+            .iter()
+            .chain(std::iter::once(&brdf_lut.image_view))
+            .map(|view| {
+                let set_layouts = [set_layout.layout];
+                let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+                    .descriptor_pool(descriptor_pool.handle)
+                    .set_layouts(&set_layouts);
+                let set = unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0];
+                let environment_info = [vk::DescriptorImageInfoBuilder::new()
+                    .sampler(sampler.handle)
+                    .image_view(environment.image_view.handle)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+                let output_info = [vk::DescriptorImageInfoBuilder::new()
+                    .image_view(view.handle)
+                    .image_layout(vk::ImageLayout::GENERAL)];
+                let writes = [
+                    vk::WriteDescriptorSetBuilder::new()
+                        .dst_set(set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&environment_info),
+                    vk::WriteDescriptorSetBuilder::new()
+                        .dst_set(set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&output_info),
+                ];
+                unsafe { physical.device.update_descriptor_sets(&writes, &[]) }
+                set
+            })
+            .collect();
+
+        let push_size = size_of::<IblPushConstants>() as u32;
+        let set_layouts = [set_layout.layout];
+        let pipelines = [
+            PipelineStruct::compute(physical, IRRADIANCE_COMP, &set_layouts, push_size),
+            PipelineStruct::compute(physical, PREFILTER_COMP, &set_layouts, push_size),
+            PipelineStruct::compute(physical, BRDF_COMP, &set_layouts, push_size),
+        ];
+
+        //(pipeline, descriptor set, output size, layers, roughness, sample count) of every dispatch
+        let mut dispatches = vec![(0, 0, IRRADIANCE_SIZE, 6, 0.0, 0)];
+        for mip in 0..PREFILTERED_MIPS {
+            let roughness = mip as f32 / (PREFILTERED_MIPS - 1) as f32;
+            dispatches.push((1, 1 + mip as usize, PREFILTERED_SIZE >> mip, 6, roughness, PREFILTER_SAMPLES));
+        }
+        dispatches.push((2, descriptor_sets.len() - 1, BRDF_LUT_SIZE, 1, 0.0, BRDF_SAMPLES));
+
+        let readback_size = level_sizes().iter().sum::<u64>();
+        let mut readback = if read_back {
+            Some(create_buffer(
+                physical,
+                readback_size,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                UsageFlags::DOWNLOAD,
+                MemoryCategory::Staging,
+            ))
+        } else {
+            None
+        };
+
+        let outputs = [
+            (&irradiance, 1, 6),
+            (&prefiltered, PREFILTERED_MIPS, 6),
+            (&brdf_lut, 1, 1),
+        ];
+        super::upload::immediate_submit(physical, |cmd| unsafe {
+            for (texture, mips, layers) in &outputs {
+                transition_image(
+                    physical,
+                    cmd,
+                    texture.image.image,
+                    color_subresource_range(*mips, *layers),
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::GENERAL,
+                );
+            }
+
+            for (pipeline, set, size, layers, roughness, samples) in &dispatches {
+                let pipeline = &pipelines[*pipeline];
+                let constants = IblPushConstants {
+                    params: [*size as f32, *roughness, *samples as f32, 0.0],
+                };
+                physical.device.cmd_bind_pipeline(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.pipelines[0],
+                );
+                physical.device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.pipeline_layout,
+                    0,
+                    &[descriptor_sets[*set]],
+                    &[],
+                );
+                physical.device.cmd_push_constants(
+                    cmd,
+                    pipeline.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_size,
+                    &constants as *const IblPushConstants as *const std::ffi::c_void,
+                );
+                let groups = size.div_ceil(GROUP_SIZE);
+                physical.device.cmd_dispatch(cmd, groups, groups, *layers);
+            }
+
+            //copied out before they are sampled if they get cached
+            let layout = if readback.is_some() {
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+            } else {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            };
+            for (texture, mips, layers) in &outputs {
+                transition_image(
+                    physical,
+                    cmd,
+                    texture.image.image,
+                    color_subresource_range(*mips, *layers),
+                    vk::ImageLayout::GENERAL,
+                    layout,
+                );
+            }
+
+            if let Some(readback) = &readback {
+                let mut offset = 0;
+                for (texture, mips, layers) in &outputs {
+                    for mip in 0..*mips {
+                        let size = (texture.extent.width >> mip).max(1);
+                        let copy_region = vk::BufferImageCopyBuilder::new()
+                            .buffer_offset(offset)
+                            .buffer_row_length(0)
+                            .buffer_image_height(0)
+                            .image_subresource(vk::ImageSubresourceLayers {
+                                aspect_mask: vk::ImageAspectFlags::COLOR,
+                                mip_level: mip,
+                                base_array_layer: 0,
+                                layer_count: *layers,
+                            })
+                            .image_extent(vk::Extent3D {
+                                width: size,
+                                height: size,
+                                depth: 1,
+                            });
+                        physical.device.cmd_copy_image_to_buffer(
+                            cmd,
+                            texture.image.image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            readback.buffer,
+                            &[copy_region],
+                        );
+                        offset += size as u64 * size as u64 * *layers as u64 * TEXEL_SIZE;
+                    }
+                    transition_image(
+                        physical,
+                        cmd,
+                        texture.image.image,
+                        color_subresource_range(*mips, *layers),
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    );
+                }
+            }
+        });
+
+        let data = readback.as_mut().map(|readback| {
+            let mut levels: Vec<Vec<u8>> = level_sizes()
+                .iter()
+                .map(|size| vec![0; *size as usize])
+                .collect();
+            let mut offset = 0;
+            for level in levels.iter_mut() {
+                unsafe {
+                    readback
+                        .allocation
+                        .as_mut()
+                        .unwrap()
+                        .read_bytes(EruptMemoryDevice::wrap(&physical.device), offset, level)
+                        .unwrap();
+                }
+                offset += level.len() as u64;
+            }
+            let brdf_lut = levels.pop().unwrap();
+            let irradiance = levels.remove(0);
+            IblData {
+                irradiance,
+                prefiltered: levels,
+                brdf_lut,
+            }
+        });
+
+        //the readback buffer, storage views, descriptor pool, pipelines and set layout are released at the end
+        //of the scope
+        (
+            EnvironmentLighting {
+                irradiance,
+                prefiltered,
+                brdf_lut,
+                sampler,
+            },
+            data,
+        )
+    }
+
+    fn from_data(physical: &mut Physical, data: &IblData) -> Self {
+        let prefiltered_levels: Vec<&[u8]> = data.prefiltered.iter().map(|level| level.as_slice()).collect();
+        EnvironmentLighting {
+            irradiance: Texture::from_levels(
+                &[&data.irradiance],
+                IBL_FORMAT,
+                IRRADIANCE_SIZE,
+                IRRADIANCE_SIZE,
+                6,
+                physical,
+            ),
+            prefiltered: Texture::from_levels(
+                &prefiltered_levels,
+                IBL_FORMAT,
+                PREFILTERED_SIZE,
+                PREFILTERED_SIZE,
+                6,
+                physical,
+            ),
+            brdf_lut: Texture::from_levels(
+                &[&data.brdf_lut],
+                IBL_FORMAT,
+                BRDF_LUT_SIZE,
+                BRDF_LUT_SIZE,
+                1,
+                physical,
+            ),
+            sampler: create_sampler(physical),
+        }
+    }
+}
+
+//Byte sizes of the irradiance map, every prefiltered mip and the brdf lut, in that order
+fn level_sizes() -> Vec<u64> {
+    let cube_level = |size: u32| size as u64 * size as u64 * 6 * TEXEL_SIZE;
+    let mut sizes = vec![cube_level(IRRADIANCE_SIZE)];
+    sizes.extend((0..PREFILTERED_MIPS).map(|mip| cube_level(PREFILTERED_SIZE >> mip)));
+    sizes.push(BRDF_LUT_SIZE as u64 * BRDF_LUT_SIZE as u64 * TEXEL_SIZE);
+    sizes
+}
+
+fn create_sampler(physical: &Physical) -> Sampler {
+    let sampler_info = vk::SamplerCreateInfoBuilder::new()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .max_lod(vk::LOD_CLAMP_NONE);
+    Sampler::new(
+        &physical.device,
+        unsafe { physical.device.create_sampler(&sampler_info, None, None) }.unwrap(),
+    )
+}
+
+//Square IBL_FORMAT texture, a cube with 6 layers
+fn create_texture(physical: &mut Physical, size: u32, mips: u32, layers: u32, usage: vk::ImageUsageFlags) -> Texture {
+    let image_info = vk::ImageCreateInfoBuilder::new()
+        .image_type(vk::ImageType::_2D)
+        .format(IBL_FORMAT)
+        .extent(vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        })
+        .mip_levels(mips)
+        .array_layers(layers)
+        .samples(vk::SampleCountFlagBits::_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+        .flags(if layers == 6 {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        });
+    let image = create_image(physical, &image_info, MemoryCategory::Texture);
+    let view_type = if layers == 6 {
+        vk::ImageViewType::CUBE
+    } else {
+        vk::ImageViewType::_2D
+    };
+    let image_view = create_image_view(
+        physical,
+        image.image,
+        IBL_FORMAT,
+        view_type,
+        color_subresource_range(mips, layers),
+    );
+    Texture {
+        image_view,
+        image,
+        extent: vk::Extent2D {
+            width: size,
+            height: size,
+        },
+    }
+}
+
+//Like the mesh cache, e.g. assets/cache/sky.hdr.ibl
+fn cache_path(source: &Path) -> PathBuf {
+    let file_name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    source
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join("cache")
+        .join(format!("{}.ibl", file_name))
+}
+
+//A directory of cube faces hashes all of its files
+fn source_hash(source: &Path) -> Option<u64> {
+    if !source.is_dir() {
+        return hash_file(source);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(source)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    let hashes: Vec<u8> = files
+        .iter()
+        .filter_map(|file| hash_file(file))
+        .flat_map(|hash| hash.to_le_bytes())
+        .collect();
+    Some(fnv1a(&hashes))
+}
+
+fn read_cache(cache: &Path, source: &Path) -> Option<IblData> {
+    let bytes = fs::read(cache).ok()?;
+    let header: IblHeader = bincode::deserialize(&bytes).ok()?;
+    if header.magic != IBL_CACHE_MAGIC || header.version != IBL_CACHE_VERSION {
+        return None;
+    }
+    if modified_nanos(source) != Some(header.source_modified) && source_hash(source) != Some(header.source_hash) {
+        println!("environment lighting cache {:?} is out of date", cache);
+        return None;
+    }
+
+    let header_size = bincode::serialized_size(&header).ok()? as usize;
+    let payload = bytes.get(header_size..)?;
+    if fnv1a(payload) != header.checksum {
+        println!("environment lighting cache {:?} is corrupted", cache);
+        return None;
+    }
+    let data: IblData = bincode::deserialize(payload).ok()?;
+
+    //a cache written with different sizes would make the uploads read out of bounds
+    let sizes = level_sizes();
+    let stored: Vec<u64> = std::iter::once(&data.irradiance)
+        .chain(data.prefiltered.iter())
+        .chain(std::iter::once(&data.brdf_lut))
+        .map(|level| level.len() as u64)
+        .collect();
+    if stored != sizes {
+        return None;
+    }
+    Some(data)
+}
+
+fn write_cache(cache: &Path, source: &Path, data: &IblData) -> Result<(), Box<dyn Error>> {
+    let payload = bincode::serialize(data)?;
+    let header = IblHeader {
+        magic: IBL_CACHE_MAGIC,
+        version: IBL_CACHE_VERSION,
+        source_modified: modified_nanos(source).unwrap_or(0),
+        source_hash: source_hash(source).unwrap_or(0),
+        checksum: fnv1a(&payload),
+    };
+    let mut bytes = bincode::serialize(&header)?;
+    bytes.extend_from_slice(&payload);
+
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(cache, &bytes)?;
+    Ok(())
+}
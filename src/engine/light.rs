@@ -0,0 +1,104 @@
+use bytemuck_derive::{Pod, Zeroable};
+
+extern crate nalgebra as na;
+
+//Has to match MAX_LIGHTS in the lit shaders
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    //cone angles in radians, measured from the light direction
+    Spot { inner_angle: f32, outer_angle: f32 },
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    //ignored for directional lights
+    pub position: na::Point3<f32>,
+    //direction the light shines in, ignored for point lights
+    pub direction: na::Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    //distance at which point and spot lights have faded out completely
+    pub range: f32,
+}
+
+impl Light {
+    pub fn directional(direction: na::Vector3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Directional,
+            position: na::Point3::origin(),
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: 0.0,
+        }
+    }
+
+    pub fn point(position: na::Point3<f32>, color: [f32; 3], intensity: f32, range: f32) -> Self {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: -na::Vector3::y(),
+            color,
+            intensity,
+            range,
+        }
+    }
+
+    pub fn spot(
+        position: na::Point3<f32>,
+        direction: na::Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            },
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range,
+        }
+    }
+
+    pub fn to_gpu(&self) -> GPULight {
+        let (kind, cone) = match self.kind {
+            LightKind::Directional => (0.0, [0.0, 0.0]),
+            LightKind::Point => (1.0, [0.0, 0.0]),
+            LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            } => (2.0, [inner_angle.cos(), outer_angle.cos()]),
+        };
+        GPULight {
+            position: [self.position.x, self.position.y, self.position.z, kind],
+            direction: [self.direction.x, self.direction.y, self.direction.z, self.range],
+            color: [self.color[0], self.color[1], self.color[2], self.intensity],
+            cone: [cone[0], cone[1], 0.0, 0.0],
+        }
+    }
+}
+
+//std140 layout of a light, everything packed into vec4s
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod, Debug)]
+pub struct GPULight {
+    //w is the light kind: 0 directional, 1 point, 2 spot
+    pub position: [f32; 4],
+    //w is the range
+    pub direction: [f32; 4],
+    //w is the intensity
+    pub color: [f32; 4],
+    //x is the cosine of the inner angle, y of the outer angle
+    pub cone: [f32; 4],
+}
@@ -0,0 +1,178 @@
+use std::{collections::HashMap, mem::size_of};
+
+use bytemuck_derive::{Pod, Zeroable};
+use erupt::vk;
+use gpu_alloc::UsageFlags;
+use gpu_alloc_erupt::EruptMemoryDevice;
+
+use super::{
+    buffer::create_buffer,
+    descriptors::Descriptors,
+    device::Physical,
+    handles::Sampler,
+    memory_stats::MemoryCategory,
+    mesh::AllocatedBuffer,
+    scene::{AlphaMode, MaterialParams},
+    texture::Texture,
+};
+
+//std140 layout of MaterialData in pbr.frag
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod, Debug)]
+pub struct GPUMaterialData {
+    //a is the opacity
+    pub base_color: [f32; 4],
+    //a is unused
+    pub emissive: [f32; 4],
+    //x metallic, y roughness, z alpha cutoff, w unused
+    pub factors: [f32; 4],
+}
+
+impl GPUMaterialData {
+    pub fn new(params: &MaterialParams) -> Self {
+        let [r, g, b] = params.diffuse;
+        let [er, eg, eb] = params.emissive;
+        //a cutoff of zero never discards
+        let alpha_cutoff = match params.alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+        };
+        GPUMaterialData {
+            base_color: [r, g, b, params.opacity],
+            emissive: [er, eg, eb, 0.0],
+            factors: [params.metallic, params.roughness, alpha_cutoff, 0.0],
+        }
+    }
+}
+
+//Things every material descriptor set needs: a sampler and the textures used when a material has no texture for a slot.
+//The defaults are chosen so they don't change the factors they get multiplied with.
+pub struct MaterialResources {
+    pub sampler: Sampler,
+    white: Texture,
+    flat_normal: Texture,
+}
+
+impl MaterialResources {
+    pub fn new(physical: &mut Physical) -> Self {
+        let sampler_info = vk::SamplerCreateInfoBuilder::new()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::REPEAT)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = Sampler::new(
+            &physical.device,
+            unsafe { physical.device.create_sampler(&sampler_info, None, None) }.unwrap(),
+        );
+
+        MaterialResources {
+            sampler,
+            white: Texture::from_rgba8(&[255, 255, 255, 255], 1, 1, false, physical),
+            //tangent space +z
+            flat_normal: Texture::from_rgba8(&[128, 128, 255, 255], 1, 1, false, physical),
+        }
+    }
+
+    //Uploads the material parameters and writes a descriptor set for them with layout Descriptors::material_set_layout.
+    //Bindings: 0 parameters, 1 base color, 2 metallic-roughness, 3 normal, 4 occlusion, 5 emissive
+    //None when the pool has no sets left, it has room for MAX_MATERIALS.
+    pub fn create_descriptor_set(
+        &self,
+        physical: &mut Physical,
+        descs: &Descriptors,
+        textures: &HashMap<String, Texture>,
+        params: &MaterialParams,
+    ) -> Option<(AllocatedBuffer, vk::DescriptorSet)> {
+        let set_layouts = [descs.material_set_layout.layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(descs.descriptor_pool.handle)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }
+            .result()
+            .ok()?[0];
+
+        let mut buffer = create_buffer(
+            physical,
+            size_of::<GPUMaterialData>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            UsageFlags::UPLOAD,
+            MemoryCategory::Uniform,
+        );
+        unsafe {
+            buffer
+                .allocation
+                .as_mut()
+                .unwrap()
+                .write_bytes(
+                    EruptMemoryDevice::wrap(&physical.device),
+                    0,
+                    bytemuck::bytes_of(&GPUMaterialData::new(params)),
+                )
+                .unwrap();
+        }
+
+        let buffer_info = [vk::DescriptorBufferInfoBuilder::new()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(size_of::<GPUMaterialData>() as u64)];
+        let write = vk::WriteDescriptorSetBuilder::new()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&buffer_info);
+        unsafe { physical.device.update_descriptor_sets(&[write], &[]) }
+        self.write_textures(physical, descriptor_set, textures, params);
+
+        Some((buffer, descriptor_set))
+    }
+
+    //Writes the texture bindings of a material descriptor set. Textures that aren't in the scene (yet) fall back to the
+    //defaults, so this is called again when a texture finished loading. The set must not be in use by a pending frame.
+    pub fn write_textures(
+        &self,
+        physical: &Physical,
+        descriptor_set: vk::DescriptorSet,
+        textures: &HashMap<String, Texture>,
+        params: &MaterialParams,
+    ) {
+        let view = |name: &Option<String>, default: &Texture| {
+            name.as_ref()
+                .and_then(|name| textures.get(name))
+                .unwrap_or(default)
+                .image_view
+                .handle
+        };
+        let views = [
+            view(&params.diffuse_texture, &self.white),
+            view(&params.metallic_roughness_texture, &self.white),
+            view(&params.normal_texture, &self.flat_normal),
+            view(&params.occlusion_texture, &self.white),
+            view(&params.emissive_texture, &self.white),
+        ];
+
+        let image_infos: Vec<[vk::DescriptorImageInfoBuilder; 1]> = views
+            .iter()
+            .map(|view| {
+                [vk::DescriptorImageInfoBuilder::new()
+                    .sampler(self.sampler.handle)
+                    .image_view(*view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+            })
+            .collect();
+        let writes: Vec<vk::WriteDescriptorSetBuilder> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, image_info)| {
+                vk::WriteDescriptorSetBuilder::new()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as u32 + 1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(image_info)
+            })
+            .collect();
+        unsafe { physical.device.update_descriptor_sets(&writes, &[]) }
+    }
+}
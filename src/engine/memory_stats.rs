@@ -0,0 +1,185 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use erupt::vk::{self, DeviceMemory};
+use gpu_alloc::{MemoryBlock, UsageFlags};
+
+//What a MemoryBlock is used for, given to Device::alloc so live blocks can be counted by it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryCategory {
+    //the mesh pool's vertex and index chunks
+    Mesh,
+    Texture,
+    Uniform,
+    //render targets, shadow maps and the render graph's transient images
+    Attachment,
+    //storage and indirect buffers written or read by compute passes
+    Storage,
+    //host visible buffers that only exist to copy data to or from the GPU
+    Staging,
+}
+
+pub const MEMORY_CATEGORIES: [MemoryCategory; 6] = [
+    MemoryCategory::Mesh,
+    MemoryCategory::Texture,
+    MemoryCategory::Uniform,
+    MemoryCategory::Attachment,
+    MemoryCategory::Storage,
+    MemoryCategory::Staging,
+];
+
+#[derive(Default, Copy, Clone, Debug)]
+pub struct CategoryStats {
+    pub blocks: usize,
+    pub bytes: u64,
+}
+
+//What VK_EXT_memory_budget reports for a heap, usage includes other processes and the allocator's unused space
+#[derive(Copy, Clone, Debug)]
+pub struct HeapBudget {
+    pub budget: u64,
+    pub usage: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    pub size: u64,
+    pub device_local: bool,
+    //bytes of the blocks handed out by Device::alloc
+    pub allocated: u64,
+    //None when the device doesn't support VK_EXT_memory_budget
+    pub budget: Option<HeapBudget>,
+}
+
+//A snapshot of the memory allocated through Device, Display prints it as a report
+#[derive(Clone, Debug)]
+pub struct MemoryStats {
+    pub heaps: Vec<HeapStats>,
+    //bytes by the usage flags they were requested with
+    pub usages: Vec<(UsageFlags, u64)>,
+    //indexed by MemoryCategory
+    pub categories: [CategoryStats; MEMORY_CATEGORIES.len()],
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "GPU memory")?;
+        for (i, heap) in self.heaps.iter().enumerate() {
+            write!(
+                f,
+                "  heap {}{}: {:.1} of {:.1} MiB allocated",
+                i,
+                if heap.device_local { " (device local)" } else { "" },
+                mib(heap.allocated),
+                mib(heap.size)
+            )?;
+            if let Some(budget) = heap.budget {
+                write!(f, ", {:.1} MiB used of a {:.1} MiB budget", mib(budget.usage), mib(budget.budget))?;
+            }
+            writeln!(f)?;
+        }
+        for (usage, bytes) in &self.usages {
+            writeln!(f, "  {:?}: {:.1} MiB", usage, mib(*bytes))?;
+        }
+        for category in MEMORY_CATEGORIES {
+            let stats = self.categories[category as usize];
+            writeln!(f, "  {:?}: {} blocks, {:.1} MiB", category, stats.blocks, mib(stats.bytes))?;
+        }
+        Ok(())
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+struct BlockRecord {
+    category: MemoryCategory,
+    usage: UsageFlags,
+    heap: usize,
+    size: u64,
+}
+
+//Device's bookkeeping of the live blocks, blocks are told apart by their memory object and offset in it
+pub struct MemoryTracker {
+    heaps: Vec<vk::MemoryHeap>,
+    heap_of_type: Vec<usize>,
+    blocks: HashMap<(DeviceMemory, u64), BlockRecord>,
+    heap_bytes: Vec<u64>,
+    usage_bytes: BTreeMap<UsageFlags, u64>,
+    categories: [CategoryStats; MEMORY_CATEGORIES.len()],
+}
+
+impl MemoryTracker {
+    pub fn new(properties: &vk::PhysicalDeviceMemoryProperties) -> Self {
+        let heaps = properties.memory_heaps[..properties.memory_heap_count as usize].to_vec();
+        let heap_of_type = properties.memory_types[..properties.memory_type_count as usize]
+            .iter()
+            .map(|memory_type| memory_type.heap_index as usize)
+            .collect();
+        MemoryTracker {
+            heap_bytes: vec![0; heaps.len()],
+            heaps,
+            heap_of_type,
+            blocks: HashMap::new(),
+            usage_bytes: BTreeMap::new(),
+            categories: Default::default(),
+        }
+    }
+
+    pub fn allocated(&mut self, block: &MemoryBlock<DeviceMemory>, usage: UsageFlags, category: MemoryCategory) {
+        let record = BlockRecord {
+            category,
+            usage,
+            heap: self.heap_of_type[block.memory_type() as usize],
+            size: block.size(),
+        };
+        self.heap_bytes[record.heap] += record.size;
+        *self.usage_bytes.entry(usage).or_insert(0) += record.size;
+        let stats = &mut self.categories[category as usize];
+        stats.blocks += 1;
+        stats.bytes += record.size;
+        self.blocks.insert((*block.memory(), block.offset()), record);
+    }
+
+    pub fn freed(&mut self, block: &MemoryBlock<DeviceMemory>) {
+        let record = match self.blocks.remove(&(*block.memory(), block.offset())) {
+            Some(record) => record,
+            None => return,
+        };
+        self.heap_bytes[record.heap] -= record.size;
+        if let Some(bytes) = self.usage_bytes.get_mut(&record.usage) {
+            *bytes -= record.size;
+            if *bytes == 0 {
+                self.usage_bytes.remove(&record.usage);
+            }
+        }
+        let stats = &mut self.categories[record.category as usize];
+        stats.blocks -= 1;
+        stats.bytes -= record.size;
+    }
+
+    pub fn stats(&self, budget: Option<&vk::PhysicalDeviceMemoryBudgetPropertiesEXT>) -> MemoryStats {
+        let heaps = self
+            .heaps
+            .iter()
+            .enumerate()
+            .map(|(i, heap)| HeapStats {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                allocated: self.heap_bytes[i],
+                budget: budget.map(|budget| HeapBudget {
+                    budget: budget.heap_budget[i],
+                    usage: budget.heap_usage[i],
+                }),
+            })
+            .collect();
+        MemoryStats {
+            heaps,
+            usages: self.usage_bytes.iter().map(|(usage, bytes)| (*usage, *bytes)).collect(),
+            categories: self.categories,
+        }
+    }
+}
@@ -1,44 +1,130 @@
 extern crate nalgebra as na;
 
-use std::{
-    mem::{size_of, size_of_val},
-    u32,
-};
+use std::{cell::RefCell, fs::File, io::BufReader, mem::size_of, path::Path, sync::Arc, u32};
 
 use bytemuck_derive::{Pod, Zeroable};
 use erupt::vk::{
     self, DeviceMemory, VertexInputAttributeDescriptionBuilder,
     VertexInputBindingDescriptionBuilder,
 };
-use gpu_alloc::{MemoryBlock, Request, UsageFlags};
-use gpu_alloc_erupt::EruptMemoryDevice;
+use gpu_alloc::MemoryBlock;
 use memoffset::offset_of;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use super::device::Physical;
-#[derive(Debug)]
+use super::{
+    device::{Device, Physical, ResourceKind},
+    mesh_cache::{CookedFile, CookedMesh, Submesh},
+    mesh_pool::{MeshPool, PoolRange},
+};
+//Destroys the buffer and frees its memory when dropped, the GPU must be done with it by then.
+//allocation is only None while dropping.
 pub struct AllocatedBuffer {
     pub buffer: vk::Buffer,
     pub allocation: Option<MemoryBlock<DeviceMemory>>,
+    device: Arc<Device>,
+}
+
+impl AllocatedBuffer {
+    pub fn new(device: &Arc<Device>, buffer: vk::Buffer, allocation: MemoryBlock<DeviceMemory>) -> Self {
+        device.created(ResourceKind::Buffer);
+        AllocatedBuffer {
+            buffer,
+            allocation: Some(allocation),
+            device: Arc::clone(device),
+        }
+    }
+}
+
+impl Drop for AllocatedBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(Some(self.buffer), None);
+            if let Some(allocation) = self.allocation.take() {
+                self.device.dealloc(allocation);
+            }
+        }
+        self.device.released(ResourceKind::Buffer);
+    }
 }
 
-struct AllocatedImage {
+//Same as AllocatedBuffer for images, views made from the image have to be destroyed before it
+pub struct AllocatedImage {
     pub image: vk::Image,
     pub allocation: Option<MemoryBlock<DeviceMemory>>,
+    device: Arc<Device>,
+}
+
+impl AllocatedImage {
+    pub fn new(device: &Arc<Device>, image: vk::Image, allocation: MemoryBlock<DeviceMemory>) -> Self {
+        device.created(ResourceKind::Image);
+        AllocatedImage {
+            image,
+            allocation: Some(allocation),
+            device: Arc::clone(device),
+        }
+    }
+}
+
+impl Drop for AllocatedImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image(Some(self.image), None);
+            if let Some(allocation) = self.allocation.take() {
+                self.device.dealloc(allocation);
+            }
+        }
+        self.device.released(ResourceKind::Image);
+    }
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Zeroable, Pod, Serialize, Debug)]
+#[derive(Copy, Clone, Zeroable, Pod, Serialize, Deserialize, Debug)]
 pub struct Vertex {
     pub pos: [f32; 3],
     pub normal: [f32; 3],
     pub color: [f32; 3],
+    pub uv: [f32; 2],
+    //xyz is the tangent, w the handedness of the bitangent
+    pub tangent: [f32; 4],
+}
+//Axis aligned bounding box in mesh space
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Bounds {
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        if vertices.is_empty() {
+            return Bounds {
+                min: [0.0; 3],
+                max: [0.0; 3],
+            };
+        }
+        let mut bounds = Bounds {
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+        };
+        for vertex in vertices {
+            for i in 0..3 {
+                bounds.min[i] = bounds.min[i].min(vertex.pos[i]);
+                bounds.max[i] = bounds.max[i].max(vertex.pos[i]);
+            }
+        }
+        bounds
+    }
 }
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct Mesh {
     pub verticies: Vec<Vertex>,
-    pub vertex_buffer: AllocatedBuffer,
+    //empty for meshes that are a plain triangle list
+    pub indices: Vec<u32>,
+    //where the mesh is in the scene's MeshPool, triangle lists have sequential indices there
+    pub vertex_range: PoolRange,
+    pub index_range: PoolRange,
 }
 #[repr(C)]
 #[derive(Copy, Clone, Zeroable, Pod)]
@@ -77,8 +163,20 @@ impl VertexDesc<'_> {
             .format(vk::Format::R32G32B32_SFLOAT)
             .offset(offset_of!(Vertex, color) as u32);
 
+        let uv_attr = vk::VertexInputAttributeDescriptionBuilder::new()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(offset_of!(Vertex, uv) as u32);
+
+        let tan_attr = vk::VertexInputAttributeDescriptionBuilder::new()
+            .binding(0)
+            .location(4)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(offset_of!(Vertex, tangent) as u32);
+
         let bindings = vec![binding_desc];
-        let attributes = vec![pos_attr, nor_attr, col_attr];
+        let attributes = vec![pos_attr, nor_attr, col_attr, uv_attr, tan_attr];
 
         return VertexDesc {
             attributes,
@@ -87,19 +185,9 @@ impl VertexDesc<'_> {
     }
 }
 
-pub fn load(path: &std::path::Path) -> Vec<Vertex> {
-    let mut vertices: Vec<Vertex> = vec![];
-
-    let (models, materials) = tobj::load_obj(
-        path,
-        &tobj::LoadOptions {
-            triangulate: true,
-            ..Default::default()
-        },
-    )
-    .expect("Failed to OBJ load file");
-
-    let mesh = &models[0].mesh;
+//Flattens an indexed tobj mesh into a plain triangle list.
+pub fn tobj_vertices(mesh: &tobj::Mesh) -> Vec<Vertex> {
+    let mut vertices: Vec<Vertex> = Vec::with_capacity(mesh.indices.len());
 
     for idx in &mesh.indices {
         let i = *idx as usize;
@@ -117,97 +205,183 @@ pub fn load(path: &std::path::Path) -> Vec<Vertex> {
         } else {
             [0.0, 0.0, 0.0]
         };
-        let color = normal.clone();
-
-        vertices.push(Vertex { pos, normal, color })
+        //obj has the origin of the texture at the bottom left, vulkan at the top left
+        let uv = if !mesh.texcoords.is_empty() {
+            [mesh.texcoords[2 * i], 1.0 - mesh.texcoords[2 * i + 1]]
+        } else {
+            [0.0, 0.0]
+        };
+        //obj has no vertex colors, the material provides the surface color
+        vertices.push(Vertex {
+            pos,
+            normal,
+            color: [1.0, 1.0, 1.0],
+            uv,
+            tangent: [0.0, 0.0, 0.0, 0.0],
+        })
     }
 
-    println!("{:?}", vertices.len() as f32);
-
     vertices
 }
-impl Mesh {
-    pub fn new(path: &std::path::Path, physical: &mut Physical) -> Self {
-        //     let triangle_data = load(path);
-        let mut triangle_data: Vec<Vertex> = vec![];
-
-        let (models, materials) = tobj::load_obj(
-            path,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-        )
-        .expect("Failed to OBJ load file");
-
-        let mesh = &models[0].mesh;
-
-        for idx in &mesh.indices {
-            let i = *idx as usize;
-            let pos = [
-                mesh.positions[3 * i],
-                mesh.positions[3 * i + 1],
-                mesh.positions[3 * i + 2],
-            ];
-            let normal = if !mesh.normals.is_empty() {
-                [
-                    mesh.normals[3 * i],
-                    mesh.normals[3 * i + 1],
-                    mesh.normals[3 * i + 2],
-                ]
-            } else {
-                [0.0, 0.0, 0.0]
-            };
-            let color = normal.clone();
 
-            triangle_data.push(Vertex { pos, normal, color })
-        }
+//The runtime form of an OBJ file: every model flattened into a triangle list, with tangents when it has normals and
+//uvs. Only the names of the MTL files are kept, load_obj_scene reads them itself since they are small.
+pub fn cook_obj(path: &Path) -> Result<CookedFile, tobj::LoadError> {
+    let file = File::open(path).map_err(|_| tobj::LoadError::OpenFileFailed)?;
+    let material_libs = RefCell::new(Vec::new());
+    let (models, _) = tobj::load_obj_buf(
+        &mut BufReader::new(file),
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        //the materials have to be loaded for the models' material ids to be resolved
+        |lib| {
+            material_libs.borrow_mut().push(lib.to_string_lossy().into_owned());
+            tobj::load_mtl(path.parent().unwrap_or_else(|| Path::new("")).join(lib))
+        },
+    )?;
 
-        println!("path {:?}, len {}", path, triangle_data.len());
-        let data: &[u8] = bytemuck::cast_slice(&triangle_data);
-        let size = size_of_val(data);
-
-        let buffer_info = vk::BufferCreateInfoBuilder::new()
-            .size(size as u64)
-            .usage(vk::BufferUsageFlags::VERTEX_BUFFER);
-
-        let mut block = unsafe {
-            physical.allocator.alloc(
-                EruptMemoryDevice::wrap(&physical.device),
-                Request {
-                    size: size as u64,
-                    align_mask: 1,
-                    usage: UsageFlags::HOST_ACCESS,
-                    memory_types: !0,
-                },
-            )
-        }
-        .unwrap();
+    let submeshes = models
+        .iter()
+        .map(|model| {
+            let mut vertices = tobj_vertices(&model.mesh);
+            if !model.mesh.normals.is_empty() && !model.mesh.texcoords.is_empty() {
+                compute_tangents(&mut vertices, &[]);
+            }
+            Submesh {
+                name: model.name.clone(),
+                material: model.mesh.material_id,
+                mesh: CookedMesh::new(vertices, Vec::new()),
+            }
+        })
+        .collect();
+    Ok(CookedFile {
+        material_libs: material_libs.into_inner(),
+        submeshes,
+    })
+}
 
-        unsafe {
-            block
-                .write_bytes(EruptMemoryDevice::wrap(&physical.device), 0, data)
-                .unwrap();
+//Per-vertex tangents from the uv layout of each triangle, accumulated and then orthogonalized against the normal.
+//indices can be empty for a plain triangle list.
+pub fn compute_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let triangle_count = if indices.is_empty() {
+        vertices.len() / 3
+    } else {
+        indices.len() / 3
+    };
+    let index = |i: usize| {
+        if indices.is_empty() {
+            i
+        } else {
+            indices[i] as usize
         }
+    };
 
-        let buffer = unsafe { physical.device.create_buffer(&buffer_info, None, None) }.unwrap();
+    let mut tangents = vec![na::Vector3::<f32>::zeros(); vertices.len()];
+    let mut bitangents = vec![na::Vector3::<f32>::zeros(); vertices.len()];
 
-        unsafe {
-            physical
-                .device
-                .bind_buffer_memory(buffer, *block.memory(), 0)
-                .unwrap();
+    for triangle in 0..triangle_count {
+        let ids = [index(3 * triangle), index(3 * triangle + 1), index(3 * triangle + 2)];
+        let [v0, v1, v2] = ids.map(|i| vertices[i]);
+
+        let edge1 = na::Vector3::from(v1.pos) - na::Vector3::from(v0.pos);
+        let edge2 = na::Vector3::from(v2.pos) - na::Vector3::from(v0.pos);
+        let duv1 = [v1.uv[0] - v0.uv[0], v1.uv[1] - v0.uv[1]];
+        let duv2 = [v2.uv[0] - v0.uv[0], v2.uv[1] - v0.uv[1]];
+
+        let det = duv1[0] * duv2[1] - duv2[0] * duv1[1];
+        if det.abs() < f32::EPSILON {
+            continue;
         }
+        let r = 1.0 / det;
+        let tangent = (edge1 * duv2[1] - edge2 * duv1[1]) * r;
+        let bitangent = (edge2 * duv1[0] - edge1 * duv2[0]) * r;
 
-        let allocated_buff = AllocatedBuffer {
-            buffer,
-            allocation: Some(block),
+        for &i in &ids {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = na::Vector3::from(vertex.normal);
+        let tangent = tangents[i] - normal * normal.dot(&tangents[i]);
+        if tangent.norm_squared() < f32::EPSILON {
+            continue;
+        }
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
         };
+        vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+    }
+}
 
+impl Mesh {
+    pub fn from_cooked(cooked: CookedMesh, physical: &mut Physical, pool: &mut MeshPool) -> Self {
+        Mesh::from_indexed(cooked.vertices, cooked.indices, physical, pool)
+    }
+
+    //Unit cube with flat faces, drawn in place of meshes that are still loading
+    pub fn placeholder(physical: &mut Physical, pool: &mut MeshPool) -> Self {
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for axis in 0..3 {
+            for sign in [-1.0f32, 1.0] {
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                let mut tangent = [0.0; 4];
+                tangent[u] = 1.0;
+                tangent[3] = 1.0;
+
+                let base = vertices.len() as u32;
+                for (a, b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    let mut pos = [0.0; 3];
+                    pos[axis] = 0.5 * sign;
+                    pos[u] = 0.5 * a;
+                    pos[v] = 0.5 * b;
+                    vertices.push(Vertex {
+                        pos,
+                        normal,
+                        //the usual missing asset magenta
+                        color: [1.0, 0.0, 1.0],
+                        uv: [(a + 1.0) * 0.5, (b + 1.0) * 0.5],
+                        tangent,
+                    });
+                }
+                //u x v points along +axis, so the back face winds the other way
+                if sign > 0.0 {
+                    indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+                } else {
+                    indices.extend_from_slice(&[base, base + 2, base + 1, base, base + 3, base + 2]);
+                }
+            }
+        }
+        Mesh::from_indexed(vertices, indices, physical, pool)
+    }
+
+    //An empty index list makes a plain triangle list
+    pub fn from_indexed(vertices: Vec<Vertex>, indices: Vec<u32>, physical: &mut Physical, pool: &mut MeshPool) -> Self {
+        let (vertex_range, index_range) = pool.upload(physical, &vertices, &indices);
         Mesh {
-            verticies: triangle_data,
-            vertex_buffer: (allocated_buff),
+            verticies: vertices,
+            indices,
+            vertex_range,
+            index_range,
         }
     }
+
+    pub fn from_vertices(triangle_data: Vec<Vertex>, physical: &mut Physical, pool: &mut MeshPool) -> Self {
+        Mesh::from_indexed(triangle_data, Vec::new(), physical, pool)
+    }
+
+    //Returns the mesh's ranges to the pool
+    pub fn cleanup(&mut self, pool: &mut MeshPool) {
+        pool.free(self.vertex_range, self.index_range);
+    }
 }
@@ -0,0 +1,357 @@
+use std::{
+    convert::Infallible,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::mesh::{Bounds, Vertex};
+
+//Bump whenever Vertex or CookedMesh change layout, old cache files are then simply re-cooked.
+pub const COOKED_MESH_VERSION: u32 = 4;
+const COOKED_MESH_MAGIC: [u8; 4] = *b"RMSH";
+
+//Mesh data in the form the renderer uploads it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CookedMesh {
+    pub vertices: Vec<Vertex>,
+    //empty when the vertices are a plain triangle list
+    pub indices: Vec<u32>,
+    pub bounds: Bounds,
+}
+
+impl CookedMesh {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        let bounds = Bounds::from_vertices(&vertices);
+        CookedMesh {
+            vertices,
+            indices,
+            bounds,
+        }
+    }
+}
+
+//Everything cooked from one source file, an OBJ file gives one submesh per model.
+//`M` is QuantizedMesh for the on disk form of a quantized file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CookedFile<M = CookedMesh> {
+    //material libraries the source refers to, relative to it. The submeshes' material indices count the materials
+    //of all of them in this order.
+    pub material_libs: Vec<String>,
+    pub submeshes: Vec<Submesh<M>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Submesh<M = CookedMesh> {
+    //as in the source file, can be empty
+    pub name: String,
+    pub material: Option<usize>,
+    pub mesh: M,
+}
+
+impl<M> CookedFile<M> {
+    fn map<N>(self, f: impl Fn(M) -> N) -> CookedFile<N> {
+        CookedFile {
+            material_libs: self.material_libs,
+            submeshes: self
+                .submeshes
+                .into_iter()
+                .map(|submesh| Submesh {
+                    name: submesh.name,
+                    material: submesh.material,
+                    mesh: f(submesh.mesh),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl CookedFile {
+    //All submeshes as one mesh, for when the file is drawn as a whole
+    pub fn into_merged(self) -> CookedMesh {
+        let indexed = self.submeshes.iter().any(|submesh| !submesh.mesh.indices.is_empty());
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for submesh in self.submeshes {
+            let base = vertices.len() as u32;
+            if indexed && submesh.mesh.indices.is_empty() {
+                indices.extend(base..base + submesh.mesh.vertices.len() as u32);
+            } else {
+                indices.extend(submesh.mesh.indices.iter().map(|index| base + index));
+            }
+            vertices.extend(submesh.mesh.vertices);
+        }
+        CookedMesh::new(vertices, indices)
+    }
+}
+
+//Fixed size header in front of the bincode encoded CookedFile
+#[derive(Serialize, Deserialize, Debug)]
+struct CookedHeader {
+    magic: [u8; 4],
+    version: u32,
+    //modification time (nanoseconds since the unix epoch) and hash of the source file the mesh was cooked from
+    source_modified: u64,
+    source_hash: u64,
+    //hash of the payload after the header
+    checksum: u64,
+    //payload is a CookedFile<QuantizedMesh> instead of a CookedFile
+    quantized: bool,
+}
+
+//Smaller on disk form of a CookedMesh, expanded back to full floats when it is read.
+//Positions, uvs and colors are stored relative to their range, normals and tangents as snorm.
+#[derive(Serialize, Deserialize, Debug)]
+struct QuantizedMesh {
+    bounds: Bounds,
+    uv_range: [[f32; 2]; 2],
+    color_range: [[f32; 3]; 2],
+    positions: Vec<[u16; 3]>,
+    normals: Vec<[i8; 3]>,
+    colors: Vec<[u16; 3]>,
+    uvs: Vec<[u16; 2]>,
+    tangents: Vec<[i8; 4]>,
+    indices: Vec<u32>,
+}
+
+impl QuantizedMesh {
+    fn new(mesh: &CookedMesh) -> Self {
+        let mut uv_range = [[f32::MAX; 2], [f32::MIN; 2]];
+        let mut color_range = [[f32::MAX; 3], [f32::MIN; 3]];
+        for vertex in &mesh.vertices {
+            for i in 0..2 {
+                uv_range[0][i] = uv_range[0][i].min(vertex.uv[i]);
+                uv_range[1][i] = uv_range[1][i].max(vertex.uv[i]);
+            }
+            for i in 0..3 {
+                color_range[0][i] = color_range[0][i].min(vertex.color[i]);
+                color_range[1][i] = color_range[1][i].max(vertex.color[i]);
+            }
+        }
+
+        let bounds = mesh.bounds;
+        QuantizedMesh {
+            bounds,
+            uv_range,
+            color_range,
+            positions: mesh
+                .vertices
+                .iter()
+                .map(|v| [0, 1, 2].map(|i| unorm16(v.pos[i], bounds.min[i], bounds.max[i])))
+                .collect(),
+            normals: mesh
+                .vertices
+                .iter()
+                .map(|v| v.normal.map(snorm8))
+                .collect(),
+            colors: mesh
+                .vertices
+                .iter()
+                .map(|v| [0, 1, 2].map(|i| unorm16(v.color[i], color_range[0][i], color_range[1][i])))
+                .collect(),
+            uvs: mesh
+                .vertices
+                .iter()
+                .map(|v| [0, 1].map(|i| unorm16(v.uv[i], uv_range[0][i], uv_range[1][i])))
+                .collect(),
+            tangents: mesh
+                .vertices
+                .iter()
+                .map(|v| v.tangent.map(snorm8))
+                .collect(),
+            indices: mesh.indices.clone(),
+        }
+    }
+
+    fn into_cooked(self) -> CookedMesh {
+        let bounds = self.bounds;
+        let (uv_range, color_range) = (self.uv_range, self.color_range);
+        let vertices = (0..self.positions.len())
+            .map(|v| Vertex {
+                pos: [0, 1, 2].map(|i| {
+                    from_unorm16(self.positions[v][i], bounds.min[i], bounds.max[i])
+                }),
+                normal: self.normals[v].map(from_snorm8),
+                color: [0, 1, 2].map(|i| {
+                    from_unorm16(self.colors[v][i], color_range[0][i], color_range[1][i])
+                }),
+                uv: [0, 1].map(|i| from_unorm16(self.uvs[v][i], uv_range[0][i], uv_range[1][i])),
+                tangent: self.tangents[v].map(from_snorm8),
+            })
+            .collect();
+
+        CookedMesh {
+            vertices,
+            indices: self.indices,
+            bounds,
+        }
+    }
+}
+
+fn unorm16(value: f32, min: f32, max: f32) -> u16 {
+    if max > min {
+        ((value - min) / (max - min) * 65535.0).round() as u16
+    } else {
+        0
+    }
+}
+
+fn from_unorm16(value: u16, min: f32, max: f32) -> f32 {
+    min + (max - min) * (value as f32 / 65535.0)
+}
+
+fn snorm8(value: f32) -> i8 {
+    (value.clamp(-1.0, 1.0) * 127.0).round() as i8
+}
+
+fn from_snorm8(value: i8) -> f32 {
+    (value as f32 / 127.0).max(-1.0)
+}
+
+//Returns the cooked file for `source`, reading it from the cache when the cache file is still valid.
+//Otherwise it is produced with `cook` and written to the cache for the next launch.
+pub fn load_or_cook<F: FnOnce(&Path) -> CookedFile>(source: &Path, cook: F) -> CookedFile {
+    match try_load_or_cook(source, |source| Ok::<_, Infallible>(cook(source))) {
+        Ok(mesh) => mesh,
+        Err(never) => match never {},
+    }
+}
+
+//load_or_cook for cooking that can fail, nothing is written to the cache then
+pub fn try_load_or_cook<E, F: FnOnce(&Path) -> Result<CookedFile, E>>(source: &Path, cook: F) -> Result<CookedFile, E> {
+    let cache = cache_path(source);
+
+    if let Some(cooked) = read_cooked(&cache, source) {
+        return Ok(cooked);
+    }
+
+    let cooked = cook(source)?;
+    if let Err(e) = write_cooked(&cache, source, &cooked, false) {
+        println!("failed to write mesh cache {:?}: {}", cache, e);
+    }
+    Ok(cooked)
+}
+
+//Cache files live in a cache directory next to the source, e.g. assets/cache/monkey_smooth.obj.mesh
+pub fn cache_path(source: &Path) -> PathBuf {
+    let file_name = source
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    source
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join("cache")
+        .join(format!("{}.mesh", file_name))
+}
+
+//Reads a cooked file, None if it is missing, from another version, corrupted or out of date with `source`
+pub fn read_cooked(cache: &Path, source: &Path) -> Option<CookedFile> {
+    let bytes = fs::read(cache).ok()?;
+    let header: CookedHeader = bincode::deserialize(&bytes).ok()?;
+    if header.magic != COOKED_MESH_MAGIC || header.version != COOKED_MESH_VERSION {
+        return None;
+    }
+
+    //a changed timestamp alone is not enough to throw the cache away, e.g. after a fresh checkout
+    let modified = modified_nanos(source);
+    let touched = modified != Some(header.source_modified);
+    if touched && hash_file(source) != Some(header.source_hash) {
+        println!("mesh cache {:?} is out of date", cache);
+        return None;
+    }
+
+    let header_size = bincode::serialized_size(&header).ok()? as usize;
+    let payload = bytes.get(header_size..)?;
+    if fnv1a(payload) != header.checksum {
+        println!("mesh cache {:?} is corrupted", cache);
+        return None;
+    }
+
+    //the content still matches, storing the new timestamp saves hashing the source again on the next launch
+    if let (true, Some(modified)) = (touched, modified) {
+        let header = CookedHeader {
+            source_modified: modified,
+            ..header
+        };
+        if let Ok(mut updated) = bincode::serialize(&header) {
+            updated.extend_from_slice(payload);
+            if let Err(e) = fs::write(cache, &updated) {
+                println!("failed to update mesh cache {:?}: {}", cache, e);
+            }
+        }
+    }
+
+    if header.quantized {
+        bincode::deserialize::<CookedFile<QuantizedMesh>>(payload)
+            .ok()
+            .map(|cooked| cooked.map(QuantizedMesh::into_cooked))
+    } else {
+        bincode::deserialize(payload).ok()
+    }
+}
+
+//Writes `cooked` to `cache`, tagged with the current state of `source`. Returns the written file size.
+pub fn write_cooked(
+    cache: &Path,
+    source: &Path,
+    cooked: &CookedFile,
+    quantize: bool,
+) -> Result<u64, Box<dyn Error>> {
+    let payload = if quantize {
+        let quantized = CookedFile {
+            material_libs: cooked.material_libs.clone(),
+            submeshes: cooked
+                .submeshes
+                .iter()
+                .map(|submesh| Submesh {
+                    name: submesh.name.clone(),
+                    material: submesh.material,
+                    mesh: QuantizedMesh::new(&submesh.mesh),
+                })
+                .collect(),
+        };
+        bincode::serialize(&quantized)?
+    } else {
+        bincode::serialize(cooked)?
+    };
+    let header = CookedHeader {
+        magic: COOKED_MESH_MAGIC,
+        version: COOKED_MESH_VERSION,
+        source_modified: modified_nanos(source).unwrap_or(0),
+        source_hash: hash_file(source).unwrap_or(0),
+        checksum: fnv1a(&payload),
+        quantized: quantize,
+    };
+
+    let mut bytes = bincode::serialize(&header)?;
+    bytes.extend_from_slice(&payload);
+
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(cache, &bytes)?;
+    Ok(bytes.len() as u64)
+}
+
+pub fn modified_nanos(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+pub fn hash_file(path: &Path) -> Option<u64> {
+    fs::read(path).ok().map(|bytes| fnv1a(&bytes))
+}
+
+//64 bit FNV-1a, plenty to notice changed or truncated files
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use super::mesh::Vertex;
+
+//Size of the simulated post-transform cache, larger than most real caches which still works well on smaller ones.
+const CACHE_SIZE: usize = 32;
+
+//Merges bit-identical vertices of a plain triangle list (or an indexed mesh) into an indexed mesh.
+//Returns the unique vertices and the new index buffer.
+pub fn weld(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let source_indices: Vec<u32> = if indices.is_empty() {
+        (0..vertices.len() as u32).collect()
+    } else {
+        indices.to_vec()
+    };
+
+    let mut unique: HashMap<&[u8], u32> = HashMap::new();
+    let mut welded_vertices = Vec::new();
+    let mut welded_indices = Vec::with_capacity(source_indices.len());
+
+    for index in source_indices {
+        let vertex = &vertices[index as usize];
+        let key: &[u8] = bytemuck::bytes_of(vertex);
+        let next = welded_vertices.len() as u32;
+        let welded = *unique.entry(key).or_insert_with(|| {
+            welded_vertices.push(*vertex);
+            next
+        });
+        welded_indices.push(welded);
+    }
+
+    (welded_vertices, welded_indices)
+}
+
+//Reorders triangles so vertices are reused while they are still in the post-transform cache.
+//This is Tom Forsyth's "linear-speed vertex cache optimisation".
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return indices.to_vec();
+    }
+
+    //triangles using each vertex, vertex v owns triangles[offsets[v]..offsets[v] + remaining[v]]
+    let mut remaining = vec![0u32; vertex_count];
+    for &index in indices {
+        remaining[index as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count];
+    let mut sum = 0;
+    for (offset, count) in offsets.iter_mut().zip(remaining.iter()) {
+        *offset = sum;
+        sum += *count as usize;
+    }
+    let mut triangles = vec![0usize; sum];
+    let mut filled = vec![0usize; vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &index in corners {
+            let v = index as usize;
+            triangles[offsets[v] + filled[v]] = triangle;
+            filled[v] += 1;
+        }
+    }
+
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = remaining
+        .iter()
+        .map(|count| vertex_score(None, *count))
+        .collect();
+    let mut triangle_scores: Vec<f32> = indices
+        .chunks_exact(3)
+        .map(|corners| corners.iter().map(|&v| vertex_scores[v as usize]).sum())
+        .collect();
+    let mut added = vec![false; triangle_count];
+
+    let mut best = (0..triangle_count).max_by(|a, b| {
+        triangle_scores[*a]
+            .partial_cmp(&triangle_scores[*b])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(triangle_count * 3);
+    //fallback when nothing in the cache has triangles left
+    let mut next_unadded = 0;
+
+    while output.len() < triangle_count * 3 {
+        let triangle = match best {
+            Some(triangle) => triangle,
+            None => {
+                while added[next_unadded] {
+                    next_unadded += 1;
+                }
+                next_unadded
+            }
+        };
+        added[triangle] = true;
+        let corners = [
+            indices[3 * triangle],
+            indices[3 * triangle + 1],
+            indices[3 * triangle + 2],
+        ];
+        output.extend_from_slice(&corners);
+
+        for &index in &corners {
+            let v = index as usize;
+            let owned = &mut triangles[offsets[v]..offsets[v] + remaining[v] as usize];
+            if let Some(position) = owned.iter().position(|t| *t == triangle) {
+                let last = owned.len() - 1;
+                owned.swap(position, last);
+            }
+            remaining[v] -= 1;
+        }
+
+        //the triangle's vertices move to the front of the cache
+        let mut new_cache: Vec<u32> = corners.to_vec();
+        new_cache.extend(cache.iter().filter(|v| !corners.contains(v)));
+        for (position, &v) in new_cache.iter().enumerate() {
+            cache_position[v as usize] = if position < CACHE_SIZE {
+                Some(position)
+            } else {
+                None
+            };
+        }
+        for &v in &new_cache {
+            let v = v as usize;
+            vertex_scores[v] = vertex_score(cache_position[v], remaining[v]);
+        }
+
+        best = None;
+        let mut best_score = -1.0;
+        for &v in &new_cache {
+            let v = v as usize;
+            for &t in &triangles[offsets[v]..offsets[v] + remaining[v] as usize] {
+                let score: f32 = indices[3 * t..3 * t + 3]
+                    .iter()
+                    .map(|&corner| vertex_scores[corner as usize])
+                    .sum();
+                triangle_scores[t] = score;
+                if score > best_score {
+                    best_score = score;
+                    best = Some(t);
+                }
+            }
+        }
+
+        new_cache.truncate(CACHE_SIZE);
+        cache = new_cache;
+    }
+
+    output
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        //the last triangle's vertices get a fixed score so the next triangle does not just reuse the same edge
+        Some(position) if position < 3 => 0.75,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(1.5)
+        }
+        None => 0.0,
+    };
+    //favour vertices with few triangles left, so lone triangles don't get stranded
+    cache_score + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+//Reorders vertices into the order they are first used by the index buffer, for better memory locality.
+pub fn optimize_vertex_fetch(vertices: &[Vertex], indices: &mut [u32]) -> Vec<Vertex> {
+    let mut remap: Vec<Option<u32>> = vec![None; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+
+    for index in indices.iter_mut() {
+        let new_index = *remap[*index as usize].get_or_insert_with(|| {
+            reordered.push(vertices[*index as usize]);
+            reordered.len() as u32 - 1
+        });
+        *index = new_index;
+    }
+
+    reordered
+}
+
+//Average post-transform cache misses per triangle for a FIFO cache, 3.0 is the worst and ~0.5 is about the best possible
+pub fn average_cache_miss_ratio(indices: &[u32], cache_size: usize) -> f32 {
+    if indices.len() < 3 {
+        return 0.0;
+    }
+    let mut cache: std::collections::VecDeque<u32> = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for index in indices {
+        if !cache.contains(index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(*index);
+        }
+    }
+    misses as f32 / (indices.len() / 3) as f32
+}
@@ -0,0 +1,247 @@
+use std::mem::size_of;
+
+use erupt::vk;
+use gpu_alloc::UsageFlags;
+
+use super::{
+    buffer::{buffer_address, create_buffer, create_buffer_with_data},
+    device::Physical,
+    memory_stats::MemoryCategory,
+    mesh::{AllocatedBuffer, Vertex},
+    upload::immediate_submit,
+};
+
+//elements per chunk, meshes that don't fit get a chunk of their own
+const VERTEX_CHUNK_SIZE: u32 = 1 << 18;
+const INDEX_CHUNK_SIZE: u32 = 1 << 20;
+
+//Where part of a mesh is in the pool, in elements of the chunk's buffer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PoolRange {
+    pub chunk: usize,
+    pub offset: u32,
+    pub count: u32,
+}
+
+//First fit allocator over the elements of a chunk. Free ranges are kept sorted by offset and merged with their
+//neighbours when they are returned.
+struct FreeList {
+    free: Vec<(u32, u32)>,
+}
+
+impl FreeList {
+    fn new(size: u32) -> Self {
+        FreeList { free: vec![(0, size)] }
+    }
+
+    fn allocate(&mut self, count: u32) -> Option<u32> {
+        let index = self.free.iter().position(|(_, size)| *size >= count)?;
+        let (offset, size) = self.free[index];
+        if size == count {
+            self.free.remove(index);
+        } else {
+            self.free[index] = (offset + count, size - count);
+        }
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u32, count: u32) {
+        let index = self.free.partition_point(|(free_offset, _)| *free_offset < offset);
+        self.free.insert(index, (offset, count));
+        if index + 1 < self.free.len() && offset + count == self.free[index + 1].0 {
+            self.free[index].1 += self.free[index + 1].1;
+            self.free.remove(index + 1);
+        }
+        if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == offset {
+            self.free[index - 1].1 += self.free[index].1;
+            self.free.remove(index);
+        }
+    }
+}
+
+struct Chunk {
+    buffer: AllocatedBuffer,
+    //for vertex pulling, 0 for chunks without SHADER_DEVICE_ADDRESS usage
+    address: vk::DeviceAddress,
+    free: FreeList,
+}
+
+//Device local buffers holding elements of one kind, sub-allocated by FreeList
+struct ChunkList {
+    chunks: Vec<Chunk>,
+    chunk_size: u32,
+    element_size: u64,
+    usage: vk::BufferUsageFlags,
+}
+
+impl ChunkList {
+    fn allocate(&mut self, physical: &mut Physical, count: u32) -> PoolRange {
+        //zero sized ranges would hand out the same offset twice
+        let count = count.max(1);
+        for (chunk, list) in self.chunks.iter_mut().enumerate() {
+            if let Some(offset) = list.free.allocate(count) {
+                return PoolRange { chunk, offset, count };
+            }
+        }
+
+        let size = self.chunk_size.max(count);
+        let buffer = create_buffer(
+            physical,
+            size as u64 * self.element_size,
+            self.usage | vk::BufferUsageFlags::TRANSFER_DST,
+            UsageFlags::FAST_DEVICE_ACCESS,
+            MemoryCategory::Mesh,
+        );
+        let address = if self.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            buffer_address(physical, buffer.buffer)
+        } else {
+            0
+        };
+        let mut free = FreeList::new(size);
+        let offset = free.allocate(count).unwrap();
+        self.chunks.push(Chunk { buffer, address, free });
+        PoolRange {
+            chunk: self.chunks.len() - 1,
+            offset,
+            count,
+        }
+    }
+
+    fn free(&mut self, range: PoolRange) {
+        self.chunks[range.chunk].free.free(range.offset, range.count);
+    }
+}
+
+//Vertices and indices of every mesh, packed into a few large buffers so drawing only rebinds them when the next mesh
+//is in another chunk. Triangle lists get a sequential index range, so every mesh is drawn indexed.
+//Freed ranges are reused right away, so a mesh must only be freed once no frame in flight draws it.
+pub struct MeshPool {
+    vertices: ChunkList,
+    indices: ChunkList,
+}
+
+impl MeshPool {
+    pub fn new() -> Self {
+        MeshPool {
+            vertices: ChunkList {
+                chunks: Vec::new(),
+                chunk_size: VERTEX_CHUNK_SIZE,
+                element_size: size_of::<Vertex>() as u64,
+                //pipelines with vertex pulling read the vertices as a storage buffer through its address
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            },
+            indices: ChunkList {
+                chunks: Vec::new(),
+                chunk_size: INDEX_CHUNK_SIZE,
+                element_size: size_of::<u32>() as u64,
+                usage: vk::BufferUsageFlags::INDEX_BUFFER,
+            },
+        }
+    }
+
+    //Reserves room for a mesh without writing it, the caller copies the data in at the ranges' byte offsets
+    pub fn allocate(&mut self, physical: &mut Physical, vertex_count: u32, index_count: u32) -> (PoolRange, PoolRange) {
+        (
+            self.vertices.allocate(physical, vertex_count),
+            self.indices.allocate(physical, index_count),
+        )
+    }
+
+    //Allocates and fills the ranges of a mesh, blocking until the copy is done. `indices` is empty for a triangle list.
+    pub fn upload(&mut self, physical: &mut Physical, vertices: &[Vertex], indices: &[u32]) -> (PoolRange, PoolRange) {
+        let indices = sequential_indices(vertices, indices);
+        let (vertex_range, index_range) = self.allocate(physical, vertices.len() as u32, indices.len() as u32);
+
+        let vertex_data: &[u8] = bytemuck::cast_slice(vertices);
+        let index_data: &[u8] = bytemuck::cast_slice(&indices);
+        let staging = create_buffer_with_data(
+            physical,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            &[vertex_data, index_data].concat(),
+            MemoryCategory::Staging,
+        );
+        let vertex_buffer = self.vertex_buffer(vertex_range.chunk);
+        let index_buffer = self.index_buffer(index_range.chunk);
+        let vertex_offset = self.vertex_byte_offset(vertex_range);
+        let index_offset = self.index_byte_offset(index_range);
+        immediate_submit(physical, |cmd| unsafe {
+            let vertex_region = vk::BufferCopyBuilder::new()
+                .src_offset(0)
+                .dst_offset(vertex_offset)
+                .size(vertex_data.len() as u64);
+            let index_region = vk::BufferCopyBuilder::new()
+                .src_offset(vertex_data.len() as u64)
+                .dst_offset(index_offset)
+                .size(index_data.len() as u64);
+            if !vertex_data.is_empty() {
+                physical
+                    .device
+                    .cmd_copy_buffer(cmd, staging.buffer, vertex_buffer, &[vertex_region]);
+            }
+            if !index_data.is_empty() {
+                physical
+                    .device
+                    .cmd_copy_buffer(cmd, staging.buffer, index_buffer, &[index_region]);
+            }
+            //pipelines with vertex pulling read the vertices from the vertex shader
+            let written = vk::MemoryBarrierBuilder::new()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ | vk::AccessFlags::SHADER_READ,
+                );
+            physical.device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER,
+                None,
+                &[written],
+                &[],
+                &[],
+            );
+        });
+
+        (vertex_range, index_range)
+    }
+
+    pub fn free(&mut self, vertex_range: PoolRange, index_range: PoolRange) {
+        self.vertices.free(vertex_range);
+        self.indices.free(index_range);
+    }
+
+    pub fn vertex_buffer(&self, chunk: usize) -> vk::Buffer {
+        self.vertices.chunks[chunk].buffer.buffer
+    }
+
+    pub fn vertex_address(&self, chunk: usize) -> vk::DeviceAddress {
+        self.vertices.chunks[chunk].address
+    }
+
+    pub fn index_buffer(&self, chunk: usize) -> vk::Buffer {
+        self.indices.chunks[chunk].buffer.buffer
+    }
+
+    pub fn vertex_byte_offset(&self, range: PoolRange) -> u64 {
+        range.offset as u64 * self.vertices.element_size
+    }
+
+    pub fn index_byte_offset(&self, range: PoolRange) -> u64 {
+        range.offset as u64 * self.indices.element_size
+    }
+}
+
+impl Default for MeshPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//The index list the pool stores for a mesh: its own, or 0..n for a triangle list
+pub fn sequential_indices(vertices: &[Vertex], indices: &[u32]) -> Vec<u32> {
+    if indices.is_empty() {
+        (0..vertices.len() as u32).collect()
+    } else {
+        indices.to_vec()
+    }
+}
@@ -0,0 +1,155 @@
+use std::{path::Path, rc::Rc};
+
+use super::{
+    assets::AssetLoader,
+    device::Physical,
+    mesh::{self, Mesh},
+    mesh_cache,
+    pipeline::PipelineStruct,
+    scene::{unique_name, AlphaMode, Material, MaterialParams, Scene},
+};
+
+extern crate nalgebra as na;
+
+//Loads every model of an OBJ file as its own mesh and every material of its MTL files as an engine material,
+//then adds one render object per model to the scene. The models are read from the mesh cache when it is up to date. Names from the file are kept, and only get prefixed
+//with the file name when the scene already has something with the same name.
+//Textures are loaded in the background by `assets`, materials use the default textures until they arrive.
+//Returns the names of the meshes that were added.
+pub fn load_obj_scene(
+    path: &Path,
+    physical: &mut Physical,
+    scene: &mut Scene,
+    assets: &AssetLoader,
+    pipeline: &Rc<PipelineStruct>,
+    transform: impl Into<na::Matrix4<f32>>,
+) -> Vec<String> {
+    let transform = transform.into();
+    //the cooked file has the parsed models, asset-cook can make it ahead of time
+    let cooked = mesh_cache::load_or_cook(path, |path| {
+        mesh::cook_obj(path).expect("Failed to OBJ load file")
+    });
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    //in the same order as tobj merged them while cooking, so the submeshes' material indices match
+    let mut materials = Vec::new();
+    for lib in &cooked.material_libs {
+        match tobj::load_mtl(base_dir.join(lib)) {
+            Ok((lib_materials, _)) => materials.extend(lib_materials),
+            Err(e) => println!("failed to load materials {:?} for {:?}: {}", lib, path, e),
+        }
+    }
+
+    let file_name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let material_names: Vec<String> = materials
+        .iter()
+        .map(|material| {
+            let name = unique_name(&scene.materials, &file_name, &material.name);
+            let params = material_params(material, base_dir, scene, assets);
+            scene.materials.insert(
+                name.clone(),
+                Material {
+                    pipeline: Rc::clone(pipeline),
+                    params,
+                    descriptor_set: None,
+                    uniform_buffer: None,
+                },
+            );
+            name
+        })
+        .collect();
+
+    let mut default_material: Option<String> = None;
+    let mut mesh_names = Vec::with_capacity(cooked.submeshes.len());
+
+    for (index, submesh) in cooked.submeshes.into_iter().enumerate() {
+        let material_name = match submesh.material.and_then(|id| material_names.get(id)) {
+            Some(name) => name.clone(),
+            None => default_material
+                .get_or_insert_with(|| {
+                    let name = unique_name(&scene.materials, &file_name, "default");
+                    scene.materials.insert(
+                        name.clone(),
+                        Material {
+                            pipeline: Rc::clone(pipeline),
+                            params: MaterialParams::default(),
+                            descriptor_set: None,
+                            uniform_buffer: None,
+                        },
+                    );
+                    name
+                })
+                .clone(),
+        };
+
+        let model_name = if submesh.name.is_empty() {
+            index.to_string()
+        } else {
+            submesh.name
+        };
+        let mesh_name = unique_name(&scene.meshes, &file_name, &model_name);
+
+        if submesh.mesh.vertices.is_empty() {
+            continue;
+        }
+        let mesh = Mesh::from_cooked(submesh.mesh, physical, &mut scene.mesh_pool);
+
+        scene.add_render_object_with_mesh(mesh, &mesh_name, &material_name, transform);
+        mesh_names.push(mesh_name);
+    }
+
+    println!(
+        "path {:?}, {} meshes, {} materials",
+        path,
+        mesh_names.len(),
+        material_names.len()
+    );
+
+    mesh_names
+}
+
+fn material_params(
+    material: &tobj::Material,
+    base_dir: &Path,
+    scene: &mut Scene,
+    assets: &AssetLoader,
+) -> MaterialParams {
+    //texture paths in a MTL file are relative to the file itself, the full path is used as the texture name
+    //so materials using the same image share one texture
+    let mut texture = |name: &str, srgb: bool| {
+        if name.is_empty() {
+            return None;
+        }
+        let path = base_dir.join(name);
+        let texture_name = path.to_string_lossy().into_owned();
+        if !scene.textures.contains_key(&texture_name) && !scene.texture_states.contains_key(&texture_name) {
+            assets.load_texture(scene, &texture_name, &path, srgb);
+        }
+        Some(texture_name)
+    };
+
+    MaterialParams {
+        diffuse: material.diffuse,
+        specular: material.specular,
+        shininess: material.shininess,
+        opacity: material.dissolve,
+        //MTL has no alpha mode, an alpha map is taken as a cutout (foliage, rails) and a dissolve below one as blended
+        alpha_mode: if material.dissolve < 1.0 {
+            AlphaMode::Blend
+        } else if !material.dissolve_texture.is_empty() {
+            AlphaMode::Mask(0.5)
+        } else {
+            AlphaMode::Opaque
+        },
+        //usual conversion from a phong exponent to a roughness
+        roughness: (2.0 / (material.shininess + 2.0)).sqrt(),
+        diffuse_texture: texture(&material.diffuse_texture, true),
+        specular_texture: texture(&material.specular_texture, true),
+        normal_texture: texture(&material.normal_texture, false),
+        ..Default::default()
+    }
+}
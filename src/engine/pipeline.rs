@@ -1,31 +1,191 @@
-use std::{ffi::CString, mem::size_of};
+use std::{ffi::CString, mem::size_of, sync::Arc};
+
+extern crate nalgebra as na;
 
 use crate::engine::mesh;
 
-use super::{descriptors::Descriptors, device::Physical, renderpass::RenderPass};
+use super::{
+    deferred::GBUFFER_ATTACHMENTS,
+    descriptors::Descriptors,
+    device::{Device, Physical, ResourceKind},
+    renderpass::RenderPass,
+    shadow::ShadowSettings,
+};
+use bytemuck_derive::{Pod, Zeroable};
 use erupt::vk::{self};
 use vk_shader_macros::include_glsl;
 const FRAG: &[u32] = include_glsl!("src/shaders/colored-triangle.frag", kind: frag);
 const TRIMESH: &[u32] = include_glsl!("src/shaders/trimesh.vert");
-#[derive(PartialEq)]
+const LIT_VERT: &[u32] = include_glsl!("src/shaders/lit.vert");
+const BLINN_PHONG_FRAG: &[u32] = include_glsl!("src/shaders/blinn-phong.frag", kind: frag);
+const PBR_FRAG: &[u32] = include_glsl!("src/shaders/pbr.frag", kind: frag);
+const SHADOW_VERT: &[u32] = include_glsl!("src/shaders/shadow.vert");
+const FULLSCREEN_VERT: &[u32] = include_glsl!("src/shaders/fullscreen.vert");
+const SKYBOX_VERT: &[u32] = include_glsl!("src/shaders/skybox.vert");
+const SKYBOX_FRAG: &[u32] = include_glsl!("src/shaders/skybox.frag", kind: frag);
+const GBUFFER_FRAG: &[u32] = include_glsl!("src/shaders/gbuffer.frag", kind: frag);
+const GPU_DRIVEN_VERT: &[u32] = include_glsl!("src/shaders/gpu-driven.vert");
+const PULLED_VERT: &[u32] = include_glsl!("src/shaders/pulled.vert", target: vulkan1_2);
+
+//Indices into PipelineStruct::pipelines for pipelines made by with_shaders
+pub const OPAQUE_PIPELINE: usize = 0;
+//alpha blended over the target, tests depth but doesn't write it
+pub const BLENDED_PIPELINE: usize = 1;
+
+//Per draw data, the model matrix is needed separately for lighting in world space
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct MeshPushConstants {
+    pub render_matrix: na::Matrix4<f32>,
+    pub model: na::Matrix4<f32>,
+}
+
+//Per draw data of pipelines that pull their vertices, in the same push constant range as MeshPushConstants.
+//The shader takes the view projection from the global set.
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct PulledPushConstants {
+    pub model: na::Matrix4<f32>,
+    //device address of the mesh pool chunk holding the mesh's vertices
+    pub vertices: vk::DeviceAddress,
+}
+
+//Destroys its pipelines and their layout when dropped. Materials share one through an Rc, so it lives until the last
+//material using it is gone.
 pub struct PipelineStruct {
     pub pipelines: Vec<vk::Pipeline>,
     pub pipeline_layout: vk::PipelineLayout,
+    //no vertex input, vertices are read from the mesh pool through PulledPushConstants
+    pub vertex_pulling: bool,
+    device: Arc<Device>,
+}
+
+impl Drop for PipelineStruct {
+    fn drop(&mut self) {
+        unsafe {
+            for pipeline in &self.pipelines {
+                self.device.destroy_pipeline(Some(*pipeline), None);
+            }
+            self.device
+                .destroy_pipeline_layout(Some(self.pipeline_layout), None);
+        }
+        self.device.released(ResourceKind::Pipeline);
+    }
 }
-//Cleanup for this class is done in the scene class
-//This class is never used directly, but only via the Scene class.
+
 impl PipelineStruct {
-    pub fn new(physical: &Physical, render_pass: &RenderPass, descs: &Descriptors) -> Self {
+    fn from_parts(
+        physical: &Physical,
+        pipelines: Vec<vk::Pipeline>,
+        pipeline_layout: vk::PipelineLayout,
+        vertex_pulling: bool,
+    ) -> Self {
+        physical.device.created(ResourceKind::Pipeline);
+        PipelineStruct {
+            pipelines,
+            pipeline_layout,
+            vertex_pulling,
+            device: Arc::clone(&physical.device),
+        }
+    }
+
+    //Unlit, outputs the vertex color
+    pub fn new(physical: &Physical, render_pass: &RenderPass, descs: &Descriptors, vertex_pulling: bool) -> Self {
+        Self::with_shaders(physical, render_pass, descs, TRIMESH, FRAG, vertex_pulling)
+    }
+
+    //Lit with the scene lights, using the vertex color as the surface color
+    pub fn blinn_phong(physical: &Physical, render_pass: &RenderPass, descs: &Descriptors, vertex_pulling: bool) -> Self {
+        Self::with_shaders(physical, render_pass, descs, LIT_VERT, BLINN_PHONG_FRAG, vertex_pulling)
+    }
+
+    //Metallic-roughness PBR, parameters and textures come from the material set
+    pub fn pbr(physical: &Physical, render_pass: &RenderPass, descs: &Descriptors, vertex_pulling: bool) -> Self {
+        Self::with_shaders(physical, render_pass, descs, LIT_VERT, PBR_FRAG, vertex_pulling)
+    }
+
+    //With `vertex_pulling` `vert` is replaced by pulled.vert, which has the outputs of lit.vert
+    pub fn with_shaders(
+        physical: &Physical,
+        render_pass: &RenderPass,
+        descs: &Descriptors,
+        vert: &[u32],
+        frag: &[u32],
+        vertex_pulling: bool,
+    ) -> Self {
+        Self::graphics(
+            physical,
+            render_pass.render_pass.handle,
+            render_pass.samples,
+            1,
+            descs,
+            &[],
+            if vertex_pulling { PULLED_VERT } else { vert },
+            frag,
+            vertex_pulling,
+        )
+    }
+
+    //pbr for the GPU driven path, the model matrix is read from the object buffer bound as set 2
+    pub fn gpu_driven(
+        physical: &Physical,
+        render_pass: &RenderPass,
+        descs: &Descriptors,
+        object_set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        Self::graphics(
+            physical,
+            render_pass.render_pass.handle,
+            render_pass.samples,
+            1,
+            descs,
+            &[object_set_layout],
+            GPU_DRIVEN_VERT,
+            PBR_FRAG,
+            false,
+        )
+    }
+
+    //Writes the surface attributes of the pbr material into the G-buffer of the deferred path.
+    //Same layout as the other scene pipelines, so it can be swapped in for any material.
+    pub fn gbuffer(physical: &Physical, render_pass: vk::RenderPass, descs: &Descriptors) -> Self {
+        Self::graphics(
+            physical,
+            render_pass,
+            vk::SampleCountFlagBits::_1,
+            GBUFFER_ATTACHMENTS,
+            descs,
+            &[],
+            LIT_VERT,
+            GBUFFER_FRAG,
+            false,
+        )
+    }
+
+    //Scene pipeline with the global and material sets followed by `extra_set_layouts`,
+    //writing `color_attachments` attachments of `render_pass`
+    #[allow(clippy::too_many_arguments)]
+    fn graphics(
+        physical: &Physical,
+        render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlagBits,
+        color_attachments: usize,
+        descs: &Descriptors,
+        extra_set_layouts: &[vk::DescriptorSetLayout],
+        vert: &[u32],
+        frag: &[u32],
+        vertex_pulling: bool,
+    ) -> Self {
         //Pipeline starts here
         //Shader Modules
-        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(FRAG);
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(frag);
         let frag_module = unsafe {
             physical
                 .device
                 .create_shader_module(&module_info, None, None)
         }
         .unwrap();
-        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(TRIMESH);
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(vert);
         let entry_point = CString::new("main").unwrap();
         let tri_mesh = unsafe {
             physical
@@ -46,9 +206,13 @@ impl PipelineStruct {
         ];
         //like openGL VAO, not using it atm
         let vertex_desc = mesh::VertexDesc::new();
-        let vertex_input = vk::PipelineVertexInputStateCreateInfoBuilder::new()
-            .vertex_attribute_descriptions(&vertex_desc.attributes)
-            .vertex_binding_descriptions(&vertex_desc.bindings);
+        let vertex_input = if vertex_pulling {
+            vk::PipelineVertexInputStateCreateInfoBuilder::new()
+        } else {
+            vk::PipelineVertexInputStateCreateInfoBuilder::new()
+                .vertex_attribute_descriptions(&vertex_desc.attributes)
+                .vertex_binding_descriptions(&vertex_desc.bindings)
+        };
 
         //what sort of topology drawn e.g triangles or lines or whatever
         let input_assembly = vk::PipelineInputAssemblyStateCreateInfoBuilder::new()
@@ -67,10 +231,432 @@ impl PipelineStruct {
             .depth_bias_clamp(0.0)
             .depth_bias_slope_factor(0.0);
 
+        let multisampling = vk::PipelineMultisampleStateCreateInfoBuilder::new()
+            .sample_shading_enable(false)
+            .rasterization_samples(samples);
+
+        let color_blend_attachments: Vec<_> = (0..color_attachments)
+            .map(|_| {
+                vk::PipelineColorBlendAttachmentStateBuilder::new()
+                    .color_write_mask(
+                        vk::ColorComponentFlags::R
+                            | vk::ColorComponentFlags::G
+                            | vk::ColorComponentFlags::B
+                            | vk::ColorComponentFlags::A,
+                    )
+                    .blend_enable(false)
+            })
+            .collect();
+        let color_blending = vk::PipelineColorBlendStateCreateInfoBuilder::new()
+            .logic_op_enable(false)
+            .attachments(&color_blend_attachments);
+
+        //straight alpha over, the destination alpha is kept as the coverage of what is behind
+        let blended_attachments: Vec<_> = (0..color_attachments)
+            .map(|_| {
+                vk::PipelineColorBlendAttachmentStateBuilder::new()
+                    .color_write_mask(
+                        vk::ColorComponentFlags::R
+                            | vk::ColorComponentFlags::G
+                            | vk::ColorComponentFlags::B
+                            | vk::ColorComponentFlags::A,
+                    )
+                    .blend_enable(true)
+                    .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                    .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                    .color_blend_op(vk::BlendOp::ADD)
+                    .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                    .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                    .alpha_blend_op(vk::BlendOp::ADD)
+            })
+            .collect();
+        let blended_color_blending = vk::PipelineColorBlendStateCreateInfoBuilder::new()
+            .logic_op_enable(false)
+            .attachments(&blended_attachments);
+
+        let viewports = vec![vk::ViewportBuilder::new()
+            .x(0.0)
+            .y(0.0)
+            .width(physical.surface_caps.current_extent.width as f32)
+            .height(physical.surface_caps.current_extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)];
+        let scissors = vec![vk::Rect2DBuilder::new()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(physical.surface_caps.current_extent)];
+        let viewport_state = vk::PipelineViewportStateCreateInfoBuilder::new()
+            .viewports(&viewports)
+            .scissors(&scissors);
+
+        let pipeline_depth_stencil_info = vk::PipelineDepthStencilStateCreateInfoBuilder::new()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .depth_bounds_test_enable(false)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0)
+            .stencil_test_enable(false);
+        //blended surfaces must not hide each other, they are sorted back-to-front instead
+        let blended_depth_stencil_info = vk::PipelineDepthStencilStateCreateInfoBuilder::new()
+            .depth_test_enable(true)
+            .depth_write_enable(false)
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .depth_bounds_test_enable(false)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0)
+            .stencil_test_enable(false);
+
+        let push_constant = [vk::PushConstantRangeBuilder::new()
+            .offset(0)
+            .size(size_of::<MeshPushConstants>() as u32)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)];
+
+        //every pipeline gets the material set, so all layouts are compatible and materials can bind it unconditionally
+        let mut set_layouts = vec![descs.global_set_layout.layout, descs.material_set_layout.layout];
+        set_layouts.extend_from_slice(extra_set_layouts);
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfoBuilder::new()
+            .push_constant_ranges(&push_constant)
+            .set_layouts(&set_layouts);
+        let pipeline_layout = unsafe {
+            physical
+                .device
+                .create_pipeline_layout(&pipeline_layout_info, None, None)
+        }
+        .unwrap();
+
+        //in the order of OPAQUE_PIPELINE and BLENDED_PIPELINE
+        let pipeline_infos = vec![
+            vk::GraphicsPipelineCreateInfoBuilder::new()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input)
+                .input_assembly_state(&input_assembly)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterizer)
+                .multisample_state(&multisampling)
+                .color_blend_state(&color_blending)
+                .layout(pipeline_layout)
+                .render_pass(render_pass)
+                .depth_stencil_state(&pipeline_depth_stencil_info)
+                .subpass(0),
+            vk::GraphicsPipelineCreateInfoBuilder::new()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input)
+                .input_assembly_state(&input_assembly)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterizer)
+                .multisample_state(&multisampling)
+                .color_blend_state(&blended_color_blending)
+                .layout(pipeline_layout)
+                .render_pass(render_pass)
+                .depth_stencil_state(&blended_depth_stencil_info)
+                .subpass(0),
+        ];
+
+        let pipelines = unsafe {
+            physical
+                .device
+                .create_graphics_pipelines(None, &pipeline_infos, None)
+        }
+        .unwrap();
+
+        //delete shader modules now.
+        unsafe {
+            physical
+                .device
+                .destroy_shader_module(Some(frag_module), None);
+            physical.device.destroy_shader_module(Some(tri_mesh), None);
+        };
+
+        Self::from_parts(physical, pipelines, pipeline_layout, vertex_pulling)
+    }
+
+    //Depth only, for rendering shadow maps with `render_pass`. Dropping it destroys the pipeline and layout.
+    pub fn shadow(physical: &Physical, render_pass: vk::RenderPass, settings: &ShadowSettings) -> Self {
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(SHADOW_VERT);
+        let vert_module = unsafe {
+            physical
+                .device
+                .create_shader_module(&module_info, None, None)
+        }
+        .unwrap();
+        let entry_point = CString::new("main").unwrap();
+        //without a fragment shader only depth gets written
+        let shader_stages = vec![vk::PipelineShaderStageCreateInfoBuilder::new()
+            .stage(vk::ShaderStageFlagBits::VERTEX)
+            .module(vert_module)
+            .name(&entry_point)];
+
+        let vertex_desc = mesh::VertexDesc::new();
+        let vertex_input = vk::PipelineVertexInputStateCreateInfoBuilder::new()
+            .vertex_attribute_descriptions(&vertex_desc.attributes)
+            .vertex_binding_descriptions(&vertex_desc.bindings);
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfoBuilder::new()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        //the bias pushes the stored depth away from the light, against acne on lit surfaces
+        let rasterizer = vk::PipelineRasterizationStateCreateInfoBuilder::new()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(true)
+            .depth_bias_constant_factor(settings.depth_bias_constant)
+            .depth_bias_clamp(0.0)
+            .depth_bias_slope_factor(settings.depth_bias_slope);
+
         let multisampling = vk::PipelineMultisampleStateCreateInfoBuilder::new()
             .sample_shading_enable(false)
             .rasterization_samples(vk::SampleCountFlagBits::_1);
 
+        let extent = vk::Extent2D {
+            width: settings.resolution,
+            height: settings.resolution,
+        };
+        let viewports = vec![vk::ViewportBuilder::new()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)];
+        let scissors = vec![vk::Rect2DBuilder::new()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(extent)];
+        let viewport_state = vk::PipelineViewportStateCreateInfoBuilder::new()
+            .viewports(&viewports)
+            .scissors(&scissors);
+
+        let pipeline_depth_stencil_info = vk::PipelineDepthStencilStateCreateInfoBuilder::new()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .depth_bounds_test_enable(false)
+            .min_depth_bounds(0.0)
+            .max_depth_bounds(1.0)
+            .stencil_test_enable(false);
+
+        let push_constant = [vk::PushConstantRangeBuilder::new()
+            .offset(0)
+            .size(size_of::<MeshPushConstants>() as u32)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)];
+
+        let pipeline_layout_info =
+            vk::PipelineLayoutCreateInfoBuilder::new().push_constant_ranges(&push_constant);
+        let pipeline_layout = unsafe {
+            physical
+                .device
+                .create_pipeline_layout(&pipeline_layout_info, None, None)
+        }
+        .unwrap();
+
+        let pipeline_infos = vec![vk::GraphicsPipelineCreateInfoBuilder::new()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .depth_stencil_state(&pipeline_depth_stencil_info)
+            .subpass(0)];
+
+        let pipelines = unsafe {
+            physical
+                .device
+                .create_graphics_pipelines(None, &pipeline_infos, None)
+        }
+        .unwrap();
+
+        unsafe {
+            physical.device.destroy_shader_module(Some(vert_module), None);
+        }
+
+        Self::from_parts(physical, pipelines, pipeline_layout, false)
+    }
+
+    //Full screen triangle running `frag` for every pixel of `render_pass`' single color attachment,
+    //used by the post chain and the deferred lighting pass. Dropping it destroys the pipeline and layout.
+    pub fn fullscreen(
+        physical: &Physical,
+        render_pass: vk::RenderPass,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_size: u32,
+        frag: &[u32],
+    ) -> Self {
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(frag);
+        let frag_module = unsafe {
+            physical
+                .device
+                .create_shader_module(&module_info, None, None)
+        }
+        .unwrap();
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(FULLSCREEN_VERT);
+        let vert_module = unsafe {
+            physical
+                .device
+                .create_shader_module(&module_info, None, None)
+        }
+        .unwrap();
+        let entry_point = CString::new("main").unwrap();
+        let shader_stages = vec![
+            vk::PipelineShaderStageCreateInfoBuilder::new()
+                .stage(vk::ShaderStageFlagBits::VERTEX)
+                .module(vert_module)
+                .name(&entry_point),
+            vk::PipelineShaderStageCreateInfoBuilder::new()
+                .stage(vk::ShaderStageFlagBits::FRAGMENT)
+                .module(frag_module)
+                .name(&entry_point),
+        ];
+
+        //no vertex buffers, the vertex shader makes the triangle from the vertex index
+        let vertex_input = vk::PipelineVertexInputStateCreateInfoBuilder::new();
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfoBuilder::new()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfoBuilder::new()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(false);
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfoBuilder::new()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlagBits::_1);
+
+        let color_blend_attachments = vec![vk::PipelineColorBlendAttachmentStateBuilder::new()
+            .color_write_mask(
+                vk::ColorComponentFlags::R
+                    | vk::ColorComponentFlags::G
+                    | vk::ColorComponentFlags::B
+                    | vk::ColorComponentFlags::A,
+            )
+            .blend_enable(false)];
+        let color_blending = vk::PipelineColorBlendStateCreateInfoBuilder::new()
+            .logic_op_enable(false)
+            .attachments(&color_blend_attachments);
+
+        let viewports = vec![vk::ViewportBuilder::new()
+            .x(0.0)
+            .y(0.0)
+            .width(physical.surface_caps.current_extent.width as f32)
+            .height(physical.surface_caps.current_extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0)];
+        let scissors = vec![vk::Rect2DBuilder::new()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(physical.surface_caps.current_extent)];
+        let viewport_state = vk::PipelineViewportStateCreateInfoBuilder::new()
+            .viewports(&viewports)
+            .scissors(&scissors);
+
+        let push_constant = [vk::PushConstantRangeBuilder::new()
+            .offset(0)
+            .size(push_constant_size)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfoBuilder::new()
+            .push_constant_ranges(&push_constant)
+            .set_layouts(set_layouts);
+        let pipeline_layout = unsafe {
+            physical
+                .device
+                .create_pipeline_layout(&pipeline_layout_info, None, None)
+        }
+        .unwrap();
+
+        let pipeline_infos = vec![vk::GraphicsPipelineCreateInfoBuilder::new()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .color_blend_state(&color_blending)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0)];
+
+        let pipelines = unsafe {
+            physical
+                .device
+                .create_graphics_pipelines(None, &pipeline_infos, None)
+        }
+        .unwrap();
+
+        unsafe {
+            physical
+                .device
+                .destroy_shader_module(Some(frag_module), None);
+            physical.device.destroy_shader_module(Some(vert_module), None);
+        }
+
+        Self::from_parts(physical, pipelines, pipeline_layout, false)
+    }
+
+    //Full screen triangle on the far plane of the main render pass, with the global set and the skybox's cubemap set.
+    //Depth is tested so only uncovered pixels are drawn, but not written.
+    pub fn skybox(
+        physical: &Physical,
+        render_pass: &RenderPass,
+        descs: &Descriptors,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> Self {
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(SKYBOX_FRAG);
+        let frag_module = unsafe {
+            physical
+                .device
+                .create_shader_module(&module_info, None, None)
+        }
+        .unwrap();
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(SKYBOX_VERT);
+        let vert_module = unsafe {
+            physical
+                .device
+                .create_shader_module(&module_info, None, None)
+        }
+        .unwrap();
+        let entry_point = CString::new("main").unwrap();
+        let shader_stages = vec![
+            vk::PipelineShaderStageCreateInfoBuilder::new()
+                .stage(vk::ShaderStageFlagBits::VERTEX)
+                .module(vert_module)
+                .name(&entry_point),
+            vk::PipelineShaderStageCreateInfoBuilder::new()
+                .stage(vk::ShaderStageFlagBits::FRAGMENT)
+                .module(frag_module)
+                .name(&entry_point),
+        ];
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfoBuilder::new();
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfoBuilder::new()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfoBuilder::new()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(false);
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfoBuilder::new()
+            .sample_shading_enable(false)
+            .rasterization_samples(render_pass.samples);
+
         let color_blend_attachments = vec![vk::PipelineColorBlendAttachmentStateBuilder::new()
             .color_write_mask(
                 vk::ColorComponentFlags::R
@@ -97,25 +683,18 @@ impl PipelineStruct {
             .viewports(&viewports)
             .scissors(&scissors);
 
+        //the triangle is exactly at the cleared depth of 1.0
         let pipeline_depth_stencil_info = vk::PipelineDepthStencilStateCreateInfoBuilder::new()
             .depth_test_enable(true)
-            .depth_write_enable(true)
+            .depth_write_enable(false)
             .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
             .depth_bounds_test_enable(false)
             .min_depth_bounds(0.0)
             .max_depth_bounds(1.0)
             .stencil_test_enable(false);
 
-        let push_constant = [vk::PushConstantRangeBuilder::new()
-            .offset(0)
-            .size(size_of::<[f32; 16]>() as u32)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)];
-        
-        let global_set_layout = [descs.global_set_layout];
-
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfoBuilder::new()
-            .push_constant_ranges(&push_constant)
-            .set_layouts(&global_set_layout);
+        let set_layouts = [descs.global_set_layout.layout, set_layout];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfoBuilder::new().set_layouts(&set_layouts);
         let pipeline_layout = unsafe {
             physical
                 .device
@@ -124,7 +703,6 @@ impl PipelineStruct {
         .unwrap();
 
         let pipeline_infos = vec![vk::GraphicsPipelineCreateInfoBuilder::new()
-            //Colored Triangle
             .stages(&shader_stages)
             .vertex_input_state(&vertex_input)
             .input_assembly_state(&input_assembly)
@@ -133,7 +711,7 @@ impl PipelineStruct {
             .multisample_state(&multisampling)
             .color_blend_state(&color_blending)
             .layout(pipeline_layout)
-            .render_pass(render_pass.render_pass)
+            .render_pass(render_pass.render_pass.handle)
             .depth_stencil_state(&pipeline_depth_stencil_info)
             .subpass(0)];
 
@@ -144,17 +722,65 @@ impl PipelineStruct {
         }
         .unwrap();
 
-        //delete shader modules now.
         unsafe {
             physical
                 .device
                 .destroy_shader_module(Some(frag_module), None);
-            physical.device.destroy_shader_module(Some(tri_mesh), None);
-        };
+            physical.device.destroy_shader_module(Some(vert_module), None);
+        }
 
-        PipelineStruct {
-            pipelines: pipelines,
-            pipeline_layout,
+        Self::from_parts(physical, pipelines, pipeline_layout, false)
+    }
+
+    //Compute pipeline, push constants are visible to the compute stage. `pipelines` holds the one pipeline.
+    pub fn compute(
+        physical: &Physical,
+        shader: &[u32],
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_size: u32,
+    ) -> Self {
+        let module_info = vk::ShaderModuleCreateInfoBuilder::new().code(shader);
+        let module = unsafe {
+            physical
+                .device
+                .create_shader_module(&module_info, None, None)
         }
+        .unwrap();
+        let entry_point = CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfoBuilder::new()
+            .stage(vk::ShaderStageFlagBits::COMPUTE)
+            .module(module)
+            .name(&entry_point);
+
+        let push_constant = [vk::PushConstantRangeBuilder::new()
+            .offset(0)
+            .size(push_constant_size)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)];
+        let mut pipeline_layout_info = vk::PipelineLayoutCreateInfoBuilder::new().set_layouts(set_layouts);
+        if push_constant_size > 0 {
+            pipeline_layout_info = pipeline_layout_info.push_constant_ranges(&push_constant);
+        }
+        let pipeline_layout = unsafe {
+            physical
+                .device
+                .create_pipeline_layout(&pipeline_layout_info, None, None)
+        }
+        .unwrap();
+
+        let pipeline_infos = [vk::ComputePipelineCreateInfoBuilder::new()
+            .stage(*stage)
+            .layout(pipeline_layout)];
+        let pipelines = unsafe {
+            physical
+                .device
+                .create_compute_pipelines(None, &pipeline_infos, None)
+        }
+        .unwrap();
+
+        unsafe {
+            physical.device.destroy_shader_module(Some(module), None);
+        }
+
+        Self::from_parts(physical, pipelines, pipeline_layout, false)
     }
 }
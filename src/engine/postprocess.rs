@@ -0,0 +1,419 @@
+use std::mem::size_of;
+
+use bytemuck_derive::{Pod, Zeroable};
+use erupt::vk;
+use vk_shader_macros::include_glsl;
+
+use super::{
+    descriptors::DescriptorSetLayout,
+    device::Physical,
+    handles::{DescriptorPool, Framebuffer, RenderPass, Sampler},
+    pipeline::PipelineStruct,
+    renderpass::HDR_FORMAT,
+    swapchain::Swapchain,
+};
+
+const COPY_FRAG: &[u32] = include_glsl!("src/shaders/post-copy.frag", kind: frag);
+const TONEMAP_FRAG: &[u32] = include_glsl!("src/shaders/post-tonemap.frag", kind: frag);
+const FXAA_FRAG: &[u32] = include_glsl!("src/shaders/post-fxaa.frag", kind: frag);
+const VIGNETTE_FRAG: &[u32] = include_glsl!("src/shaders/post-vignette.frag", kind: frag);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TonemapOperator {
+    Reinhard,
+    Aces,
+}
+
+//One full screen pass of the chain. The chain starts on the hdr scene image and the last pass writes the swapchain image,
+//so everything after the tonemap works on display range colors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PostEffect {
+    //exposure is a multiplier applied before the operator, gamma an extra curve on top of the
+    //swapchain's own srgb encoding (1.0 leaves it alone)
+    Tonemap {
+        operator: TonemapOperator,
+        exposure: f32,
+        gamma: f32,
+    },
+    //span_max is the longest edge search in pixels, reduce_mul and reduce_min limit blurring of low contrast areas
+    Fxaa {
+        span_max: f32,
+        reduce_mul: f32,
+        reduce_min: f32,
+    },
+    //darkens the corners, radius and softness are relative to half the screen diagonal
+    Vignette {
+        intensity: f32,
+        radius: f32,
+        softness: f32,
+    },
+}
+
+impl PostEffect {
+    pub fn tonemap(operator: TonemapOperator) -> Self {
+        PostEffect::Tonemap {
+            operator,
+            exposure: 1.0,
+            gamma: 1.0,
+        }
+    }
+
+    pub fn fxaa() -> Self {
+        PostEffect::Fxaa {
+            span_max: 8.0,
+            reduce_mul: 1.0 / 8.0,
+            reduce_min: 1.0 / 128.0,
+        }
+    }
+
+    pub fn vignette() -> Self {
+        PostEffect::Vignette {
+            intensity: 0.35,
+            radius: 0.75,
+            softness: 0.45,
+        }
+    }
+
+    fn shader(&self) -> &'static [u32] {
+        match self {
+            PostEffect::Tonemap { .. } => TONEMAP_FRAG,
+            PostEffect::Fxaa { .. } => FXAA_FRAG,
+            PostEffect::Vignette { .. } => VIGNETTE_FRAG,
+        }
+    }
+
+    //the shader's params vector
+    fn params(&self) -> [f32; 4] {
+        match *self {
+            PostEffect::Tonemap {
+                operator,
+                exposure,
+                gamma,
+            } => {
+                let operator = match operator {
+                    TonemapOperator::Reinhard => 0.0,
+                    TonemapOperator::Aces => 1.0,
+                };
+                [exposure, gamma, operator, 0.0]
+            }
+            PostEffect::Fxaa {
+                span_max,
+                reduce_mul,
+                reduce_min,
+            } => [span_max, reduce_mul, reduce_min, 0.0],
+            PostEffect::Vignette {
+                intensity,
+                radius,
+                softness,
+            } => [intensity, radius, softness, 0.0],
+        }
+    }
+}
+
+//Number of full screen passes the chain records for `effects`
+pub fn pass_count(effects: &[PostEffect]) -> usize {
+    effects.len().max(1)
+}
+
+//Number of intermediate images the chain ping-pongs between
+pub fn intermediate_count(effects: &[PostEffect]) -> usize {
+    (pass_count(effects) - 1).min(2)
+}
+
+pub fn default_post_effects() -> Vec<PostEffect> {
+    vec![
+        PostEffect::tonemap(TonemapOperator::Aces),
+        PostEffect::fxaa(),
+        PostEffect::vignette(),
+    ]
+}
+
+//Push constants of every post shader
+#[repr(C)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct PostPushConstants {
+    pub params: [f32; 4],
+    //xy is the size of an input texel in uv
+    pub texel_size: [f32; 4],
+}
+
+struct PostPass {
+    pipeline: PipelineStruct,
+    params: [f32; 4],
+}
+
+//Offscreen image between two passes, owned by the render graph
+struct Intermediate {
+    framebuffer: Framebuffer,
+    descriptor_set: vk::DescriptorSet,
+}
+
+//Runs the post effects on the scene's hdr image, ping-ponging between two intermediate images
+//and writing the last pass into the swapchain image. Each pass is a render graph pass of its own.
+pub struct PostChain {
+    passes: Vec<PostPass>,
+    //renders into an intermediate image, and into the swapchain image
+    intermediate_pass: RenderPass,
+    swapchain_pass: RenderPass,
+    swapchain_framebuffers: Vec<Framebuffer>,
+    intermediates: Vec<Intermediate>,
+    #[allow(dead_code)]
+    descriptor_pool: DescriptorPool,
+    //samples the hdr image
+    hdr_descriptor: vk::DescriptorSet,
+    #[allow(dead_code)]
+    sampler: Sampler,
+    extent: vk::Extent2D,
+}
+
+impl PostChain {
+    pub fn new(
+        physical: &mut Physical,
+        swapchain: &Swapchain,
+        hdr_view: vk::ImageView,
+        intermediate_views: &[vk::ImageView],
+        effects: &[PostEffect],
+    ) -> Self {
+        let extent = physical.surface_caps.current_extent;
+
+        let intermediate_pass = create_render_pass(physical, HDR_FORMAT);
+        let swapchain_pass = create_render_pass(physical, physical.format.format);
+
+        let binding = [vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+        let set_layout = DescriptorSetLayout::new(physical, &binding);
+
+        //the hdr image and two intermediates
+        let sizes = [vk::DescriptorPoolSizeBuilder::new()
+            ._type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(3)];
+        let pool_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .max_sets(3)
+            .pool_sizes(&sizes);
+        let descriptor_pool = unsafe {
+            physical
+                .device
+                .create_descriptor_pool(&pool_info, None, None)
+        }
+        .unwrap();
+        let descriptor_pool = DescriptorPool::new(&physical.device, descriptor_pool);
+
+        let sampler_info = vk::SamplerCreateInfoBuilder::new()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE);
+        let sampler = Sampler::new(
+            &physical.device,
+            unsafe { physical.device.create_sampler(&sampler_info, None, None) }.unwrap(),
+        );
+
+        let allocate_set = |physical: &Physical, view: vk::ImageView| {
+            let set_layouts = [set_layout.layout];
+            let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+                .descriptor_pool(descriptor_pool.handle)
+                .set_layouts(&set_layouts);
+            let set = unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0];
+            let image_info = [vk::DescriptorImageInfoBuilder::new()
+                .sampler(sampler.handle)
+                .image_view(view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+            let write = vk::WriteDescriptorSetBuilder::new()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_info);
+            unsafe { physical.device.update_descriptor_sets(&[write], &[]) }
+            set
+        };
+        let hdr_descriptor = allocate_set(physical, hdr_view);
+
+        //without effects the hdr image is still copied to the screen
+        let passes: Vec<PostPass> = if effects.is_empty() {
+            vec![PostPass {
+                pipeline: PipelineStruct::fullscreen(
+                    physical,
+                    swapchain_pass.handle,
+                    &[set_layout.layout],
+                    size_of::<PostPushConstants>() as u32,
+                    COPY_FRAG,
+                ),
+                params: [0.0; 4],
+            }]
+        } else {
+            effects
+                .iter()
+                .enumerate()
+                .map(|(i, effect)| {
+                    let pass = if i + 1 == effects.len() {
+                        &swapchain_pass
+                    } else {
+                        &intermediate_pass
+                    };
+                    PostPass {
+                        pipeline: PipelineStruct::fullscreen(
+                            physical,
+                            pass.handle,
+                            &[set_layout.layout],
+                            size_of::<PostPushConstants>() as u32,
+                            effect.shader(),
+                        ),
+                        params: effect.params(),
+                    }
+                })
+                .collect()
+        };
+
+        let intermediates = intermediate_views
+            .iter()
+            .map(|view| Intermediate {
+                framebuffer: create_framebuffer(physical, intermediate_pass.handle, *view, extent),
+                descriptor_set: allocate_set(physical, *view),
+            })
+            .collect();
+
+        let swapchain_framebuffers = swapchain
+            .image_views
+            .iter()
+            .map(|view| create_framebuffer(physical, swapchain_pass.handle, view.handle, extent))
+            .collect();
+
+        PostChain {
+            passes,
+            intermediate_pass,
+            swapchain_pass,
+            swapchain_framebuffers,
+            intermediates,
+            descriptor_pool,
+            hdr_descriptor,
+            sampler,
+            extent,
+        }
+    }
+
+    //Records the `index`th pass. Pass 0 reads the hdr image, pass i writes intermediate i % 2 and the last pass
+    //writes the swapchain image.
+    pub fn record_pass(&self, physical: &Physical, cmd: vk::CommandBuffer, index: usize, swapchain_image_index: u32) {
+        let texel_size = [
+            1.0 / self.extent.width as f32,
+            1.0 / self.extent.height as f32,
+            0.0,
+            0.0,
+        ];
+        let pass = &self.passes[index];
+        let input = if index == 0 {
+            self.hdr_descriptor
+        } else {
+            self.intermediates[(index - 1) % 2].descriptor_set
+        };
+        let (render_pass, framebuffer) = if index + 1 == self.passes.len() {
+            (
+                self.swapchain_pass.handle,
+                self.swapchain_framebuffers[swapchain_image_index as usize].handle,
+            )
+        } else {
+            (self.intermediate_pass.handle, self.intermediates[index % 2].framebuffer.handle)
+        };
+
+        let rp_info = vk::RenderPassBeginInfoBuilder::new()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            });
+        let constants = PostPushConstants {
+            params: pass.params,
+            texel_size,
+        };
+        unsafe {
+            physical
+                .device
+                .cmd_begin_render_pass(cmd, &rp_info, vk::SubpassContents::INLINE);
+            physical.device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                pass.pipeline.pipelines[0],
+            );
+            physical.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                pass.pipeline.pipeline_layout,
+                0,
+                &[input],
+                &[],
+            );
+            physical.device.cmd_push_constants(
+                cmd,
+                pass.pipeline.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                size_of::<PostPushConstants>() as u32,
+                &constants as *const PostPushConstants as *const std::ffi::c_void,
+            );
+            //one triangle covering the screen, positions come from the vertex index
+            physical.device.cmd_draw(cmd, 3, 1, 0, 0);
+            physical.device.cmd_end_render_pass(cmd);
+        }
+    }
+}
+
+//Single color attachment that is completely overwritten, so its old contents are never loaded.
+//The render graph does the layout transitions and synchronization around it.
+pub fn create_render_pass(physical: &Physical, format: vk::Format) -> RenderPass {
+    let color_attachment = vk::AttachmentDescription2Builder::new()
+        .format(format)
+        .samples(vk::SampleCountFlagBits::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    let color_attachment_ref = [vk::AttachmentReference2Builder::new()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+    let subpass = vk::SubpassDescription2Builder::new()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_ref);
+
+    let attachments = [color_attachment];
+    let subpasses = [subpass];
+    let render_pass_info = vk::RenderPassCreateInfo2Builder::new()
+        .attachments(&attachments)
+        .subpasses(&subpasses);
+    let render_pass = unsafe {
+        physical
+            .device
+            .create_render_pass2(&render_pass_info, None, None)
+    }
+    .unwrap();
+    RenderPass::new(&physical.device, render_pass)
+}
+
+pub fn create_framebuffer(
+    physical: &Physical,
+    render_pass: vk::RenderPass,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+) -> Framebuffer {
+    let attachments = [view];
+    let framebuffer_info = vk::FramebufferCreateInfoBuilder::new()
+        .render_pass(render_pass)
+        .attachments(&attachments)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+    let framebuffer = unsafe {
+        physical
+            .device
+            .create_framebuffer(&framebuffer_info, None, None)
+    }
+    .unwrap();
+    Framebuffer::new(&physical.device, framebuffer)
+}
@@ -0,0 +1,750 @@
+//Passes declare which images and buffers they read and write, and how. From that the graph works out the order
+//they run in, drops passes nothing depends on, creates the transient images and computes every barrier and
+//layout transition once. Each frame it only has to record the precomputed barriers in between the passes,
+//the passes record their own commands.
+//
+//Render passes used inside the graph should keep their attachments in the layout the graph put them in
+//(initial and final layout equal to the subpass layout) and need no external subpass dependencies.
+use std::fmt::Write;
+
+use erupt::vk;
+
+use super::{
+    device::Physical,
+    handles::ImageView,
+    memory_stats::MemoryCategory,
+    mesh::AllocatedImage,
+    texture::{create_image, create_image_view},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+//How a pass uses a resource
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    ColorAttachment,
+    DepthAttachment,
+    SampledFragment,
+    //depth images sampled in the fragment shader, e.g. shadow maps
+    SampledDepthFragment,
+    SampledCompute,
+    StorageImageCompute,
+    TransferSrc,
+    TransferDst,
+    Present,
+    UniformRead,
+    VertexBufferRead,
+    IndexBufferRead,
+    IndirectRead,
+    StorageBufferReadCompute,
+    StorageBufferWriteCompute,
+    StorageBufferReadVertex,
+}
+
+struct AccessInfo {
+    layout: vk::ImageLayout,
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    write: bool,
+}
+
+impl Access {
+    fn info(self) -> AccessInfo {
+        let (layout, stage, access, write) = match self {
+            Access::ColorAttachment => (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                true,
+            ),
+            Access::DepthAttachment => (
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                true,
+            ),
+            Access::SampledFragment => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                false,
+            ),
+            Access::SampledDepthFragment => (
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                false,
+            ),
+            Access::SampledCompute => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                false,
+            ),
+            Access::StorageImageCompute => (
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                true,
+            ),
+            Access::TransferSrc => (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+                false,
+            ),
+            Access::TransferDst => (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+                true,
+            ),
+            //presentation is synchronized with the render semaphore
+            Access::Present => (
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::AccessFlags::empty(),
+                false,
+            ),
+            Access::UniformRead => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::UNIFORM_READ,
+                false,
+            ),
+            Access::VertexBufferRead => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                false,
+            ),
+            Access::IndexBufferRead => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::INDEX_READ,
+                false,
+            ),
+            Access::IndirectRead => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::AccessFlags::INDIRECT_COMMAND_READ,
+                false,
+            ),
+            Access::StorageBufferReadCompute => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                false,
+            ),
+            Access::StorageBufferWriteCompute => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                true,
+            ),
+            Access::StorageBufferReadVertex => (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                false,
+            ),
+        };
+        AccessInfo {
+            layout,
+            stage,
+            access,
+            write,
+        }
+    }
+
+    fn image_usage(self) -> vk::ImageUsageFlags {
+        match self {
+            Access::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            Access::DepthAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            Access::SampledFragment | Access::SampledDepthFragment | Access::SampledCompute => {
+                vk::ImageUsageFlags::SAMPLED
+            }
+            Access::StorageImageCompute => vk::ImageUsageFlags::STORAGE,
+            Access::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+            Access::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+            _ => vk::ImageUsageFlags::empty(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub layers: u32,
+    pub samples: vk::SampleCountFlagBits,
+}
+
+impl ImageDesc {
+    pub fn new(format: vk::Format, extent: vk::Extent2D) -> Self {
+        ImageDesc {
+            format,
+            extent,
+            layers: 1,
+            samples: vk::SampleCountFlagBits::_1,
+        }
+    }
+
+    fn aspect(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D32_SFLOAT | vk::Format::D16_UNORM => vk::ImageAspectFlags::DEPTH,
+            vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            }
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+}
+
+//Images handed over from outside the frame, like swapchain images
+#[derive(Copy, Clone, Debug)]
+pub struct External {
+    //stage the frame's submission waits in before the image can be used
+    pub wait_stage: vk::PipelineStageFlags,
+    //how the image is left at the end of the frame
+    pub final_access: Access,
+}
+
+enum ResourceKind {
+    //created by the graph and only used inside it
+    Transient,
+    //one handle per frame image index, created and destroyed elsewhere
+    ImportedImages(Vec<vk::Image>, Option<External>),
+    ImportedBuffers(Vec<vk::Buffer>),
+}
+
+struct Resource {
+    name: String,
+    desc: Option<ImageDesc>,
+    kind: ResourceKind,
+}
+
+struct Pass {
+    name: String,
+    reads: Vec<(ResourceId, Access)>,
+    writes: Vec<(ResourceId, Access)>,
+    //never culled even if nothing reads its output
+    keep: bool,
+}
+
+//Declaration of the frame, turned into a CompiledGraph by compile
+#[derive(Default)]
+pub struct RenderGraph {
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //An image only used inside the graph, its usage flags come from the passes using it
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ResourceId {
+        self.add_resource(name, Some(desc), ResourceKind::Transient)
+    }
+
+    //`images` are indexed by the image index passed to begin_step, a single image is used for every frame.
+    //Without `external` the image is assumed to stay in the graph's hands between frames.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        desc: ImageDesc,
+        images: Vec<vk::Image>,
+        external: Option<External>,
+    ) -> ResourceId {
+        self.add_resource(name, Some(desc), ResourceKind::ImportedImages(images, external))
+    }
+
+    pub fn import_buffer(&mut self, name: &str, buffers: Vec<vk::Buffer>) -> ResourceId {
+        self.add_resource(name, None, ResourceKind::ImportedBuffers(buffers))
+    }
+
+    fn add_resource(&mut self, name: &str, desc: Option<ImageDesc>, kind: ResourceKind) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.to_string(),
+            desc,
+            kind,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    //Resources a pass writes without also reading them have undefined contents at the start of the pass
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[(ResourceId, Access)],
+        writes: &[(ResourceId, Access)],
+    ) -> PassId {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            keep: false,
+        });
+        PassId(self.passes.len() - 1)
+    }
+
+    //Keeps a pass with side effects the graph doesn't know about
+    pub fn keep(&mut self, pass: PassId) {
+        self.passes[pass.0].keep = true;
+    }
+
+    pub fn compile(self, physical: &mut Physical) -> CompiledGraph {
+        let order = self.order();
+        let images = self.create_images(physical);
+
+        //run the frame twice, the state at the end of the first run is what the next frame starts with
+        let mut states: Vec<State> = self
+            .resources
+            .iter()
+            .map(|resource| match resource.kind {
+                ResourceKind::ImportedImages(_, Some(external)) => State::external(external.wait_stage),
+                _ => State::default(),
+            })
+            .collect();
+        self.simulate(&order, &mut states);
+        for (state, resource) in states.iter_mut().zip(self.resources.iter()) {
+            if let ResourceKind::ImportedImages(_, Some(external)) = resource.kind {
+                *state = State::external(external.wait_stage);
+            }
+        }
+        let steps = self.simulate(&order, &mut states);
+
+        //hand external images back in the layout their owner expects
+        let mut end_barriers = Barriers::default();
+        for (index, resource) in self.resources.iter().enumerate() {
+            if let ResourceKind::ImportedImages(_, Some(external)) = resource.kind {
+                states[index].transition(ResourceId(index), external.final_access, true, &mut end_barriers);
+            }
+        }
+
+        let culled = (0..self.passes.len())
+            .filter(|pass| !order.contains(pass))
+            .map(PassId)
+            .collect();
+
+        CompiledGraph {
+            resources: self.resources,
+            passes: self.passes,
+            images,
+            order,
+            steps,
+            end_barriers,
+            culled,
+        }
+    }
+
+    //Passes in dependency order, leaving out the ones whose results are never used
+    fn order(&self) -> Vec<usize> {
+        //a pass depends on the last earlier writer of everything it uses, and writes wait for earlier reads
+        let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (resource, _) in pass.reads.iter().chain(pass.writes.iter()) {
+                let writes = pass.writes.iter().any(|(written, _)| written == resource);
+                for earlier in (0..index).rev() {
+                    let other = &self.passes[earlier];
+                    let other_writes = other.writes.iter().any(|(written, _)| written == resource);
+                    let other_reads = other.reads.iter().any(|(read, _)| read == resource);
+                    if other_writes || (writes && other_reads) {
+                        dependencies[index].push(earlier);
+                    }
+                    if other_writes {
+                        break;
+                    }
+                }
+            }
+        }
+
+        //passes writing external images are the outputs of the frame, keep everything they need
+        let mut needed = vec![false; self.passes.len()];
+        let mut stack: Vec<usize> = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| {
+                pass.keep
+                    || pass.writes.iter().any(|(resource, _)| {
+                        matches!(
+                            self.resources[resource.0].kind,
+                            ResourceKind::ImportedImages(_, Some(_)) | ResourceKind::ImportedBuffers(_)
+                        )
+                    })
+            })
+            .map(|(index, _)| index)
+            .collect();
+        while let Some(pass) = stack.pop() {
+            if !needed[pass] {
+                needed[pass] = true;
+                stack.extend(dependencies[pass].iter().copied());
+            }
+        }
+
+        //dependencies always point to earlier passes, so declaration order is already a valid order
+        (0..self.passes.len()).filter(|pass| needed[*pass]).collect()
+    }
+
+    fn create_images(&self, physical: &mut Physical) -> Vec<Option<(ImageView, AllocatedImage)>> {
+        self.resources
+            .iter()
+            .enumerate()
+            .map(|(index, resource)| {
+                let desc = match (&resource.kind, resource.desc) {
+                    (ResourceKind::Transient, Some(desc)) => desc,
+                    _ => return None,
+                };
+                let accesses = self.passes.iter().flat_map(|pass| {
+                    pass.reads
+                        .iter()
+                        .chain(pass.writes.iter())
+                        .filter(|(resource, _)| resource.0 == index)
+                        .map(|(_, access)| *access)
+                });
+                let mut usage = vk::ImageUsageFlags::empty();
+                for access in accesses {
+                    usage |= access.image_usage();
+                }
+                //multisampled attachments only live inside their render pass
+                let attachment_usage =
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+                if desc.samples != vk::SampleCountFlagBits::_1 && attachment_usage.contains(usage) {
+                    usage |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+                }
+
+                let image_info = vk::ImageCreateInfoBuilder::new()
+                    .image_type(vk::ImageType::_2D)
+                    .format(desc.format)
+                    .extent(vk::Extent3D {
+                        width: desc.extent.width,
+                        height: desc.extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(desc.layers)
+                    .samples(desc.samples)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage);
+                let image = create_image(physical, &image_info, MemoryCategory::Attachment);
+                let view_type = if desc.layers > 1 {
+                    vk::ImageViewType::_2D_ARRAY
+                } else {
+                    vk::ImageViewType::_2D
+                };
+                let view = create_image_view(physical, image.image, desc.format, view_type, range(&desc));
+                Some((view, image))
+            })
+            .collect()
+    }
+
+    //Walks the passes in order, returning the barriers needed before each one
+    fn simulate(&self, order: &[usize], states: &mut [State]) -> Vec<Barriers> {
+        order
+            .iter()
+            .map(|&pass| {
+                let pass = &self.passes[pass];
+                let mut barriers = Barriers::default();
+                for (resource, access) in &pass.writes {
+                    //written and not read: the old contents don't matter
+                    let keep_contents = pass.reads.iter().any(|(read, _)| read == resource);
+                    states[resource.0].transition(*resource, *access, keep_contents, &mut barriers);
+                }
+                for (resource, access) in &pass.reads {
+                    if pass.writes.iter().any(|(written, _)| written == resource) {
+                        continue;
+                    }
+                    states[resource.0].transition(*resource, *access, true, &mut barriers);
+                }
+                barriers
+            })
+            .collect()
+    }
+}
+
+fn range(desc: &ImageDesc) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: desc.aspect(),
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: desc.layers,
+    }
+}
+
+//What happened to a resource last, as far as synchronization is concerned
+#[derive(Copy, Clone)]
+struct State {
+    layout: vk::ImageLayout,
+    //last write, and all reads since then
+    write_stage: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    read_stage: vk::PipelineStageFlags,
+    //stages that already see the last write
+    visible_stage: vk::PipelineStageFlags,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            layout: vk::ImageLayout::UNDEFINED,
+            write_stage: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            read_stage: vk::PipelineStageFlags::empty(),
+            visible_stage: vk::PipelineStageFlags::empty(),
+        }
+    }
+}
+
+impl State {
+    //acquired images: only the wait on the acquire semaphore has to be chained to
+    fn external(wait_stage: vk::PipelineStageFlags) -> Self {
+        State {
+            read_stage: wait_stage,
+            ..State::default()
+        }
+    }
+
+    fn transition(&mut self, resource: ResourceId, access: Access, keep_contents: bool, barriers: &mut Barriers) {
+        let info = access.info();
+        let layout_change = info.layout != vk::ImageLayout::UNDEFINED && info.layout != self.layout;
+        //read after write that isn't visible yet, or write after write
+        let after_write = !self.write_stage.is_empty()
+            && (info.write || !self.visible_stage.contains(info.stage));
+        //write after read only needs the reads to finish
+        let after_read = info.write && !self.read_stage.is_empty();
+
+        if layout_change || after_write || after_read {
+            let src_stage = self.write_stage | self.read_stage;
+            barriers.src_stage |= src_stage;
+            barriers.dst_stage |= info.stage;
+            barriers.barriers.push(Barrier {
+                resource,
+                old_layout: if keep_contents {
+                    self.layout
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                },
+                new_layout: if info.layout == vk::ImageLayout::UNDEFINED {
+                    self.layout
+                } else {
+                    info.layout
+                },
+                src_access: self.write_access,
+                dst_access: info.access,
+            });
+            self.visible_stage = info.stage;
+        } else {
+            self.visible_stage |= info.stage;
+        }
+
+        if info.layout != vk::ImageLayout::UNDEFINED {
+            self.layout = info.layout;
+        }
+        if info.write {
+            self.write_stage = info.stage;
+            self.write_access = info.access
+                & (vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                    | vk::AccessFlags::SHADER_WRITE
+                    | vk::AccessFlags::TRANSFER_WRITE);
+            self.read_stage = vk::PipelineStageFlags::empty();
+        } else {
+            self.read_stage |= info.stage;
+        }
+    }
+}
+
+struct Barrier {
+    resource: ResourceId,
+    //equal for buffers
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+#[derive(Default)]
+struct Barriers {
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    barriers: Vec<Barrier>,
+}
+
+pub struct CompiledGraph {
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+    images: Vec<Option<(ImageView, AllocatedImage)>>,
+    //indices into passes
+    order: Vec<usize>,
+    //barriers before each pass in order
+    steps: Vec<Barriers>,
+    end_barriers: Barriers,
+    culled: Vec<PassId>,
+}
+
+impl CompiledGraph {
+    //View of a transient image
+    pub fn image_view(&self, resource: ResourceId) -> vk::ImageView {
+        self.images[resource.0]
+            .as_ref()
+            .map(|(view, _)| view.handle)
+            .expect("not a transient image")
+    }
+
+    //Number of passes that run each frame
+    pub fn steps(&self) -> usize {
+        self.order.len()
+    }
+
+    //Records the barriers needed before the step'th pass and returns which pass that is, so the caller can record it
+    pub fn begin_step(&self, physical: &Physical, cmd: vk::CommandBuffer, step: usize, image_index: usize) -> PassId {
+        self.record_barriers(physical, cmd, &self.steps[step], image_index);
+        PassId(self.order[step])
+    }
+
+    //Transitions external images for whatever comes after the frame, after the last step
+    pub fn end_frame(&self, physical: &Physical, cmd: vk::CommandBuffer, image_index: usize) {
+        self.record_barriers(physical, cmd, &self.end_barriers, image_index);
+    }
+
+    fn record_barriers(&self, physical: &Physical, cmd: vk::CommandBuffer, barriers: &Barriers, image_index: usize) {
+        if barriers.barriers.is_empty() {
+            return;
+        }
+        let mut image_barriers = Vec::new();
+        let mut buffer_barriers = Vec::new();
+        for barrier in &barriers.barriers {
+            let resource = &self.resources[barrier.resource.0];
+            match &resource.kind {
+                ResourceKind::ImportedBuffers(buffers) => buffer_barriers.push(
+                    vk::BufferMemoryBarrierBuilder::new()
+                        .src_access_mask(barrier.src_access)
+                        .dst_access_mask(barrier.dst_access)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .buffer(buffers[image_index % buffers.len()])
+                        .offset(0)
+                        .size(vk::WHOLE_SIZE),
+                ),
+                kind => {
+                    let image = match kind {
+                        ResourceKind::ImportedImages(images, _) => images[image_index % images.len()],
+                        _ => self.images[barrier.resource.0].as_ref().unwrap().1.image,
+                    };
+                    image_barriers.push(
+                        vk::ImageMemoryBarrierBuilder::new()
+                            .src_access_mask(barrier.src_access)
+                            .dst_access_mask(barrier.dst_access)
+                            .old_layout(barrier.old_layout)
+                            .new_layout(barrier.new_layout)
+                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .image(image)
+                            .subresource_range(range(resource.desc.as_ref().unwrap())),
+                    );
+                }
+            }
+        }
+
+        let src_stage = if barriers.src_stage.is_empty() {
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        } else {
+            barriers.src_stage
+        };
+        unsafe {
+            physical.device.cmd_pipeline_barrier(
+                cmd,
+                src_stage,
+                barriers.dst_stage,
+                None,
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            );
+        }
+    }
+
+    //Human readable description of the compiled frame
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "render graph: {} passes ({} culled), {} resources",
+            self.order.len(),
+            self.culled.len(),
+            self.resources.len()
+        );
+        for (index, resource) in self.resources.iter().enumerate() {
+            let kind = match &resource.kind {
+                ResourceKind::Transient => "transient".to_string(),
+                ResourceKind::ImportedImages(images, None) => format!("imported x{}", images.len()),
+                ResourceKind::ImportedImages(images, Some(_)) => format!("external x{}", images.len()),
+                ResourceKind::ImportedBuffers(buffers) => format!("buffer x{}", buffers.len()),
+            };
+            match &resource.desc {
+                Some(desc) => {
+                    let _ = writeln!(
+                        out,
+                        "  resource {} '{}': {:?} {}x{}x{} {:?}, {}",
+                        index,
+                        resource.name,
+                        desc.format,
+                        desc.extent.width,
+                        desc.extent.height,
+                        desc.layers,
+                        desc.samples,
+                        kind
+                    );
+                }
+                None => {
+                    let _ = writeln!(out, "  resource {} '{}': {}", index, resource.name, kind);
+                }
+            }
+        }
+        for (step, &pass_index) in self.order.iter().enumerate() {
+            let pass = &self.passes[pass_index];
+            let _ = writeln!(out, "  step {}: pass '{}'", step, pass.name);
+            self.dump_barriers(&mut out, &self.steps[step]);
+            for (resource, access) in &pass.reads {
+                let _ = writeln!(out, "      reads  '{}' as {:?}", self.resources[resource.0].name, access);
+            }
+            for (resource, access) in &pass.writes {
+                let _ = writeln!(out, "      writes '{}' as {:?}", self.resources[resource.0].name, access);
+            }
+        }
+        let _ = writeln!(out, "  end of frame:");
+        self.dump_barriers(&mut out, &self.end_barriers);
+        for pass in &self.culled {
+            let _ = writeln!(out, "  culled pass '{}'", self.passes[pass.0].name);
+        }
+        out
+    }
+
+    fn dump_barriers(&self, out: &mut String, barriers: &Barriers) {
+        if barriers.barriers.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "    barrier {:?} -> {:?}", barriers.src_stage, barriers.dst_stage);
+        for barrier in &barriers.barriers {
+            let _ = writeln!(
+                out,
+                "      '{}': {:?} -> {:?}, {:?} -> {:?}",
+                self.resources[barrier.resource.0].name,
+                barrier.old_layout,
+                barrier.new_layout,
+                barrier.src_access,
+                barrier.dst_access
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use erupt::vk;
+
+use super::scene::Scene;
+
+extern crate nalgebra as na;
+
+//Bits given to each part of the sort key, highest bits are compared first.
+const PIPELINE_BITS: u32 = 12;
+const DESCRIPTOR_BITS: u32 = 12;
+const MESH_BITS: u32 = 16;
+const DEPTH_BITS: u32 = 24;
+const STATE_BITS: u32 = PIPELINE_BITS + DESCRIPTOR_BITS + MESH_BITS;
+
+pub struct RenderItem {
+    pub key: u64,
+    //index into Scene::objects
+    pub object: usize,
+}
+
+//How many binds and draws were recorded for the last frame
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BindStats {
+    pub pipeline_binds: u32,
+    pub descriptor_binds: u32,
+    pub vertex_buffer_binds: u32,
+    pub index_buffer_binds: u32,
+    pub draws: u32,
+}
+
+impl std::ops::AddAssign for BindStats {
+    fn add_assign(&mut self, other: Self) {
+        self.pipeline_binds += other.pipeline_binds;
+        self.descriptor_binds += other.descriptor_binds;
+        self.vertex_buffer_binds += other.vertex_buffer_binds;
+        self.index_buffer_binds += other.index_buffer_binds;
+        self.draws += other.draws;
+    }
+}
+
+pub struct RenderQueue {
+    pub opaque: Vec<RenderItem>,
+    pub transparent: Vec<RenderItem>,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        RenderQueue {
+            opaque: Vec::new(),
+            transparent: Vec::new(),
+        }
+    }
+
+    //Rebuilds the queue for the current camera position.
+    //Opaque objects are grouped by state and then drawn front-to-back inside a group,
+    //transparent objects are strictly back-to-front and only use the state to break ties.
+    pub fn build(&mut self, scene: &Scene, eye: &na::Point3<f32>) {
+        self.opaque.clear();
+        self.transparent.clear();
+
+        let mut pipeline_ids: HashMap<vk::Pipeline, u64> = HashMap::new();
+        let mut descriptor_ids: HashMap<Option<vk::DescriptorSet>, u64> = HashMap::new();
+        let mut mesh_ids: HashMap<&str, u64> = HashMap::new();
+
+        for (index, (mesh_name, material_name, transform)) in scene.objects.iter().enumerate() {
+            let material = match scene.materials.get(material_name) {
+                Some(material) => material,
+                None => continue,
+            };
+            if scene.mesh(mesh_name).is_none() {
+                continue;
+            }
+
+            let next = pipeline_ids.len() as u64;
+            let pipeline = *pipeline_ids
+                .entry(material.pipeline())
+                .or_insert(next);
+            let next = descriptor_ids.len() as u64;
+            let descriptor = *descriptor_ids
+                .entry(scene.material_descriptor(material))
+                .or_insert(next);
+            let next = mesh_ids.len() as u64;
+            let mesh = *mesh_ids.entry(mesh_name.as_str()).or_insert(next);
+
+            let state = (pipeline & mask(PIPELINE_BITS)) << (DESCRIPTOR_BITS + MESH_BITS)
+                | (descriptor & mask(DESCRIPTOR_BITS)) << MESH_BITS
+                | (mesh & mask(MESH_BITS));
+            let depth = depth_bits(eye, transform);
+
+            if material.transparent() {
+                let key = (mask(DEPTH_BITS) - depth) << STATE_BITS | state;
+                self.transparent.push(RenderItem { key, object: index });
+            } else {
+                let key = state << DEPTH_BITS | depth;
+                self.opaque.push(RenderItem { key, object: index });
+            }
+        }
+
+        self.opaque.sort_unstable_by_key(|item| item.key);
+        self.transparent.sort_unstable_by_key(|item| item.key);
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    (1 << bits) - 1
+}
+
+//Squared distance is positive, so its float bits sort the same way as the value itself.
+//Keeping only the top bits drops some mantissa precision, which is fine for ordering.
+fn depth_bits(eye: &na::Point3<f32>, transform: &na::Matrix4<f32>) -> u64 {
+    let position = na::Point3::new(transform[(0, 3)], transform[(1, 3)], transform[(2, 3)]);
+    let distance = na::distance_squared(eye, &position);
+    (distance.to_bits() >> (32 - DEPTH_BITS)) as u64
+}
@@ -1,92 +1,69 @@
-use super::{device::Physical, swapchain::Swapchain};
+use super::{
+    device::Physical,
+    handles::{self, Framebuffer},
+};
 
-use erupt::vk::{self, DeviceMemory};
-use gpu_alloc::MemoryBlock;
-use gpu_alloc_erupt::EruptMemoryDevice;
+use erupt::vk;
 
+//Format of the offscreen target the scene is rendered into
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+//The pass the scene is drawn in, rendering into the render graph's offscreen hdr image. The attachments stay in
+//their attachment layouts, the graph transitions them for whoever uses them next.
 pub struct RenderPass {
-    pub framebuffers: Vec<vk::Framebuffer>,
-    pub render_pass: vk::RenderPass,
-    depth_image: vk::Image,
-    depth_image_view: vk::ImageView,
-    depth_image_allocation: Option<MemoryBlock<DeviceMemory>>,
+    pub framebuffer: Framebuffer,
+    pub render_pass: handles::RenderPass,
+    //pipelines used in this render pass need the same rasterization_samples
+    pub samples: vk::SampleCountFlagBits,
 }
 
 impl RenderPass {
-    pub fn new(physical: &mut Physical, swapchain: &Swapchain) -> Self {
-        let extent_3d = vk::Extent3DBuilder::new()
-            .width(physical.surface_caps.current_extent.width)
-            .height(physical.surface_caps.current_extent.height)
-            .depth(1);
-
-        //create depth images
-        let image_create_info = vk::ImageCreateInfoBuilder::new()
-            .image_type(vk::ImageType::_2D)
-            .format(vk::Format::D32_SFLOAT)
-            .extent(extent_3d.build())
-            .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlagBits::_1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT);
-
-        let image = unsafe {
-            physical
-                .device
-                .create_image(&image_create_info, None, None)
-                .unwrap()
-        };
-        let mem_requirements =
-            unsafe { physical.device.get_image_memory_requirements(image, None) };
-        let alloc_request = gpu_alloc::Request {
-            size: mem_requirements.size,
-            align_mask: mem_requirements.alignment,
-            usage: gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
-            memory_types: mem_requirements.memory_type_bits,
+    //`color_view` is the multisampled color target that gets resolved into `hdr_view`, None without MSAA.
+    //Without `clear` the pass draws on top of what is already in the hdr and depth images, which the deferred
+    //path uses for transparent objects and the skybox after the lighting pass.
+    pub fn new(
+        physical: &mut Physical,
+        samples: vk::SampleCountFlagBits,
+        hdr_view: vk::ImageView,
+        depth_view: vk::ImageView,
+        color_view: Option<vk::ImageView>,
+        clear: bool,
+    ) -> Self {
+        let msaa = color_view.is_some();
+        let load_op = if clear {
+            vk::AttachmentLoadOp::CLEAR
+        } else {
+            vk::AttachmentLoadOp::LOAD
         };
-        let block = unsafe {
-            physical
-                .allocator
-                .alloc(EruptMemoryDevice::wrap(&physical.device), alloc_request)
-        }
-        .unwrap();
-
-        unsafe {
-            physical
-                .device
-                .bind_image_memory(image, *block.memory(), 0)
-                .unwrap();
-        }
-
-        let image_view_create_info = vk::ImageViewCreateInfoBuilder::new()
-            .view_type(vk::ImageViewType::_2D)
-            .image(image)
-            .format(vk::Format::D32_SFLOAT)
-            .subresource_range(
-                *vk::ImageSubresourceRangeBuilder::new()
-                    .base_mip_level(0)
-                    .level_count(1)
-                    .base_array_layer(0)
-                    .layer_count(1)
-                    .aspect_mask(vk::ImageAspectFlags::DEPTH),
-            );
-
-        let depth_image_view = unsafe {
-            physical
-                .device
-                .create_image_view(&image_view_create_info, None, None)
-        }
-        .unwrap();
 
         let color_attachment = vk::AttachmentDescription2Builder::new()
-            .format(physical.format.format)
+            .format(HDR_FORMAT)
+            .samples(samples)
+            .load_op(load_op)
+            .store_op(if msaa {
+                vk::AttachmentStoreOp::DONT_CARE
+            } else {
+                vk::AttachmentStoreOp::STORE
+            })
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        //the hdr image, written once at the end of the subpass
+        let resolve_attachment = vk::AttachmentDescription2Builder::new()
+            .format(HDR_FORMAT)
             .samples(vk::SampleCountFlagBits::_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
             .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+            .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let resolve_attachment_ref = vk::AttachmentReference2Builder::new()
+            .attachment(2)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
         let color_attachment_ref = vk::AttachmentReference2Builder::new()
             .attachment(0)
@@ -94,26 +71,34 @@ impl RenderPass {
 
         let depth_attachment = vk::AttachmentDescription2Builder::new()
             .flags(vk::AttachmentDescriptionFlags::empty())
-            .format(vk::Format::D32_SFLOAT)
-            .samples(vk::SampleCountFlagBits::_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .format(DEPTH_FORMAT)
+            .samples(samples)
+            .load_op(load_op)
             .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::CLEAR)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
         let depth_attachment_ref = vk::AttachmentReference2Builder::new()
             .attachment(1)
             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
         let color_attach_slice = &[color_attachment_ref];
+        let resolve_attach_slice = &[resolve_attachment_ref];
 
-        let subpass = vk::SubpassDescription2Builder::new()
+        let mut subpass = vk::SubpassDescription2Builder::new()
             .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
             .color_attachments(color_attach_slice)
             .depth_stencil_attachment(&depth_attachment_ref);
+        if msaa {
+            subpass = subpass.resolve_attachments(resolve_attach_slice);
+        }
+
+        let mut attachments = vec![color_attachment, depth_attachment];
+        if msaa {
+            attachments.push(resolve_attachment);
+        }
 
-        let attachments = [color_attachment, depth_attachment];
         let subpasses = [subpass];
         let render_pass_info = vk::RenderPassCreateInfo2Builder::new()
             .attachments(&attachments)
@@ -125,56 +110,30 @@ impl RenderPass {
                 .create_render_pass2(&render_pass_info, None, None)
         }
         .unwrap();
+        let render_pass = handles::RenderPass::new(&physical.device, render_pass);
 
-        //TODO Clean this up
-        let framebuffers: Vec<_> = swapchain
-            .image_views
-            .iter()
-            .map(|image_view| {
-                let attachments = vec![*image_view, depth_image_view];
-                let framebuffer_info = vk::FramebufferCreateInfoBuilder::new()
-                    .render_pass(render_pass)
-                    .attachments(&attachments)
-                    .width(physical.surface_caps.current_extent.width)
-                    .height(physical.surface_caps.current_extent.height)
-                    .layers(1);
-
-                unsafe {
-                    physical
-                        .device
-                        .create_framebuffer(&framebuffer_info, None, None)
-                }
-                .unwrap()
-            })
-            .collect();
+        let framebuffer_attachments = match color_view {
+            Some(color_view) => vec![color_view, depth_view, hdr_view],
+            None => vec![hdr_view, depth_view],
+        };
+        let framebuffer_info = vk::FramebufferCreateInfoBuilder::new()
+            .render_pass(render_pass.handle)
+            .attachments(&framebuffer_attachments)
+            .width(physical.surface_caps.current_extent.width)
+            .height(physical.surface_caps.current_extent.height)
+            .layers(1);
+        let framebuffer = unsafe {
+            physical
+                .device
+                .create_framebuffer(&framebuffer_info, None, None)
+        }
+        .unwrap();
+        let framebuffer = Framebuffer::new(&physical.device, framebuffer);
 
         RenderPass {
-            framebuffers,
+            framebuffer,
             render_pass,
-            depth_image: image,
-            depth_image_view,
-            depth_image_allocation: Some(block),
-        }
-    }
-
-    pub fn cleanup(&mut self, physical: &mut Physical) {
-        unsafe {
-            physical.device.destroy_image(Some(self.depth_image), None);
-            physical
-                .device
-                .destroy_image_view(Some(self.depth_image_view), None);
-            physical.allocator.dealloc(
-                EruptMemoryDevice::wrap(&physical.device),
-                self.depth_image_allocation.take().unwrap(),
-            );
-            for framebuffer in self.framebuffers.iter() {
-                physical
-                    .device
-                    .destroy_framebuffer(Some(*framebuffer), None);
-            }
-            physical
-                .device
-                .destroy_render_pass(Some(self.render_pass), None)
+            samples,
         }
     }
 }
@@ -1,20 +1,151 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
 
+use bytemuck::Zeroable;
 use erupt::vk;
-use gpu_alloc_erupt::EruptMemoryDevice;
-use nalgebra::Isometry3;
+use nalgebra::Matrix4;
 
-use super::{device::Physical, mesh::Mesh, pipeline::PipelineStruct};
+extern crate nalgebra as na;
+
+use super::{
+    assets::LoadState,
+    deletion::DeletionQueue,
+    device::Physical,
+    descriptors::Descriptors,
+    frame::GPUSceneData,
+    light::{GPULight, Light, LightKind, MAX_LIGHTS},
+    material::MaterialResources,
+    mesh::{AllocatedBuffer, Mesh},
+    mesh_pool::MeshPool,
+    pipeline::{PipelineStruct, BLENDED_PIPELINE, OPAQUE_PIPELINE},
+    shadow::MAX_CASCADES,
+    skybox::Skybox,
+    texture::Texture,
+    upload::Uploaded,
+};
+
+//Name of the material VulkanApp::new creates, drawn with when a material couldn't get its own descriptor set
+pub const DEFAULT_MATERIAL: &str = "default";
+
+//How the alpha of the base color is used
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AlphaMode {
+    Opaque,
+    //fragments with an alpha below the cutoff are discarded
+    Mask(f32),
+    //blended over what is behind, drawn after the opaque objects without writing depth
+    Blend,
+}
+
+//Surface properties as they come from the source asset.
+//Textures are referenced by their name in Scene::textures.
+#[derive(PartialEq, Clone, Debug)]
+pub struct MaterialParams {
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub opacity: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub alpha_mode: AlphaMode,
+    pub diffuse_texture: Option<String>,
+    pub specular_texture: Option<String>,
+    pub normal_texture: Option<String>,
+    pub metallic_roughness_texture: Option<String>,
+    pub occlusion_texture: Option<String>,
+    pub emissive_texture: Option<String>,
+}
+
+impl MaterialParams {
+    pub fn uses_texture(&self, name: &str) -> bool {
+        [
+            &self.diffuse_texture,
+            &self.specular_texture,
+            &self.normal_texture,
+            &self.metallic_roughness_texture,
+            &self.occlusion_texture,
+            &self.emissive_texture,
+        ]
+        .iter()
+        .any(|texture| texture.as_deref() == Some(name))
+    }
+}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        MaterialParams {
+            diffuse: [1.0, 1.0, 1.0],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 0.0,
+            opacity: 1.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: [0.0, 0.0, 0.0],
+            alpha_mode: AlphaMode::Opaque,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
 
-#[derive(PartialEq)]
 pub struct Material {
-    pub pipeline: PipelineStruct,
+    //shared with the other materials made for the same shaders
+    pub pipeline: Rc<PipelineStruct>,
+    pub params: MaterialParams,
+    //per material resources, bound to set 1 when present
+    pub descriptor_set: Option<vk::DescriptorSet>,
+    //params as GPUMaterialData, read through the descriptor set
+    pub uniform_buffer: Option<AllocatedBuffer>,
+}
+
+impl Material {
+    //blended materials are drawn after opaque ones, sorted back-to-front
+    pub fn transparent(&self) -> bool {
+        self.params.alpha_mode == AlphaMode::Blend
+    }
+
+    //the variant of the material's pipeline matching its alpha mode
+    pub fn pipeline(&self) -> vk::Pipeline {
+        let variant = if self.transparent() {
+            BLENDED_PIPELINE
+        } else {
+            OPAQUE_PIPELINE
+        };
+        self.pipeline.pipelines[variant]
+    }
+}
+
+//Transform hierarchy as it was in the imported file.
+pub struct Node {
+    pub parent: Option<usize>,
+    //relative to the parent
+    pub transform: Matrix4<f32>,
+    //render objects placed by this node, indices into Scene::objects
+    pub objects: Vec<usize>,
 }
 
 pub struct Scene {
-    pub objects: Vec<(String, String, nalgebra::Isometry3<f32>)>,
+    pub objects: Vec<(String, String, Matrix4<f32>)>,
     pub meshes: HashMap<String, Mesh>,
     pub materials: HashMap<String, Material>,
+    pub textures: HashMap<String, Texture>,
+    //meshes and textures that are loaded in the background, ones added directly have no entry
+    pub mesh_states: HashMap<String, LoadState>,
+    pub texture_states: HashMap<String, LoadState>,
+    //drawn for objects whose mesh is still loading or failed to load, materials use their default textures meanwhile
+    pub placeholder_mesh: Option<Mesh>,
+    //vertices and indices of all the meshes above
+    pub mesh_pool: MeshPool,
+    pub nodes: Vec<Node>,
+    pub lights: Vec<Light>,
+    pub ambient_color: [f32; 3],
+    //background where nothing is drawn, hidden behind the skybox if there is one
+    pub clear_color: [f32; 4],
+    pub skybox: Option<Skybox>,
 }
 
 impl Scene {
@@ -23,6 +154,114 @@ impl Scene {
             objects: Vec::new(),
             meshes: HashMap::new(),
             materials: HashMap::new(),
+            textures: HashMap::new(),
+            mesh_states: HashMap::new(),
+            texture_states: HashMap::new(),
+            placeholder_mesh: None,
+            mesh_pool: MeshPool::new(),
+            nodes: Vec::new(),
+            lights: Vec::new(),
+            ambient_color: [0.03, 0.03, 0.03],
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            skybox: None,
+        }
+    }
+
+    //The mesh objects named `name` draw with, the placeholder while it is loading
+    pub fn mesh(&self, name: &str) -> Option<&Mesh> {
+        match self.meshes.get(name) {
+            Some(mesh) => Some(mesh),
+            None => match self.mesh_states.get(name) {
+                Some(LoadState::Pending) | Some(LoadState::Failed(_)) => self.placeholder_mesh.as_ref(),
+                _ => None,
+            },
+        }
+    }
+
+    //Puts a finished upload in place of its placeholder. Returns the name of an arrived texture, the descriptor
+    //sets of the materials using it still point at the default texture. A mesh or texture loaded again replaces the
+    //old one, which is destroyed once the frames in flight are done with it.
+    pub fn finish_upload(&mut self, uploaded: Uploaded, deletion: &mut DeletionQueue, framenumber: i64) -> Option<String> {
+        match uploaded {
+            Uploaded::Mesh(name, mesh) => {
+                self.mesh_states.insert(name.clone(), LoadState::Loaded);
+                if let Some(old) = self.meshes.insert(name, mesh) {
+                    deletion.free_mesh(framenumber, old);
+                }
+                None
+            }
+            Uploaded::Texture(name, texture) => {
+                self.texture_states.insert(name.clone(), LoadState::Loaded);
+                if let Some(old) = self.textures.insert(name.clone(), texture) {
+                    deletion.release(framenumber, old);
+                }
+                Some(name)
+            }
+        }
+    }
+
+    //Takes a mesh out of the scene, objects using it stop drawing. The pool ranges are freed once no frame draws it.
+    pub fn remove_mesh(&mut self, name: &str, deletion: &mut DeletionQueue, framenumber: i64) {
+        self.mesh_states.remove(name);
+        if let Some(mesh) = self.meshes.remove(name) {
+            deletion.free_mesh(framenumber, mesh);
+        }
+    }
+
+    //Takes a material out of the scene, objects using it stop drawing. Its pipeline is only destroyed when no other
+    //material shares it.
+    pub fn remove_material(
+        &mut self,
+        name: &str,
+        descs: &Descriptors,
+        deletion: &mut DeletionQueue,
+        framenumber: i64,
+    ) {
+        let material = match self.materials.remove(name) {
+            Some(material) => material,
+            None => return,
+        };
+        if let Some(descriptor_set) = material.descriptor_set {
+            deletion.free_descriptor_set(framenumber, descs.descriptor_pool.handle, descriptor_set);
+        }
+        deletion.release(framenumber, material.pipeline);
+        deletion.release(framenumber, material.uniform_buffer);
+    }
+
+    //Returns false and leaves the light out if the scene already has MAX_LIGHTS, the GPU data has no room for more
+    pub fn add_light(&mut self, light: Light) -> bool {
+        if self.lights.len() >= MAX_LIGHTS {
+            println!("scene already has {} lights, the light is not added", MAX_LIGHTS);
+            return false;
+        }
+        self.lights.push(light);
+        true
+    }
+
+    //The first directional light casts the shadows
+    pub fn shadow_light(&self) -> Option<usize> {
+        self.lights
+            .iter()
+            .take(MAX_LIGHTS)
+            .position(|light| light.kind == LightKind::Directional)
+    }
+
+    //Lighting uniform for the frame with shadows turned off, only the first MAX_LIGHTS lights are used
+    pub fn gpu_scene_data(&self, camera_position: &na::Point3<f32>) -> GPUSceneData {
+        let mut lights = [GPULight::zeroed(); MAX_LIGHTS];
+        for (gpu_light, light) in lights.iter_mut().zip(self.lights.iter()) {
+            *gpu_light = light.to_gpu();
+        }
+        let [r, g, b] = self.ambient_color;
+        GPUSceneData {
+            ambient_color: [r, g, b, 1.0],
+            camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+            light_count: [self.lights.len().min(MAX_LIGHTS) as u32, 0, 0, 0],
+            shadow_params: [0.0, 0.0, 0.0, -1.0],
+            cascade_splits: [0.0; MAX_CASCADES],
+            cascade_texel_sizes: [0.0; MAX_CASCADES],
+            light_viewproj: [na::Matrix4::identity(); MAX_CASCADES],
+            lights,
         }
     }
 
@@ -32,14 +271,14 @@ impl Scene {
         mesh_name: &str,
         material: Material,
         material_name: &str,
-        translation_matrix: Isometry3<f32>,
+        translation_matrix: impl Into<Matrix4<f32>>,
     ) {
         self.meshes.insert(mesh_name.to_string(), mesh);
         self.materials.insert(material_name.to_string(), material);
         self.objects.push((
             mesh_name.to_string(),
             material_name.to_string(),
-            translation_matrix,
+            translation_matrix.into(),
         ));
     }
 
@@ -48,13 +287,13 @@ impl Scene {
         mesh: Mesh,
         mesh_name: &str,
         material_name: &str,
-        translation_matrix: Isometry3<f32>,
+        translation_matrix: impl Into<Matrix4<f32>>,
     ) {
         self.meshes.insert(mesh_name.to_string(), mesh);
         self.objects.push((
             mesh_name.to_string(),
             material_name.to_string(),
-            translation_matrix,
+            translation_matrix.into(),
         ));
     }
 
@@ -62,34 +301,83 @@ impl Scene {
         &mut self,
         mesh_name: &str,
         material_name: &str,
-        translation_matrix: Isometry3<f32>,
+        translation_matrix: impl Into<Matrix4<f32>>,
     ) {
         self.objects.push((
             mesh_name.to_string(),
             material_name.to_string(),
-            translation_matrix,
+            translation_matrix.into(),
         ));
     }
 
-    pub fn cleanup(&mut self, physical: &mut Physical) {
-        unsafe {
-            for (_, mesh) in self.meshes.iter_mut() {
-                physical.allocator.dealloc(
-                    EruptMemoryDevice::wrap(&physical.device),
-                    mesh.vertex_buffer.allocation.take().unwrap(),
-                );
-                physical
-                    .device
-                    .destroy_buffer(Some(mesh.vertex_buffer.buffer), None);
+    //returns the index of the new node
+    pub fn add_node(&mut self, parent: Option<usize>, transform: Matrix4<f32>) -> usize {
+        self.nodes.push(Node {
+            parent,
+            transform,
+            objects: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    //Transform of the node relative to the scene root
+    pub fn world_transform(&self, node: usize) -> Matrix4<f32> {
+        let mut transform = self.nodes[node].transform;
+        let mut parent = self.nodes[node].parent;
+        while let Some(index) = parent {
+            transform = self.nodes[index].transform * transform;
+            parent = self.nodes[index].parent;
+        }
+        transform
+    }
+
+    //Uploads the parameters of every material that doesn't have a descriptor set yet, call after loading
+    pub fn create_material_descriptors(
+        &mut self,
+        physical: &mut Physical,
+        descs: &Descriptors,
+        resources: &MaterialResources,
+    ) {
+        //the default material goes first, it stands in for the materials that don't get a set
+        let mut names: Vec<String> = self.materials.keys().cloned().collect();
+        names.sort_by_key(|name| name != DEFAULT_MATERIAL);
+        for name in names {
+            let material = self.materials.get_mut(&name).unwrap();
+            if material.descriptor_set.is_some() {
+                continue;
             }
-            for (_, material) in self.materials.iter() {
-                physical
-                    .device
-                    .destroy_pipeline_layout(Some(material.pipeline.pipeline_layout), None);
-                for pipeline in &material.pipeline.pipelines {
-                    physical.device.destroy_pipeline(Some(*pipeline), None);
+            match resources.create_descriptor_set(physical, descs, &self.textures, &material.params) {
+                Some((buffer, descriptor_set)) => {
+                    material.uniform_buffer = Some(buffer);
+                    material.descriptor_set = Some(descriptor_set);
                 }
+                None => println!("out of material descriptor sets, {} is drawn with the default material", name),
             }
         }
     }
+
+    //The material's descriptor set, or the default material's if it has none
+    pub fn material_descriptor(&self, material: &Material) -> Option<vk::DescriptorSet> {
+        material.descriptor_set.or_else(|| {
+            self.materials
+                .get(DEFAULT_MATERIAL)
+                .and_then(|default| default.descriptor_set)
+        })
+    }
+}
+
+//Keeps a name from an imported file unless the scene already uses it, in which case it gets prefixed with the file name,
+//and numbered if that is taken too. Importing the same file twice must not replace what the first import made.
+pub fn unique_name<T>(existing: &HashMap<String, T>, file_name: &str, name: &str) -> String {
+    if !existing.contains_key(name) {
+        return name.to_string();
+    }
+    let prefixed = format!("{}/{}", file_name, name);
+    let mut unique = prefixed.clone();
+    let mut suffix = 1;
+    while existing.contains_key(&unique) {
+        unique = format!("{}.{}", prefixed, suffix);
+        suffix += 1;
+    }
+    unique
 }
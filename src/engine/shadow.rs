@@ -0,0 +1,408 @@
+use erupt::vk;
+
+extern crate nalgebra as na;
+
+use super::{
+    device::Physical,
+    handles::{Framebuffer, ImageView, RenderPass, Sampler},
+    memory_stats::MemoryCategory,
+    frame::GPUSceneData,
+    mesh::AllocatedImage,
+    pipeline::{MeshPushConstants, PipelineStruct},
+    scene::Scene,
+    texture::{create_image, create_image_view},
+};
+
+//Has to match MAX_CASCADES in the lit shaders
+pub const MAX_CASCADES: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct ShadowSettings {
+    //width and height of every cascade
+    pub resolution: u32,
+    //1 to MAX_CASCADES
+    pub cascade_count: usize,
+    //how the camera frustum is split between cascades: 0 evenly, 1 logarithmic
+    pub split_lambda: f32,
+    //shadows end at this distance from the camera, or the camera's far plane if that is closer
+    pub max_distance: f32,
+    //depth bias applied while rendering the shadow map
+    pub depth_bias_constant: f32,
+    pub depth_bias_slope: f32,
+    //offset of the receiver along its normal when sampling, in shadow map texels
+    pub normal_bias: f32,
+    //how far behind the visible area objects still cast shadows into it
+    pub caster_margin: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 2048,
+            cascade_count: 4,
+            split_lambda: 0.75,
+            max_distance: 100.0,
+            depth_bias_constant: 1.25,
+            depth_bias_slope: 1.75,
+            normal_bias: 1.5,
+            caster_margin: 50.0,
+        }
+    }
+}
+
+//Light space matrices for one frame
+pub struct Cascades {
+    pub view_proj: [na::Matrix4<f32>; MAX_CASCADES],
+    //far end of each cascade as distance along the camera's view direction
+    pub splits: [f32; MAX_CASCADES],
+    //world space size of a shadow map texel in each cascade
+    pub texel_sizes: [f32; MAX_CASCADES],
+    pub count: usize,
+}
+
+//Depth only rendering of the scene from the main directional light, one array layer per cascade.
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    pub render_pass: RenderPass,
+    pub pipeline: PipelineStruct,
+    framebuffers: Vec<Framebuffer>,
+    //whole array, sampled in the lit shaders
+    pub array_view: ImageView,
+    #[allow(dead_code)]
+    layer_views: Vec<ImageView>,
+    //compares against the stored depth, so filtering gives the fraction of lit samples
+    pub sampler: Sampler,
+    //imported into the render graph, which transitions it between rendering and sampling.
+    //Dropped after the views of it.
+    pub image: AllocatedImage,
+}
+
+impl ShadowMap {
+    pub fn new(physical: &mut Physical, settings: ShadowSettings) -> Self {
+        let settings = ShadowSettings {
+            cascade_count: settings.cascade_count.clamp(1, MAX_CASCADES),
+            ..settings
+        };
+        let layers = settings.cascade_count as u32;
+
+        let image_info = vk::ImageCreateInfoBuilder::new()
+            .image_type(vk::ImageType::_2D)
+            .format(vk::Format::D32_SFLOAT)
+            .extent(vk::Extent3D {
+                width: settings.resolution,
+                height: settings.resolution,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlagBits::_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED);
+        let image = create_image(physical, &image_info, MemoryCategory::Attachment);
+
+        let range = |base_array_layer, layer_count| vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer,
+            layer_count,
+        };
+        let array_view = create_image_view(
+            physical,
+            image.image,
+            vk::Format::D32_SFLOAT,
+            vk::ImageViewType::_2D_ARRAY,
+            range(0, layers),
+        );
+        let layer_views: Vec<ImageView> = (0..layers)
+            .map(|layer| {
+                create_image_view(
+                    physical,
+                    image.image,
+                    vk::Format::D32_SFLOAT,
+                    vk::ImageViewType::_2D,
+                    range(layer, 1),
+                )
+            })
+            .collect();
+
+        let depth_attachment = vk::AttachmentDescription2Builder::new()
+            .format(vk::Format::D32_SFLOAT)
+            .samples(vk::SampleCountFlagBits::_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        let depth_attachment_ref = vk::AttachmentReference2Builder::new()
+            .attachment(0)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let subpass = vk::SubpassDescription2Builder::new()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .depth_stencil_attachment(&depth_attachment_ref);
+
+        let attachments = [depth_attachment];
+        let subpasses = [subpass];
+        let render_pass_info = vk::RenderPassCreateInfo2Builder::new()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+        let render_pass = unsafe {
+            physical
+                .device
+                .create_render_pass2(&render_pass_info, None, None)
+        }
+        .unwrap();
+        let render_pass = RenderPass::new(&physical.device, render_pass);
+
+        let framebuffers = layer_views
+            .iter()
+            .map(|view| {
+                let attachments = [view.handle];
+                let framebuffer_info = vk::FramebufferCreateInfoBuilder::new()
+                    .render_pass(render_pass.handle)
+                    .attachments(&attachments)
+                    .width(settings.resolution)
+                    .height(settings.resolution)
+                    .layers(1);
+                let framebuffer = unsafe {
+                    physical
+                        .device
+                        .create_framebuffer(&framebuffer_info, None, None)
+                }
+                .unwrap();
+                Framebuffer::new(&physical.device, framebuffer)
+            })
+            .collect();
+
+        let sampler_info = vk::SamplerCreateInfoBuilder::new()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL);
+        let sampler = Sampler::new(
+            &physical.device,
+            unsafe { physical.device.create_sampler(&sampler_info, None, None) }.unwrap(),
+        );
+
+        let pipeline = PipelineStruct::shadow(physical, render_pass.handle, &settings);
+
+        ShadowMap {
+            settings,
+            render_pass,
+            pipeline,
+            framebuffers,
+            array_view,
+            layer_views,
+            sampler,
+            image,
+        }
+    }
+
+    //Fits one orthographic projection around each slice of the camera frustum.
+    //`light_direction` is the direction the light shines in, `view` the camera's view transform.
+    pub fn cascades(
+        &self,
+        light_direction: &na::Vector3<f32>,
+        view: &na::Isometry3<f32>,
+        fov_y: f32,
+        aspect: f32,
+        near: f32,
+        far: f32,
+    ) -> Cascades {
+        let settings = &self.settings;
+        let count = settings.cascade_count;
+        let far = far.min(settings.max_distance);
+        let direction = light_direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            na::Vector3::z()
+        } else {
+            na::Vector3::y()
+        };
+        let tan_half_y = (fov_y / 2.0).tan();
+        let tan_half_x = tan_half_y * aspect;
+        let camera_to_world = view.inverse();
+
+        let mut cascades = Cascades {
+            view_proj: [na::Matrix4::identity(); MAX_CASCADES],
+            splits: [far; MAX_CASCADES],
+            texel_sizes: [0.0; MAX_CASCADES],
+            count,
+        };
+
+        let mut split_near = near;
+        for cascade in 0..count {
+            //blend between logarithmic splits (even texel density) and uniform ones
+            let p = (cascade + 1) as f32 / count as f32;
+            let log = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            let split_far = settings.split_lambda * log + (1.0 - settings.split_lambda) * uniform;
+
+            let mut corners = Vec::with_capacity(8);
+            for distance in [split_near, split_far] {
+                for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+                    let point = na::Point3::new(
+                        x * distance * tan_half_x,
+                        y * distance * tan_half_y,
+                        -distance,
+                    );
+                    corners.push(camera_to_world * point);
+                }
+            }
+            let center = corners
+                .iter()
+                .fold(na::Vector3::zeros(), |sum, corner| sum + corner.coords)
+                / corners.len() as f32;
+            let center = na::Point3::from(center);
+            //a bounding sphere keeps the projection size constant while the camera rotates,
+            //rounded so float noise doesn't change it either
+            let radius = corners
+                .iter()
+                .map(|corner| na::distance(corner, &center))
+                .fold(0.0f32, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            let eye = center - direction * (radius + settings.caster_margin);
+            let light_view = na::Isometry3::look_at_rh(&eye, &center, &up).to_homogeneous();
+            let mut projection =
+                orthographic(-radius, radius, -radius, radius, 0.0, 2.0 * radius + settings.caster_margin);
+
+            //snap to whole texels so shadow edges don't crawl when the camera moves
+            let texels = settings.resolution as f32 / 2.0;
+            let origin = projection * light_view * na::Vector4::new(0.0, 0.0, 0.0, 1.0);
+            let offset_x = (origin.x * texels).round() / texels - origin.x;
+            let offset_y = (origin.y * texels).round() / texels - origin.y;
+            projection[(0, 3)] += offset_x;
+            projection[(1, 3)] += offset_y;
+
+            cascades.view_proj[cascade] = projection * light_view;
+            cascades.splits[cascade] = split_far;
+            cascades.texel_sizes[cascade] = 2.0 * radius / settings.resolution as f32;
+            split_near = split_far;
+        }
+
+        cascades
+    }
+
+    //Renders every opaque object into each cascade, as the render graph's shadow pass
+    pub fn record(&self, physical: &Physical, cmd: vk::CommandBuffer, scene: &Scene, cascades: &Cascades) {
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        let has_light = scene.shadow_light().is_some();
+
+        for cascade in 0..cascades.count {
+            let rp_info = vk::RenderPassBeginInfoBuilder::new()
+                .render_pass(self.render_pass.handle)
+                .framebuffer(self.framebuffers[cascade].handle)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: vk::Extent2D {
+                        width: self.settings.resolution,
+                        height: self.settings.resolution,
+                    },
+                })
+                .clear_values(&clear_values);
+            unsafe {
+                physical
+                    .device
+                    .cmd_begin_render_pass(cmd, &rp_info, vk::SubpassContents::INLINE);
+            }
+
+            //the map is still cleared without a light, the lit shaders bind it either way
+            if has_light {
+                unsafe {
+                    physical.device.cmd_bind_pipeline(
+                        cmd,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline.pipelines[0],
+                    );
+                }
+                let mut last_vertex_buffer: Option<vk::Buffer> = None;
+                let mut last_index_buffer: Option<vk::Buffer> = None;
+                for (mesh_name, material_name, transform) in &scene.objects {
+                    match scene.materials.get(material_name) {
+                        Some(material) if !material.transparent() => {}
+                        _ => continue,
+                    }
+                    let mesh = match scene.mesh(mesh_name) {
+                        Some(mesh) => mesh,
+                        None => continue,
+                    };
+                    let constants = MeshPushConstants {
+                        render_matrix: cascades.view_proj[cascade] * transform,
+                        model: *transform,
+                    };
+                    unsafe {
+                        physical.device.cmd_push_constants(
+                            cmd,
+                            self.pipeline.pipeline_layout,
+                            vk::ShaderStageFlags::VERTEX,
+                            0,
+                            std::mem::size_of::<MeshPushConstants>() as u32,
+                            &constants as *const MeshPushConstants as *const std::ffi::c_void,
+                        );
+                        let vertex_buffer = scene.mesh_pool.vertex_buffer(mesh.vertex_range.chunk);
+                        if last_vertex_buffer != Some(vertex_buffer) {
+                            physical.device.cmd_bind_vertex_buffers(cmd, 0, &[vertex_buffer], &[0]);
+                            last_vertex_buffer = Some(vertex_buffer);
+                        }
+                        let index_buffer = scene.mesh_pool.index_buffer(mesh.index_range.chunk);
+                        if last_index_buffer != Some(index_buffer) {
+                            physical
+                                .device
+                                .cmd_bind_index_buffer(cmd, index_buffer, 0, vk::IndexType::UINT32);
+                            last_index_buffer = Some(index_buffer);
+                        }
+                        physical.device.cmd_draw_indexed(
+                            cmd,
+                            mesh.index_range.count,
+                            1,
+                            mesh.index_range.offset,
+                            mesh.vertex_range.offset as i32,
+                            0,
+                        );
+                    }
+                }
+            }
+
+            unsafe {
+                physical.device.cmd_end_render_pass(cmd);
+            }
+        }
+    }
+}
+
+impl Cascades {
+    //Fills in the shadow part of the scene uniform, `light` is the index of the shadowed light if there is one
+    pub fn write_to(&self, data: &mut GPUSceneData, settings: &ShadowSettings, light: Option<usize>) {
+        data.shadow_params = [
+            self.count as f32,
+            settings.normal_bias,
+            1.0 / settings.resolution as f32,
+            light.map_or(-1.0, |light| light as f32),
+        ];
+        data.cascade_splits = self.splits;
+        data.cascade_texel_sizes = self.texel_sizes;
+        data.light_viewproj = self.view_proj;
+    }
+}
+
+//Right handed orthographic projection with vulkan's 0 to 1 depth range
+fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> na::Matrix4<f32> {
+    na::Matrix4::new(
+        2.0 / (right - left), 0.0, 0.0, -(right + left) / (right - left),
+        0.0, 2.0 / (top - bottom), 0.0, -(top + bottom) / (top - bottom),
+        0.0, 0.0, -1.0 / (far - near), -near / (far - near),
+        0.0, 0.0, 0.0, 1.0,
+    )
+}
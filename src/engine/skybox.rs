@@ -0,0 +1,114 @@
+use erupt::vk;
+
+use super::{
+    descriptors::{DescriptorSetLayout, Descriptors},
+    device::Physical,
+    handles::{DescriptorPool, Sampler},
+    pipeline::PipelineStruct,
+    renderpass::RenderPass,
+    texture::Texture,
+};
+
+//Environment cubemap drawn behind everything. It is a full screen triangle on the far plane, so it is drawn after
+//the opaque objects and only shows where the depth buffer is still clear.
+pub struct Skybox {
+    //the descriptor set refers to these, they are only held so they live as long as it
+    #[allow(dead_code)]
+    cubemap: Texture,
+    #[allow(dead_code)]
+    sampler: Sampler,
+    #[allow(dead_code)]
+    descriptor_pool: DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline: PipelineStruct,
+}
+
+impl Skybox {
+    pub fn new(physical: &mut Physical, render_pass: &RenderPass, descs: &Descriptors, cubemap: Texture) -> Self {
+        let binding = [vk::DescriptorSetLayoutBindingBuilder::new()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+        let set_layout = DescriptorSetLayout::new(physical, &binding);
+
+        let sizes = [vk::DescriptorPoolSizeBuilder::new()
+            ._type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)];
+        let pool_info = vk::DescriptorPoolCreateInfoBuilder::new()
+            .max_sets(1)
+            .pool_sizes(&sizes);
+        let descriptor_pool = unsafe {
+            physical
+                .device
+                .create_descriptor_pool(&pool_info, None, None)
+        }
+        .unwrap();
+        let descriptor_pool = DescriptorPool::new(&physical.device, descriptor_pool);
+
+        let sampler_info = vk::SamplerCreateInfoBuilder::new()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = Sampler::new(
+            &physical.device,
+            unsafe { physical.device.create_sampler(&sampler_info, None, None) }.unwrap(),
+        );
+
+        let set_layouts = [set_layout.layout];
+        let allocate_info = vk::DescriptorSetAllocateInfoBuilder::new()
+            .descriptor_pool(descriptor_pool.handle)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { physical.device.allocate_descriptor_sets(&allocate_info) }.unwrap()[0];
+        let image_info = [vk::DescriptorImageInfoBuilder::new()
+            .sampler(sampler.handle)
+            .image_view(cubemap.image_view.handle)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write = vk::WriteDescriptorSetBuilder::new()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+        unsafe { physical.device.update_descriptor_sets(&[write], &[]) }
+
+        let pipeline = PipelineStruct::skybox(physical, render_pass, descs, set_layout.layout);
+
+        Skybox {
+            cubemap,
+            sampler,
+            descriptor_pool,
+            descriptor_set,
+            pipeline,
+        }
+    }
+
+    //Records the draw inside the main render pass, `global_descriptor` provides the camera
+    pub fn record(
+        &self,
+        physical: &Physical,
+        cmd: vk::CommandBuffer,
+        global_descriptor: vk::DescriptorSet,
+        global_offsets: &[u32],
+    ) {
+        unsafe {
+            physical.device.cmd_bind_pipeline(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipelines[0],
+            );
+            physical.device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline_layout,
+                0,
+                &[global_descriptor, self.descriptor_set],
+                global_offsets,
+            );
+            physical.device.cmd_draw(cmd, 3, 1, 0, 0);
+        }
+    }
+}
@@ -1,11 +1,18 @@
-use super::device::Physical;
+use std::sync::Arc;
+
+use super::{
+    device::{Device, Physical, ResourceKind},
+    handles::ImageView,
+};
 
 use erupt::vk::{self};
 
+//Destroys the swapchain and its views when dropped, the images belong to the swapchain
 pub struct Swapchain {
     pub swapchain: vk::SwapchainKHR,
     pub images: Vec<vk::Image>,
-    pub image_views: Vec<vk::ImageView>,
+    pub image_views: Vec<ImageView>,
+    device: Arc<Device>,
 }
 
 impl Swapchain {
@@ -64,29 +71,33 @@ impl Swapchain {
                             .layer_count(1)
                             .build(),
                     );
-                unsafe {
+                let view = unsafe {
                     physical
                         .device
                         .create_image_view(&image_view_info, None, None)
                 }
-                .unwrap()
+                .unwrap();
+                ImageView::new(&physical.device, view)
             })
             .collect();
 
+        physical.device.created(ResourceKind::Swapchain);
         Swapchain {
             swapchain,
             images: swapchain_images,
             image_views: swapchain_image_views,
+            device: Arc::clone(&physical.device),
         }
     }
-    pub fn cleanup(&mut self, physical: &Physical) {
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        //the views go first, they are views of the swapchain's images
+        self.image_views.clear();
         unsafe {
-            physical
-                .device
-                .destroy_swapchain_khr(Some(self.swapchain), None);
-            for &image_view in self.image_views.iter() {
-                physical.device.destroy_image_view(Some(image_view), None);
-            }
+            self.device.destroy_swapchain_khr(Some(self.swapchain), None);
         }
+        self.device.released(ResourceKind::Swapchain);
     }
 }
@@ -0,0 +1,426 @@
+use std::path::Path;
+
+use erupt::vk;
+use gpu_alloc::UsageFlags;
+use gpu_alloc_erupt::EruptMemoryDevice;
+
+use super::{
+    buffer::create_buffer, device::Physical, handles::ImageView, memory_stats::MemoryCategory, mesh::AllocatedImage,
+    upload::immediate_submit,
+};
+
+//The view is declared first, so it's destroyed before the image when the texture is dropped
+pub struct Texture {
+    pub image_view: ImageView,
+    pub image: AllocatedImage,
+    pub extent: vk::Extent2D,
+}
+
+impl Texture {
+    pub fn from_rgba8(
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        srgb: bool,
+        physical: &mut Physical,
+    ) -> Self {
+        let format = if srgb {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        };
+        Texture::from_levels(&[pixels], format, width, height, 1, physical)
+    }
+
+    //Six faces in the order +x, -x, +y, -y, +z, -z, which are expected to be files named px, nx, py, ny, pz and nz
+    //with any image extension in `directory`
+    pub fn load_cubemap_faces(directory: &Path, physical: &mut Physical) -> Self {
+        let mut pixels = Vec::new();
+        let mut size = None;
+        for face in &["px", "nx", "py", "ny", "pz", "nz"] {
+            let path = std::fs::read_dir(directory)
+                .expect("Failed to read cubemap directory")
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .find(|path| path.file_stem().and_then(|stem| stem.to_str()) == Some(face))
+                .unwrap_or_else(|| panic!("cubemap face {} missing in {:?}", face, directory));
+            let image = image::open(&path)
+                .expect("Failed to load cubemap face")
+                .to_rgba8();
+            let dimensions = image.dimensions();
+            if *size.get_or_insert(dimensions) != dimensions || dimensions.0 != dimensions.1 {
+                panic!("cubemap faces in {:?} need the same square size", directory);
+            }
+            pixels.extend(image.into_raw());
+        }
+        let (width, height) = size.unwrap();
+
+        println!("cubemap {:?}, {}x{}", directory, width, height);
+        Texture::from_levels(&[&pixels], vk::Format::R8G8B8A8_SRGB, width, height, 6, physical)
+    }
+
+    //Projects an equirectangular (latitude-longitude) .hdr image onto the six faces of a cube with `face_size` texels
+    pub fn load_equirectangular(path: &Path, face_size: u32, physical: &mut Physical) -> Self {
+        let (width, height, texels) = load_hdr(path);
+        //half floats can be filtered linearly everywhere, full floats can't
+        let faces: Vec<[u16; 4]> = equirectangular_to_cube(&texels, width, height, face_size)
+            .iter()
+            .map(|texel| texel.map(f32_to_f16))
+            .collect();
+
+        println!("environment {:?}, {}x{} -> {}x{} cube", path, width, height, face_size, face_size);
+        Texture::from_levels(
+            &[bytemuck::cast_slice(&faces)],
+            vk::Format::R16G16B16A16_SFLOAT,
+            face_size,
+            face_size,
+            6,
+            physical,
+        )
+    }
+
+    //One slice of pixels per mip level, starting with the full size `width` x `height`. 6 layers make a cubemap,
+    //the layers of a level follow each other.
+    pub fn from_levels(
+        levels: &[&[u8]],
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        layers: u32,
+        physical: &mut Physical,
+    ) -> Self {
+        let extent = vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        };
+        let mip_levels = levels.len() as u32;
+
+        let size: usize = levels.iter().map(|level| level.len()).sum();
+        let mut staging = create_buffer(
+            physical,
+            size as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            UsageFlags::UPLOAD,
+            MemoryCategory::Staging,
+        );
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        for level in levels {
+            unsafe {
+                staging
+                    .allocation
+                    .as_mut()
+                    .unwrap()
+                    .write_bytes(EruptMemoryDevice::wrap(&physical.device), offset, level)
+                    .unwrap();
+            }
+            offsets.push(offset);
+            offset += level.len() as u64;
+        }
+
+        let image_info = vk::ImageCreateInfoBuilder::new()
+            .image_type(vk::ImageType::_2D)
+            .format(format)
+            .extent(extent)
+            .mip_levels(mip_levels)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlagBits::_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .flags(if layers == 6 {
+                vk::ImageCreateFlags::CUBE_COMPATIBLE
+            } else {
+                vk::ImageCreateFlags::empty()
+            });
+        let image = create_image(physical, &image_info, MemoryCategory::Texture);
+
+        let range = color_subresource_range(mip_levels, layers);
+        immediate_submit(physical, |cmd| unsafe {
+            transition_image(
+                physical,
+                cmd,
+                image.image,
+                range,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+
+            let copy_regions: Vec<vk::BufferImageCopyBuilder> = offsets
+                .iter()
+                .enumerate()
+                .map(|(level, offset)| {
+                    vk::BufferImageCopyBuilder::new()
+                        .buffer_offset(*offset)
+                        .buffer_row_length(0)
+                        .buffer_image_height(0)
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: level as u32,
+                            base_array_layer: 0,
+                            layer_count: layers,
+                        })
+                        .image_extent(vk::Extent3D {
+                            width: (width >> level).max(1),
+                            height: (height >> level).max(1),
+                            depth: 1,
+                        })
+                })
+                .collect();
+            physical.device.cmd_copy_buffer_to_image(
+                cmd,
+                staging.buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &copy_regions,
+            );
+
+            transition_image(
+                physical,
+                cmd,
+                image.image,
+                range,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        });
+
+        //immediate_submit waited for the copy, the staging buffer is freed when it goes out of scope
+        let view_type = if layers == 6 {
+            vk::ImageViewType::CUBE
+        } else {
+            vk::ImageViewType::_2D
+        };
+        let image_view = create_image_view(physical, image.image, format, view_type, range);
+
+        Texture {
+            image,
+            image_view,
+            extent: vk::Extent2D { width, height },
+        }
+    }
+}
+
+//Width, height and rgb texels of a Radiance .hdr file
+fn load_hdr(path: &Path) -> (u32, u32, Vec<[f32; 3]>) {
+    let file = std::fs::File::open(path).expect("Failed to open hdr image");
+    let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file))
+        .expect("Failed to read hdr image");
+    let metadata = decoder.metadata();
+    let texels = decoder
+        .read_image_hdr()
+        .expect("Failed to decode hdr image")
+        .into_iter()
+        .map(|texel| texel.0)
+        .collect();
+    (metadata.width, metadata.height, texels)
+}
+
+//Direction through texel (s, t) of a cube face, s and t in -1..1 going right and down on the face.
+//Follows the Vulkan cubemap face orientation.
+pub fn cube_face_direction(face: usize, s: f32, t: f32) -> [f32; 3] {
+    match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    }
+}
+
+//Resamples a latitude-longitude image into six rgba faces, bilinearly filtered
+pub fn equirectangular_to_cube(texels: &[[f32; 3]], width: u32, height: u32, face_size: u32) -> Vec<[f32; 4]> {
+    let texel = |x: i64, y: i64| {
+        let x = x.rem_euclid(width as i64) as usize;
+        let y = y.clamp(0, height as i64 - 1) as usize;
+        texels[y * width as usize + x]
+    };
+
+    let mut faces = Vec::with_capacity((face_size * face_size * 6) as usize);
+    for face in 0..6 {
+        for y in 0..face_size {
+            for x in 0..face_size {
+                let s = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                let t = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                let [dx, dy, dz] = cube_face_direction(face, s, t);
+                let length = (dx * dx + dy * dy + dz * dz).sqrt();
+
+                //u follows the longitude, v goes from straight up to straight down
+                let u = 0.5 + dz.atan2(dx) / (2.0 * std::f32::consts::PI);
+                let v = (dy / length).clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+                let fx = u * width as f32 - 0.5;
+                let fy = v * height as f32 - 0.5;
+                let (x0, y0) = (fx.floor() as i64, fy.floor() as i64);
+                let (wx, wy) = (fx - fx.floor(), fy - fy.floor());
+
+                let mut color = [0.0; 4];
+                for (sx, sy, weight) in &[
+                    (x0, y0, (1.0 - wx) * (1.0 - wy)),
+                    (x0 + 1, y0, wx * (1.0 - wy)),
+                    (x0, y0 + 1, (1.0 - wx) * wy),
+                    (x0 + 1, y0 + 1, wx * wy),
+                ] {
+                    let sample = texel(*sx, *sy);
+                    for channel in 0..3 {
+                        color[channel] += sample[channel] * weight;
+                    }
+                }
+                color[3] = 1.0;
+                faces.push(color);
+            }
+        }
+    }
+    faces
+}
+
+//Nearest half float, overflowing to infinity and flushing values too small for a half to zero
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        //subnormal halfs
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = (mantissa | 0x80_0000) >> (1 - exponent);
+        return sign | ((mantissa + 0x1000) >> 13) as u16;
+    }
+    //rounding may carry into the exponent, which is still the right result
+    sign | (((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13)) as u16
+}
+
+//Creates an image and binds it to freshly allocated device local memory
+pub fn create_image(
+    physical: &mut Physical,
+    image_info: &vk::ImageCreateInfoBuilder,
+    category: MemoryCategory,
+) -> AllocatedImage {
+    let image = unsafe { physical.device.create_image(image_info, None, None) }.unwrap();
+
+    let mem_requirements = unsafe { physical.device.get_image_memory_requirements(image, None) };
+    let block = physical.device.alloc(
+        gpu_alloc::Request {
+            size: mem_requirements.size,
+            align_mask: mem_requirements.alignment - 1,
+            usage: UsageFlags::FAST_DEVICE_ACCESS,
+            memory_types: mem_requirements.memory_type_bits,
+        },
+        category,
+    );
+
+    unsafe {
+        physical
+            .device
+            .bind_image_memory(image, *block.memory(), block.offset())
+            .unwrap();
+    }
+
+    AllocatedImage::new(&physical.device, image, block)
+}
+
+pub fn create_image_view(
+    physical: &Physical,
+    image: vk::Image,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    range: vk::ImageSubresourceRange,
+) -> ImageView {
+    let image_view_info = vk::ImageViewCreateInfoBuilder::new()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(range);
+    let view = unsafe {
+        physical
+            .device
+            .create_image_view(&image_view_info, None, None)
+    }
+    .unwrap();
+    ImageView::new(&physical.device, view)
+}
+
+pub fn color_subresource_range(level_count: u32, layer_count: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count,
+        base_array_layer: 0,
+        layer_count,
+    }
+}
+
+//Layout transition covering the usual loading time cases: undefined -> transfer -> sampled,
+//with GENERAL standing for images written by compute shaders
+pub unsafe fn transition_image(
+    physical: &Physical,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let (src_access, src_stage) = match old_layout {
+        vk::ImageLayout::UNDEFINED => (
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        _ => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+    };
+    let (dst_access, dst_stage) = match new_layout {
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        ),
+        vk::ImageLayout::GENERAL => (
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+        ),
+        _ => (
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ),
+    };
+
+    let barrier = vk::ImageMemoryBarrierBuilder::new()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access);
+
+    physical
+        .device
+        .cmd_pipeline_barrier(cmd, src_stage, dst_stage, None, &[], &[], &[barrier]);
+}
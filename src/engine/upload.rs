@@ -0,0 +1,583 @@
+use std::{
+    collections::HashMap,
+    mem::size_of_val,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use erupt::{vk, DeviceLoader};
+use gpu_alloc::UsageFlags;
+use gpu_alloc_erupt::EruptMemoryDevice;
+
+use super::{
+    buffer::create_buffer,
+    device::Physical,
+    memory_stats::MemoryCategory,
+    mesh::{AllocatedBuffer, Mesh, Vertex},
+    mesh_pool::{sequential_indices, MeshPool},
+    texture::{color_subresource_range, create_image, create_image_view, Texture},
+};
+
+//Records commands into a one-off command buffer, submits it to the graphics queue and blocks until the GPU is done.
+//Only meant for loading time work like copying textures into device local images.
+pub fn immediate_submit<F: FnOnce(vk::CommandBuffer)>(physical: &Physical, record: F) {
+    let command_pool_info = vk::CommandPoolCreateInfoBuilder::new()
+        .queue_family_index(physical.graphics_queue_family)
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+    let command_pool = unsafe {
+        physical
+            .device
+            .create_command_pool(&command_pool_info, None, None)
+    }
+    .unwrap();
+
+    let command_buffer_info = vk::CommandBufferAllocateInfoBuilder::new()
+        .command_pool(command_pool)
+        .command_buffer_count(1)
+        .level(vk::CommandBufferLevel::PRIMARY);
+    let command_buffer = unsafe {
+        physical
+            .device
+            .allocate_command_buffers(&command_buffer_info)
+    }
+    .unwrap()[0];
+
+    let cmd_begin_info = vk::CommandBufferBeginInfoBuilder::new()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    unsafe {
+        physical
+            .device
+            .begin_command_buffer(command_buffer, &cmd_begin_info)
+            .unwrap();
+    }
+
+    record(command_buffer);
+
+    unsafe { physical.device.end_command_buffer(command_buffer) }.unwrap();
+
+    let fence_info = vk::FenceCreateInfoBuilder::new();
+    let fence = unsafe { physical.device.create_fence(&fence_info, None, None) }.unwrap();
+
+    let command_buffers = [command_buffer];
+    let submit_info = vk::SubmitInfoBuilder::new().command_buffers(&command_buffers);
+    unsafe {
+        physical
+            .device
+            .queue_submit(physical.graphics_queue, &[submit_info], Some(fence))
+            .unwrap();
+        physical
+            .device
+            .wait_for_fences(&[fence], true, u64::MAX)
+            .unwrap();
+
+        physical.device.destroy_fence(Some(fence), None);
+        physical
+            .device
+            .destroy_command_pool(Some(command_pool), None);
+    }
+}
+
+//Destination of a copy out of an upload's staging buffer
+#[derive(Clone)]
+enum UploadCopy {
+    Buffer {
+        buffer: vk::Buffer,
+        src_offset: u64,
+        dst_offset: u64,
+        size: u64,
+    },
+    //one region per mip level, packed one after another in the staging buffer
+    Image {
+        image: vk::Image,
+        level_offsets: Vec<u64>,
+        extent: vk::Extent2D,
+        range: vk::ImageSubresourceRange,
+    },
+}
+
+struct UploadJob {
+    id: u64,
+    staging: vk::Buffer,
+    copies: Vec<UploadCopy>,
+}
+
+//A resource whose data has arrived on the GPU, ready to be put into the Scene under its name
+pub enum Uploaded {
+    Mesh(String, Mesh),
+    Texture(String, Texture),
+}
+
+struct PendingUpload {
+    staging: AllocatedBuffer,
+    copies: Vec<UploadCopy>,
+    resource: Uploaded,
+}
+
+struct UploadWorker {
+    jobs: Sender<UploadJob>,
+    done: Receiver<u64>,
+    thread: JoinHandle<()>,
+}
+
+//Uploads meshes and textures without blocking the frame. The render thread allocates the resources and fills a staging
+//buffer, a background thread copies it on Physical::transfer_queue and releases the resources to the graphics queue
+//family. poll acquires them in the frame's command buffer and hands them back for the Scene.
+//Without a transfer only queue family the copies are made on the graphics queue in poll instead.
+pub struct UploadManager {
+    worker: Option<UploadWorker>,
+    pending: HashMap<u64, PendingUpload>,
+    //waiting for poll when there is no worker
+    queued: Vec<u64>,
+    next_id: u64,
+}
+
+impl UploadManager {
+    pub fn new(physical: &Physical) -> Self {
+        let worker = if physical.has_transfer_queue() {
+            let (jobs, job_receiver) = mpsc::channel();
+            let (done_sender, done) = mpsc::channel();
+            //UploadManager::cleanup joins the thread before the device is destroyed
+            let device = Arc::clone(&physical.device);
+            let queue = physical.transfer_queue;
+            let families = (physical.transfer_queue_family, physical.graphics_queue_family);
+            let thread = std::thread::Builder::new()
+                .name("upload".to_string())
+                .spawn(move || run_worker(&device, queue, families, job_receiver, done_sender))
+                .unwrap();
+            Some(UploadWorker { jobs, done, thread })
+        } else {
+            None
+        };
+
+        UploadManager {
+            worker,
+            pending: HashMap::new(),
+            queued: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    //Uploads into ranges of the mesh pool, an empty index list makes a plain triangle list
+    pub fn upload_mesh(
+        &mut self,
+        physical: &mut Physical,
+        pool: &mut MeshPool,
+        name: &str,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    ) {
+        let pool_indices = sequential_indices(&vertices, &indices);
+        let vertex_data: &[u8] = bytemuck::cast_slice(&vertices);
+        let index_data: &[u8] = bytemuck::cast_slice(&pool_indices);
+        let staging = self.staging(physical, &[vertex_data, index_data]);
+
+        let (vertex_range, index_range) = pool.allocate(physical, vertices.len() as u32, pool_indices.len() as u32);
+        let copies = vec![
+            UploadCopy::Buffer {
+                buffer: pool.vertex_buffer(vertex_range.chunk),
+                src_offset: 0,
+                dst_offset: pool.vertex_byte_offset(vertex_range),
+                size: size_of_val(vertex_data) as u64,
+            },
+            UploadCopy::Buffer {
+                buffer: pool.index_buffer(index_range.chunk),
+                src_offset: size_of_val(vertex_data) as u64,
+                dst_offset: pool.index_byte_offset(index_range),
+                size: size_of_val(index_data) as u64,
+            },
+        ];
+
+        let mesh = Mesh {
+            verticies: vertices,
+            indices,
+            vertex_range,
+            index_range,
+        };
+        self.submit(staging, copies, Uploaded::Mesh(name.to_string(), mesh));
+    }
+
+    //Uploads a 2D texture from its mip levels, largest first, like Texture::from_levels
+    pub fn upload_texture(
+        &mut self,
+        physical: &mut Physical,
+        name: &str,
+        levels: &[&[u8]],
+        format: vk::Format,
+        width: u32,
+        height: u32,
+    ) {
+        let staging = self.staging(physical, levels);
+        let mut level_offsets = Vec::new();
+        let mut offset = 0;
+        for level in levels {
+            level_offsets.push(offset);
+            offset += level.len() as u64;
+        }
+
+        let mip_levels = levels.len() as u32;
+        let image_info = vk::ImageCreateInfoBuilder::new()
+            .image_type(vk::ImageType::_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlagBits::_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST);
+        let image = create_image(physical, &image_info, MemoryCategory::Texture);
+        let range = color_subresource_range(mip_levels, 1);
+        let image_view = create_image_view(physical, image.image, format, vk::ImageViewType::_2D, range);
+
+        let extent = vk::Extent2D { width, height };
+        let copies = vec![UploadCopy::Image {
+            image: image.image,
+            level_offsets,
+            extent,
+            range,
+        }];
+        let texture = Texture {
+            image,
+            image_view,
+            extent,
+        };
+        self.submit(staging, copies, Uploaded::Texture(name.to_string(), texture));
+    }
+
+    //Collects finished uploads. Acquires ownership of their resources in `cmd`, which has to be submitted to the graphics
+    //queue before they are used, so call it at the start of the frame's command buffer.
+    pub fn poll(&mut self, physical: &mut Physical, cmd: vk::CommandBuffer) -> Vec<Uploaded> {
+        let read_access =
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ | vk::AccessFlags::SHADER_READ;
+        //the vertex shader reads the mesh pool with vertex pulling, the fragment shader samples the textures
+        let read_stages = vk::PipelineStageFlags::VERTEX_INPUT
+            | vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER;
+        let mut barriers = Barriers::default();
+
+        let finished: Vec<u64> = match &self.worker {
+            Some(worker) => {
+                let finished: Vec<u64> = worker.done.try_iter().collect();
+                for id in &finished {
+                    barriers.handover(
+                        &self.pending[id].copies,
+                        vk::AccessFlags::empty(),
+                        read_access,
+                        (physical.transfer_queue_family, physical.graphics_queue_family),
+                    );
+                }
+                unsafe {
+                    barriers.record(
+                        &physical.device,
+                        cmd,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        read_stages,
+                    )
+                };
+                finished
+            }
+            None => {
+                let queued: Vec<u64> = self.queued.drain(..).collect();
+                if !queued.is_empty() {
+                    let pending = &self.pending;
+                    immediate_submit(physical, |cmd| unsafe {
+                        for id in &queued {
+                            let upload = &pending[id];
+                            record_copies(&physical.device, cmd, upload.staging.buffer, &upload.copies);
+                            barriers.handover(
+                                &upload.copies,
+                                vk::AccessFlags::TRANSFER_WRITE,
+                                read_access,
+                                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+                            );
+                        }
+                        barriers.record(&physical.device, cmd, vk::PipelineStageFlags::TRANSFER, read_stages);
+                    });
+                }
+                queued
+            }
+        };
+
+        finished
+            .into_iter()
+            .map(|id| self.pending.remove(&id).unwrap().resource)
+            .collect()
+    }
+
+    fn staging(&mut self, physical: &mut Physical, parts: &[&[u8]]) -> AllocatedBuffer {
+        let size: usize = parts.iter().map(|part| part.len()).sum();
+        let mut staging = create_buffer(
+            physical,
+            size.max(1) as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            UsageFlags::UPLOAD,
+            MemoryCategory::Staging,
+        );
+        let mut offset = 0;
+        for part in parts {
+            unsafe {
+                staging
+                    .allocation
+                    .as_mut()
+                    .unwrap()
+                    .write_bytes(EruptMemoryDevice::wrap(&physical.device), offset, part)
+                    .unwrap();
+            }
+            offset += part.len() as u64;
+        }
+        staging
+    }
+
+    fn submit(&mut self, staging: AllocatedBuffer, copies: Vec<UploadCopy>, resource: Uploaded) {
+        let id = self.next_id;
+        self.next_id += 1;
+        match &self.worker {
+            Some(worker) => worker
+                .jobs
+                .send(UploadJob {
+                    id,
+                    staging: staging.buffer,
+                    copies: copies.clone(),
+                })
+                .expect("upload thread exited"),
+            None => self.queued.push(id),
+        }
+        self.pending.insert(
+            id,
+            PendingUpload {
+                staging,
+                copies,
+                resource,
+            },
+        );
+    }
+
+    //Stops the worker, so the staging buffers are no longer read. Uploads that haven't been handed out by poll
+    //are released with the manager, meshes only hold ranges of the scene's pool, which is freed with it.
+    pub fn cleanup(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            //closing the channel ends the thread once the jobs it already has are done
+            drop(worker.jobs);
+            worker.thread.join().unwrap();
+        }
+    }
+}
+
+//Records the copies of one upload, moving its images into TRANSFER_DST_OPTIMAL first
+unsafe fn record_copies(device: &DeviceLoader, cmd: vk::CommandBuffer, staging: vk::Buffer, copies: &[UploadCopy]) {
+    for copy in copies {
+        match copy {
+            UploadCopy::Buffer {
+                buffer,
+                src_offset,
+                dst_offset,
+                size,
+            } => {
+                if *size == 0 {
+                    continue;
+                }
+                let region = vk::BufferCopyBuilder::new()
+                    .src_offset(*src_offset)
+                    .dst_offset(*dst_offset)
+                    .size(*size);
+                device.cmd_copy_buffer(cmd, staging, *buffer, &[region]);
+            }
+            UploadCopy::Image {
+                image,
+                level_offsets,
+                extent,
+                range,
+            } => {
+                let barrier = vk::ImageMemoryBarrierBuilder::new()
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(*image)
+                    .subresource_range(*range);
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    None,
+                    &[],
+                    &[],
+                    &[barrier],
+                );
+
+                let regions: Vec<vk::BufferImageCopyBuilder> = level_offsets
+                    .iter()
+                    .enumerate()
+                    .map(|(level, offset)| {
+                        vk::BufferImageCopyBuilder::new()
+                            .buffer_offset(*offset)
+                            .buffer_row_length(0)
+                            .buffer_image_height(0)
+                            .image_subresource(vk::ImageSubresourceLayers {
+                                aspect_mask: vk::ImageAspectFlags::COLOR,
+                                mip_level: level as u32,
+                                base_array_layer: 0,
+                                layer_count: range.layer_count,
+                            })
+                            .image_extent(vk::Extent3D {
+                                width: (extent.width >> level).max(1),
+                                height: (extent.height >> level).max(1),
+                                depth: 1,
+                            })
+                    })
+                    .collect();
+                device.cmd_copy_buffer_to_image(
+                    cmd,
+                    staging,
+                    *image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                );
+            }
+        }
+    }
+}
+
+//Barriers handing the destinations of uploads from the transfer writes over to the frame, leaving images in
+//SHADER_READ_ONLY_OPTIMAL. Across queue families the same barriers are recorded on both queues, first releasing
+//ownership on the transfer queue and then acquiring it on the graphics queue.
+#[derive(Default)]
+struct Barriers {
+    buffers: Vec<vk::BufferMemoryBarrierBuilder<'static>>,
+    images: Vec<vk::ImageMemoryBarrierBuilder<'static>>,
+}
+
+impl Barriers {
+    fn handover(
+        &mut self,
+        copies: &[UploadCopy],
+        src_access: vk::AccessFlags,
+        dst_access: vk::AccessFlags,
+        (src_family, dst_family): (u32, u32),
+    ) {
+        for copy in copies {
+            match copy {
+                //only the copied range, the rest of a pool buffer is in use by the frames
+                UploadCopy::Buffer {
+                    buffer,
+                    dst_offset,
+                    size,
+                    ..
+                } => self.buffers.push(
+                    vk::BufferMemoryBarrierBuilder::new()
+                        .src_access_mask(src_access)
+                        .dst_access_mask(dst_access)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                        .buffer(*buffer)
+                        .offset(*dst_offset)
+                        .size((*size).max(1)),
+                ),
+                UploadCopy::Image { image, range, .. } => self.images.push(
+                    vk::ImageMemoryBarrierBuilder::new()
+                        .src_access_mask(src_access)
+                        .dst_access_mask(dst_access)
+                        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                        .image(*image)
+                        .subresource_range(*range),
+                ),
+            }
+        }
+    }
+
+    unsafe fn record(
+        &mut self,
+        device: &DeviceLoader,
+        cmd: vk::CommandBuffer,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) {
+        if self.buffers.is_empty() && self.images.is_empty() {
+            return;
+        }
+        device.cmd_pipeline_barrier(cmd, src_stage, dst_stage, None, &[], &self.buffers, &self.images);
+        self.buffers.clear();
+        self.images.clear();
+    }
+}
+
+//Body of the upload thread: copies every job on the transfer queue and reports it once the copy has finished.
+//Jobs that arrive together share a submission.
+fn run_worker(
+    device: &DeviceLoader,
+    queue: vk::Queue,
+    (transfer_family, graphics_family): (u32, u32),
+    jobs: Receiver<UploadJob>,
+    done: Sender<u64>,
+) {
+    let command_pool_info = vk::CommandPoolCreateInfoBuilder::new()
+        .queue_family_index(transfer_family)
+        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+    let command_pool = unsafe { device.create_command_pool(&command_pool_info, None, None) }.unwrap();
+    let command_buffer_info = vk::CommandBufferAllocateInfoBuilder::new()
+        .command_pool(command_pool)
+        .command_buffer_count(1)
+        .level(vk::CommandBufferLevel::PRIMARY);
+    let cmd = unsafe { device.allocate_command_buffers(&command_buffer_info) }.unwrap()[0];
+    let fence_info = vk::FenceCreateInfoBuilder::new();
+    let fence = unsafe { device.create_fence(&fence_info, None, None) }.unwrap();
+
+    let mut barriers = Barriers::default();
+    while let Ok(job) = jobs.recv() {
+        let batch: Vec<UploadJob> = std::iter::once(job).chain(jobs.try_iter()).collect();
+
+        let cmd_begin_info = vk::CommandBufferBeginInfoBuilder::new()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            device
+                .reset_command_buffer(cmd, Some(vk::CommandBufferResetFlags::empty()))
+                .unwrap();
+            device.begin_command_buffer(cmd, &cmd_begin_info).unwrap();
+            for job in &batch {
+                record_copies(device, cmd, job.staging, &job.copies);
+                barriers.handover(
+                    &job.copies,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::empty(),
+                    (transfer_family, graphics_family),
+                );
+            }
+            barriers.record(
+                device,
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            );
+            device.end_command_buffer(cmd).unwrap();
+
+            let command_buffers = [cmd];
+            let submit_info = vk::SubmitInfoBuilder::new().command_buffers(&command_buffers);
+            device.queue_submit(queue, &[submit_info], Some(fence)).unwrap();
+            device.wait_for_fences(&[fence], true, u64::MAX).unwrap();
+            device.reset_fences(&[fence]).unwrap();
+        }
+
+        for job in batch {
+            //the manager is gone, nobody is waiting for the rest
+            if done.send(job.id).is_err() {
+                break;
+            }
+        }
+    }
+
+    unsafe {
+        device.destroy_fence(Some(fence), None);
+        device.destroy_command_pool(Some(command_pool), None);
+    }
+}
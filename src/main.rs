@@ -1,5 +1,4 @@
-mod engine;
-mod window;
+use vkguide_erupt::window;
 fn main() {
     let _window = window::start();
 }
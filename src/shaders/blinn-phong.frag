@@ -0,0 +1,133 @@
+#version 450
+
+#define MAX_LIGHTS 16
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+#define MAX_CASCADES 4
+
+layout (location = 0) in vec3 inColor;
+layout (location = 1) in vec3 inWorldPos;
+layout (location = 2) in vec3 inNormal;
+layout (location = 3) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+struct Light {
+	//w: light type
+	vec4 position;
+	//w: range
+	vec4 direction;
+	//w: intensity
+	vec4 color;
+	//x: cos inner angle, y: cos outer angle
+	vec4 cone;
+};
+
+layout (set = 0, binding = 0) uniform CameraBuffer {
+	mat4 view;
+	mat4 proj;
+	mat4 viewproj;
+} cameraData;
+
+layout (set = 0, binding = 1) uniform SceneData {
+	vec4 ambientColor;
+	vec4 cameraPosition;
+	uvec4 lightCount;
+	//x: cascade count, y: normal bias in texels, z: texel size in uv, w: index of the shadowed light or -1
+	vec4 shadowParams;
+	vec4 cascadeSplits;
+	vec4 cascadeTexelSizes;
+	mat4 lightViewProj[MAX_CASCADES];
+	Light lights[MAX_LIGHTS];
+} sceneData;
+
+layout (set = 0, binding = 2) uniform sampler2DArrayShadow shadowMap;
+//diffuse image based lighting, replaces a flat ambient term
+layout (set = 0, binding = 3) uniform samplerCube irradianceMap;
+
+const float shininess = 32.0;
+const float specularStrength = 0.5;
+
+//fraction of the shadow map samples around the fragment that see the light, 3x3 PCF
+float shadowFactor(vec3 worldPos, vec3 N)
+{
+	int cascadeCount = int(sceneData.shadowParams.x);
+	float viewDepth = -(cameraData.view * vec4(worldPos, 1.0)).z;
+	int cascade = cascadeCount;
+	for (int i = 0; i < cascadeCount; i++) {
+		if (viewDepth <= sceneData.cascadeSplits[i]) {
+			cascade = i;
+			break;
+		}
+	}
+	//beyond the shadow distance
+	if (cascade == cascadeCount) {
+		return 1.0;
+	}
+
+	//moving the receiver along its normal keeps it from shadowing itself
+	vec3 offsetPos = worldPos + N * sceneData.cascadeTexelSizes[cascade] * sceneData.shadowParams.y;
+	vec4 lightPos = sceneData.lightViewProj[cascade] * vec4(offsetPos, 1.0);
+	vec3 coords = lightPos.xyz / lightPos.w;
+	if (coords.z > 1.0) {
+		return 1.0;
+	}
+	vec2 uv = coords.xy * 0.5 + 0.5;
+
+	float texel = sceneData.shadowParams.z;
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			lit += texture(shadowMap, vec4(uv + vec2(x, y) * texel, cascade, coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+
+//smooth falloff reaching exactly zero at the light's range
+float attenuation(float dist, float range)
+{
+	float ratio = dist / max(range, 0.0001);
+	float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+	return window * window / (dist * dist + 1.0);
+}
+
+void main() 
+{
+	vec3 N = normalize(inNormal);
+	vec3 V = normalize(sceneData.cameraPosition.xyz - inWorldPos);
+	vec3 albedo = inColor;
+
+	vec3 color = texture(irradianceMap, N).rgb * albedo;
+	for (uint i = 0; i < sceneData.lightCount.x; i++) {
+		Light light = sceneData.lights[i];
+		int type = int(light.position.w);
+
+		vec3 L;
+		float strength = light.color.w;
+		if (type == LIGHT_DIRECTIONAL) {
+			L = -normalize(light.direction.xyz);
+			if (int(i) == int(sceneData.shadowParams.w)) {
+				strength *= shadowFactor(inWorldPos, N);
+			}
+		} else {
+			vec3 toLight = light.position.xyz - inWorldPos;
+			float dist = length(toLight);
+			L = toLight / dist;
+			strength *= attenuation(dist, light.direction.w);
+			if (type == LIGHT_SPOT) {
+				float cosAngle = dot(-L, normalize(light.direction.xyz));
+				strength *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+			}
+		}
+
+		float diffuse = max(dot(N, L), 0.0);
+		vec3 H = normalize(L + V);
+		float specular = diffuse > 0.0 ? pow(max(dot(N, H), 0.0), shininess) * specularStrength : 0.0;
+
+		color += (albedo * diffuse + vec3(specular)) * light.color.rgb * strength;
+	}
+
+	outFragColor = vec4(color, 1.0f);
+}
@@ -0,0 +1,254 @@
+#version 450
+
+//Full screen pass of the deferred path lighting the G-buffer with every scene light, see deferred.rs.
+//The lighting matches pbr.frag.
+
+#define MAX_LIGHTS 16
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+#define MAX_CASCADES 4
+
+const float PI = 3.14159265359;
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+struct Light {
+	//w: light type
+	vec4 position;
+	//w: range
+	vec4 direction;
+	//w: intensity
+	vec4 color;
+	//x: cos inner angle, y: cos outer angle
+	vec4 cone;
+};
+
+layout (set = 0, binding = 0) uniform CameraBuffer {
+	mat4 view;
+	mat4 proj;
+	mat4 viewproj;
+} cameraData;
+
+layout (set = 0, binding = 1) uniform SceneData {
+	vec4 ambientColor;
+	vec4 cameraPosition;
+	uvec4 lightCount;
+	//x: cascade count, y: normal bias in texels, z: texel size in uv, w: index of the shadowed light or -1
+	vec4 shadowParams;
+	vec4 cascadeSplits;
+	vec4 cascadeTexelSizes;
+	mat4 lightViewProj[MAX_CASCADES];
+	Light lights[MAX_LIGHTS];
+} sceneData;
+
+layout (set = 0, binding = 2) uniform sampler2DArrayShadow shadowMap;
+//image based lighting, see ibl.rs
+layout (set = 0, binding = 3) uniform samplerCube irradianceMap;
+layout (set = 0, binding = 4) uniform samplerCube prefilteredMap;
+layout (set = 0, binding = 5) uniform sampler2D brdfLut;
+
+layout (set = 1, binding = 0) uniform sampler2D gAlbedo;
+layout (set = 1, binding = 1) uniform sampler2D gNormal;
+layout (set = 1, binding = 2) uniform sampler2D gMaterial;
+layout (set = 1, binding = 3) uniform sampler2D gEmissive;
+layout (set = 1, binding = 4) uniform sampler2D gDepth;
+
+layout (push_constant) uniform constants
+{
+	mat4 inverseViewProj;
+	vec4 clearColor;
+	//x: debug view, 0 is the lit image. y: far plane
+	vec4 params;
+} pc;
+
+#define VIEW_LIT 0
+#define VIEW_ALBEDO 1
+#define VIEW_NORMALS 2
+#define VIEW_MATERIAL 3
+#define VIEW_EMISSIVE 4
+#define VIEW_DEPTH 5
+
+//fraction of the shadow map samples around the fragment that see the light, 3x3 PCF
+float shadowFactor(vec3 worldPos, vec3 N)
+{
+	int cascadeCount = int(sceneData.shadowParams.x);
+	float viewDepth = -(cameraData.view * vec4(worldPos, 1.0)).z;
+	int cascade = cascadeCount;
+	for (int i = 0; i < cascadeCount; i++) {
+		if (viewDepth <= sceneData.cascadeSplits[i]) {
+			cascade = i;
+			break;
+		}
+	}
+	//beyond the shadow distance
+	if (cascade == cascadeCount) {
+		return 1.0;
+	}
+
+	//moving the receiver along its normal keeps it from shadowing itself
+	vec3 offsetPos = worldPos + N * sceneData.cascadeTexelSizes[cascade] * sceneData.shadowParams.y;
+	vec4 lightPos = sceneData.lightViewProj[cascade] * vec4(offsetPos, 1.0);
+	vec3 coords = lightPos.xyz / lightPos.w;
+	if (coords.z > 1.0) {
+		return 1.0;
+	}
+	vec2 uv = coords.xy * 0.5 + 0.5;
+
+	float texel = sceneData.shadowParams.z;
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++) {
+		for (int y = -1; y <= 1; y++) {
+			lit += texture(shadowMap, vec4(uv + vec2(x, y) * texel, cascade, coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+
+//smooth falloff reaching exactly zero at the light's range
+float attenuation(float dist, float range)
+{
+	float ratio = dist / max(range, 0.0001);
+	float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+	return window * window / (dist * dist + 1.0);
+}
+
+//GGX / Trowbridge-Reitz normal distribution
+float distributionGGX(float NdotH, float roughness)
+{
+	float a = roughness * roughness;
+	float a2 = a * a;
+	float denom = NdotH * NdotH * (a2 - 1.0) + 1.0;
+	return a2 / (PI * denom * denom);
+}
+
+//Smith's method with Schlick-GGX for both view and light direction
+float geometrySmith(float NdotV, float NdotL, float roughness)
+{
+	float r = roughness + 1.0;
+	float k = (r * r) / 8.0;
+	float ggxV = NdotV / (NdotV * (1.0 - k) + k);
+	float ggxL = NdotL / (NdotL * (1.0 - k) + k);
+	return ggxV * ggxL;
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0)
+{
+	return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+//fresnel for the whole environment, rough surfaces reflect less at grazing angles
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 F0, float roughness)
+{
+	return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+//light from the environment map, split into the diffuse irradiance and the prefiltered specular part
+vec3 environmentLighting(vec3 N, vec3 V, float NdotV, vec3 albedo, vec3 F0, float metallic, float roughness)
+{
+	vec3 F = fresnelSchlickRoughness(NdotV, F0, roughness);
+	vec3 kD = (vec3(1.0) - F) * (1.0 - metallic);
+	vec3 diffuse = texture(irradianceMap, N).rgb * albedo;
+
+	vec3 R = reflect(-V, N);
+	float lod = roughness * float(textureQueryLevels(prefilteredMap) - 1);
+	vec3 prefiltered = textureLod(prefilteredMap, R, lod).rgb;
+	vec2 brdf = texture(brdfLut, vec2(NdotV, roughness)).rg;
+	vec3 specular = prefiltered * (F * brdf.x + brdf.y);
+
+	return kD * diffuse + specular;
+}
+
+void main() 
+{
+	float depth = texture(gDepth, inUV).r;
+	int view = int(pc.params.x);
+	//nothing was drawn here
+	if (depth >= 1.0) {
+		outFragColor = view == VIEW_LIT ? pc.clearColor : vec4(0.0, 0.0, 0.0, 1.0);
+		return;
+	}
+
+	vec4 albedoOcclusion = texture(gAlbedo, inUV);
+	vec3 N = normalize(texture(gNormal, inUV).xyz);
+	vec2 metallicRoughness = texture(gMaterial, inUV).rg;
+	vec3 emissive = texture(gEmissive, inUV).rgb;
+
+	//back through the camera, the full screen triangle's uv maps to the same clip space xy
+	vec4 unprojected = pc.inverseViewProj * vec4(inUV * 2.0 - 1.0, depth, 1.0);
+	vec3 worldPos = unprojected.xyz / unprojected.w;
+
+	if (view == VIEW_ALBEDO) {
+		outFragColor = vec4(albedoOcclusion.rgb, 1.0);
+		return;
+	} else if (view == VIEW_NORMALS) {
+		outFragColor = vec4(N * 0.5 + 0.5, 1.0);
+		return;
+	} else if (view == VIEW_MATERIAL) {
+		outFragColor = vec4(metallicRoughness, albedoOcclusion.a, 1.0);
+		return;
+	} else if (view == VIEW_EMISSIVE) {
+		outFragColor = vec4(emissive, 1.0);
+		return;
+	} else if (view == VIEW_DEPTH) {
+		float viewDepth = -(cameraData.view * vec4(worldPos, 1.0)).z;
+		outFragColor = vec4(vec3(viewDepth / pc.params.y), 1.0);
+		return;
+	}
+
+	vec3 baseColor = albedoOcclusion.rgb;
+	float occlusion = albedoOcclusion.a;
+	float metallic = metallicRoughness.r;
+	float roughness = metallicRoughness.g;
+	vec3 V = normalize(sceneData.cameraPosition.xyz - worldPos);
+	float NdotV = max(dot(N, V), 0.0001);
+
+	//dielectrics reflect about 4%, metals reflect their base color
+	vec3 F0 = mix(vec3(0.04), baseColor, metallic);
+
+	vec3 Lo = vec3(0.0);
+	for (uint i = 0; i < sceneData.lightCount.x; i++) {
+		Light light = sceneData.lights[i];
+		int type = int(light.position.w);
+
+		vec3 L;
+		float strength = light.color.w;
+		if (type == LIGHT_DIRECTIONAL) {
+			L = -normalize(light.direction.xyz);
+			if (int(i) == int(sceneData.shadowParams.w)) {
+				strength *= shadowFactor(worldPos, N);
+			}
+		} else {
+			vec3 toLight = light.position.xyz - worldPos;
+			float dist = length(toLight);
+			L = toLight / dist;
+			strength *= attenuation(dist, light.direction.w);
+			if (type == LIGHT_SPOT) {
+				float cosAngle = dot(-L, normalize(light.direction.xyz));
+				strength *= smoothstep(light.cone.y, light.cone.x, cosAngle);
+			}
+		}
+
+		float NdotL = max(dot(N, L), 0.0);
+		if (NdotL <= 0.0) {
+			continue;
+		}
+		vec3 H = normalize(L + V);
+		float NdotH = max(dot(N, H), 0.0);
+
+		float D = distributionGGX(NdotH, roughness);
+		float G = geometrySmith(NdotV, NdotL, roughness);
+		vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+
+		vec3 specular = D * G * F / (4.0 * NdotV * NdotL + 0.0001);
+		//whatever isn't reflected is refracted, and metals absorb all of the refracted light
+		vec3 kD = (vec3(1.0) - F) * (1.0 - metallic);
+
+		Lo += (kD * baseColor / PI + specular) * light.color.rgb * strength * NdotL;
+	}
+
+	vec3 ambient = environmentLighting(N, V, NdotV, baseColor, F0, metallic, roughness) * occlusion;
+	outFragColor = vec4(ambient + Lo + emissive, 1.0);
+}
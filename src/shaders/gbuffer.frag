@@ -0,0 +1,61 @@
+#version 450
+
+//Surface attributes for the deferred lighting pass, read the same way pbr.frag reads them
+
+layout (location = 0) in vec3 inColor;
+layout (location = 1) in vec3 inWorldPos;
+layout (location = 2) in vec3 inNormal;
+layout (location = 3) in vec2 inUV;
+layout (location = 4) in vec4 inTangent;
+
+//a: occlusion
+layout (location = 0) out vec4 outAlbedo;
+layout (location = 1) out vec4 outNormal;
+//r: metallic, g: roughness
+layout (location = 2) out vec4 outMaterial;
+layout (location = 3) out vec4 outEmissive;
+
+layout (set = 1, binding = 0) uniform MaterialData {
+	//a: opacity
+	vec4 baseColor;
+	vec4 emissive;
+	//x: metallic, y: roughness, z: alpha cutoff
+	vec4 factors;
+} material;
+
+layout (set = 1, binding = 1) uniform sampler2D baseColorMap;
+//glTF convention: roughness in g, metallic in b
+layout (set = 1, binding = 2) uniform sampler2D metallicRoughnessMap;
+layout (set = 1, binding = 3) uniform sampler2D normalMap;
+layout (set = 1, binding = 4) uniform sampler2D occlusionMap;
+layout (set = 1, binding = 5) uniform sampler2D emissiveMap;
+
+vec3 surfaceNormal()
+{
+	vec3 N = normalize(inNormal);
+	//meshes without uvs have no tangents, the normal map can't be used then
+	if (dot(inTangent.xyz, inTangent.xyz) < 0.000001) {
+		return N;
+	}
+	vec3 T = normalize(inTangent.xyz - N * dot(N, inTangent.xyz));
+	vec3 B = cross(N, T) * (inTangent.w < 0.0 ? -1.0 : 1.0);
+	vec3 tangentNormal = texture(normalMap, inUV).xyz * 2.0 - 1.0;
+	return normalize(mat3(T, B, N) * tangentNormal);
+}
+
+void main() 
+{
+	vec4 baseColor = material.baseColor * texture(baseColorMap, inUV) * vec4(inColor, 1.0);
+	if (baseColor.a < material.factors.z) {
+		discard;
+	}
+	vec4 metallicRoughness = texture(metallicRoughnessMap, inUV);
+	float metallic = clamp(material.factors.x * metallicRoughness.b, 0.0, 1.0);
+	float roughness = clamp(material.factors.y * metallicRoughness.g, 0.04, 1.0);
+	float occlusion = texture(occlusionMap, inUV).r;
+
+	outAlbedo = vec4(baseColor.rgb, occlusion);
+	outNormal = vec4(surfaceNormal(), 0.0);
+	outMaterial = vec4(metallic, roughness, 0.0, 0.0);
+	outEmissive = vec4(material.emissive.rgb * texture(emissiveMap, inUV).rgb, 1.0);
+}
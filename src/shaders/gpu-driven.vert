@@ -0,0 +1,43 @@
+#version 450
+
+layout (location = 0) in vec3 vPosition;
+layout (location = 1) in vec3 vNormal;
+layout (location = 2) in vec3 vColor;
+layout (location = 3) in vec2 vUV;
+layout (location = 4) in vec4 vTangent;
+
+layout (location = 0) out vec3 outColor;
+layout (location = 1) out vec3 outWorldPos;
+layout (location = 2) out vec3 outNormal;
+layout (location = 3) out vec2 outUV;
+layout (location = 4) out vec4 outTangent;
+
+layout (set = 0, binding = 0) uniform CameraBuffer {
+	mat4 view;
+	mat4 proj;
+	mat4 viewproj;
+} cameraData;
+
+//matches GPUObjectData in gpu_driven.rs
+struct ObjectData {
+	mat4 model;
+	vec4 sphere;
+	uvec4 draw;
+};
+
+layout (std430, set = 2, binding = 0) readonly buffer Objects {
+	ObjectData objects[];
+};
+
+//lit.vert with the model matrix of the object the culling pass put in the first instance
+void main()
+{
+	mat4 model = objects[gl_InstanceIndex].model;
+	vec4 worldPos = model * vec4(vPosition, 1.0f);
+	gl_Position = cameraData.viewproj * worldPos;
+	outColor = vColor;
+	outWorldPos = worldPos.xyz;
+	outNormal = transpose(inverse(mat3(model))) * vNormal;
+	outUV = vUV;
+	outTangent = vec4(mat3(model) * vTangent.xyz, vTangent.w);
+}
@@ -0,0 +1,33 @@
+#version 450
+
+layout (location = 0) in vec3 vPosition;
+layout (location = 1) in vec3 vNormal;
+layout (location = 2) in vec3 vColor;
+layout (location = 3) in vec2 vUV;
+layout (location = 4) in vec4 vTangent;
+
+layout (location = 0) out vec3 outColor;
+layout (location = 1) out vec3 outWorldPos;
+layout (location = 2) out vec3 outNormal;
+layout (location = 3) out vec2 outUV;
+layout (location = 4) out vec4 outTangent;
+
+//push constants block
+layout( push_constant ) uniform constants
+{
+	mat4 render_matrix;
+	mat4 model;
+} PushConstants;
+
+void main() 
+{	
+	vec4 worldPos = PushConstants.model * vec4(vPosition, 1.0f);
+	gl_Position = PushConstants.render_matrix * vec4(vPosition, 1.0f);
+	outColor = vColor;
+	outWorldPos = worldPos.xyz;
+	//normal matrix, keeps normals perpendicular under non-uniform scale
+	outNormal = transpose(inverse(mat3(PushConstants.model))) * vNormal;
+	outUV = vUV;
+	//w is the handedness of the bitangent
+	outTangent = vec4(mat3(PushConstants.model) * vTangent.xyz, vTangent.w);
+}
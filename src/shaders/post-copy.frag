@@ -0,0 +1,12 @@
+#version 450
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+layout (set = 0, binding = 0) uniform sampler2D inputImage;
+
+void main() 
+{
+	outFragColor = vec4(texture(inputImage, inUV).rgb, 1.0f);
+}
@@ -0,0 +1,62 @@
+#version 450
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+layout (set = 0, binding = 0) uniform sampler2D inputImage;
+
+//x: span max, y: reduce mul, z: reduce min
+layout( push_constant ) uniform constants
+{
+	vec4 params;
+	vec4 texelSize;
+} PushConstants;
+
+//edges are found on perceptual brightness, the input is linear
+float luma(vec3 color)
+{
+	return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+//FXAA 3.11 console version: blur along the edge direction found from the 2x2 neighbourhood
+void main() 
+{
+	vec2 texel = PushConstants.texelSize.xy;
+	float spanMax = PushConstants.params.x;
+	float reduceMul = PushConstants.params.y;
+	float reduceMin = PushConstants.params.z;
+
+	vec3 rgbM = texture(inputImage, inUV).rgb;
+	float lumaNW = luma(texture(inputImage, inUV + vec2(-1.0, -1.0) * texel).rgb);
+	float lumaNE = luma(texture(inputImage, inUV + vec2(1.0, -1.0) * texel).rgb);
+	float lumaSW = luma(texture(inputImage, inUV + vec2(-1.0, 1.0) * texel).rgb);
+	float lumaSE = luma(texture(inputImage, inUV + vec2(1.0, 1.0) * texel).rgb);
+	float lumaM = luma(rgbM);
+
+	float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+	float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+	vec2 dir;
+	dir.x = -((lumaNW + lumaNE) - (lumaSW + lumaSE));
+	dir.y = ((lumaNW + lumaSW) - (lumaNE + lumaSE));
+
+	float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * (0.25 * reduceMul), reduceMin);
+	float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+	dir = clamp(dir * rcpDirMin, vec2(-spanMax), vec2(spanMax)) * texel;
+
+	vec3 rgbA = 0.5 * (
+		texture(inputImage, inUV + dir * (1.0 / 3.0 - 0.5)).rgb +
+		texture(inputImage, inUV + dir * (2.0 / 3.0 - 0.5)).rgb);
+	vec3 rgbB = rgbA * 0.5 + 0.25 * (
+		texture(inputImage, inUV + dir * -0.5).rgb +
+		texture(inputImage, inUV + dir * 0.5).rgb);
+
+	//the wider blur went past the edge, use the narrow one
+	float lumaB = luma(rgbB);
+	if (lumaB < lumaMin || lumaB > lumaMax) {
+		outFragColor = vec4(rgbA, 1.0f);
+	} else {
+		outFragColor = vec4(rgbB, 1.0f);
+	}
+}
@@ -0,0 +1,43 @@
+#version 450
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+layout (set = 0, binding = 0) uniform sampler2D inputImage;
+
+//x: exposure, y: gamma, z: operator (0 Reinhard, 1 ACES)
+layout( push_constant ) uniform constants
+{
+	vec4 params;
+	vec4 texelSize;
+} PushConstants;
+
+vec3 reinhard(vec3 color)
+{
+	return color / (1.0 + color);
+}
+
+//Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color)
+{
+	const float a = 2.51;
+	const float b = 0.03;
+	const float c = 2.43;
+	const float d = 0.59;
+	const float e = 0.14;
+	return clamp((color * (a * color + b)) / (color * (c * color + d) + e), 0.0, 1.0);
+}
+
+void main() 
+{
+	vec3 color = texture(inputImage, inUV).rgb * PushConstants.params.x;
+	if (PushConstants.params.z < 0.5) {
+		color = reinhard(color);
+	} else {
+		color = aces(color);
+	}
+	//the srgb swapchain does the display encoding, this is an adjustment on top of it
+	color = pow(color, vec3(1.0 / PushConstants.params.y));
+	outFragColor = vec4(color, 1.0f);
+}
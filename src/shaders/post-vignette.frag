@@ -0,0 +1,26 @@
+#version 450
+
+layout (location = 0) in vec2 inUV;
+
+layout (location = 0) out vec4 outFragColor;
+
+layout (set = 0, binding = 0) uniform sampler2D inputImage;
+
+//x: intensity, y: radius, z: softness
+layout( push_constant ) uniform constants
+{
+	vec4 params;
+	vec4 texelSize;
+} PushConstants;
+
+void main() 
+{
+	vec3 color = texture(inputImage, inUV).rgb;
+	//0 in the center, 1 in the corners
+	float dist = length(inUV - 0.5) * sqrt(2.0);
+	float radius = PushConstants.params.y;
+	float softness = PushConstants.params.z;
+	float vignette = smoothstep(radius, radius - softness, dist);
+	color *= mix(1.0 - PushConstants.params.x, 1.0, vignette);
+	outFragColor = vec4(color, 1.0f);
+}
@@ -0,0 +1,54 @@
+#version 450
+#extension GL_EXT_buffer_reference : require
+
+layout (location = 0) out vec3 outColor;
+layout (location = 1) out vec3 outWorldPos;
+layout (location = 2) out vec3 outNormal;
+layout (location = 3) out vec2 outUV;
+layout (location = 4) out vec4 outTangent;
+
+layout (set = 0, binding = 0) uniform CameraBuffer {
+	mat4 view;
+	mat4 proj;
+	mat4 viewproj;
+} cameraData;
+
+//Vertex in mesh.rs, tightly packed: pos 0-2, normal 3-5, color 6-8, uv 9-10, tangent 11-14
+#define VERTEX_FLOATS 15
+
+layout (buffer_reference, std430, buffer_reference_align = 4) readonly buffer Vertices {
+	float data[];
+};
+
+//matches PulledPushConstants in pipeline.rs
+layout (push_constant) uniform constants
+{
+	mat4 model;
+	//start of the mesh pool chunk, gl_VertexIndex already includes the mesh's vertex offset
+	Vertices vertices;
+} PushConstants;
+
+vec3 read3(uint base)
+{
+	return vec3(PushConstants.vertices.data[base], PushConstants.vertices.data[base + 1], PushConstants.vertices.data[base + 2]);
+}
+
+//lit.vert with the vertex fetched from the pool through its device address instead of vertex attributes
+void main()
+{
+	uint base = uint(gl_VertexIndex) * VERTEX_FLOATS;
+	vec3 position = read3(base);
+	vec3 normal = read3(base + 3);
+	vec3 color = read3(base + 6);
+	vec2 uv = vec2(PushConstants.vertices.data[base + 9], PushConstants.vertices.data[base + 10]);
+	vec4 tangent = vec4(read3(base + 11), PushConstants.vertices.data[base + 14]);
+
+	mat4 model = PushConstants.model;
+	vec4 worldPos = model * vec4(position, 1.0f);
+	gl_Position = cameraData.viewproj * worldPos;
+	outColor = color;
+	outWorldPos = worldPos.xyz;
+	outNormal = transpose(inverse(mat3(model))) * normal;
+	outUV = uv;
+	outTangent = vec4(mat3(model) * tangent.xyz, tangent.w);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout (location = 0) in vec3 vPosition;
+
+//same block as the lit pipelines, render_matrix is the light's view projection times the model
+layout( push_constant ) uniform constants
+{
+	mat4 render_matrix;
+	mat4 model;
+} PushConstants;
+
+void main() 
+{	
+	gl_Position = PushConstants.render_matrix * vec4(vPosition, 1.0f);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout (location = 0) in vec3 inDirection;
+
+layout (location = 0) out vec4 outFragColor;
+
+layout (set = 1, binding = 0) uniform samplerCube environmentMap;
+
+void main() 
+{
+	outFragColor = vec4(texture(environmentMap, normalize(inDirection)).rgb, 1.0f);
+}
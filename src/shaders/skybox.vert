@@ -0,0 +1,21 @@
+#version 450
+
+layout (location = 0) out vec3 outDirection;
+
+layout (set = 0, binding = 0) uniform CameraBuffer {
+	mat4 view;
+	mat4 proj;
+	mat4 viewproj;
+} cameraData;
+
+//one triangle covering the screen at the far plane, the view direction is unprojected from it
+void main() 
+{
+	vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+	vec4 clipPos = vec4(uv * 2.0f - 1.0f, 1.0f, 1.0f);
+	gl_Position = clipPos;
+
+	vec4 viewPos = inverse(cameraData.proj) * clipPos;
+	//only the rotation of the view matters, its inverse is the transpose
+	outDirection = transpose(mat3(cameraData.view)) * (viewPos.xyz / viewPos.w);
+}
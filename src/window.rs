@@ -7,7 +7,7 @@ use winit::{
 };
 extern crate nalgebra as na;
 
-use super::engine::VulkanApp;
+use super::engine::{RenderSettings, ShadingPath, VulkanApp};
 
 const CAMERA_SPEED: f32 = 0.10;
 pub fn start() {
@@ -19,7 +19,27 @@ pub fn start() {
         .build(&event_loop)
         .unwrap();
 
-    let mut a = VulkanApp::new(&window);
+    //--deferred switches to the deferred renderer
+    let shading = if std::env::args().skip(1).any(|arg| arg == "--deferred") {
+        ShadingPath::Deferred
+    } else {
+        ShadingPath::Forward
+    };
+    //--gpu-driven culls and draws the opaque objects on the GPU
+    let gpu_driven = std::env::args().skip(1).any(|arg| arg == "--gpu-driven");
+    //--vertex-pulling fetches vertices in the shaders instead of through vertex input
+    let vertex_pulling = std::env::args().skip(1).any(|arg| arg == "--vertex-pulling");
+    //--frame-stats prints the bind and draw counts every 1000 frames
+    let frame_stats = std::env::args().skip(1).any(|arg| arg == "--frame-stats");
+    //--memory-report prints the GPU memory use every 1000 frames
+    let memory_report = std::env::args().skip(1).any(|arg| arg == "--memory-report");
+    let settings = RenderSettings {
+        shading,
+        gpu_driven,
+        vertex_pulling,
+        ..RenderSettings::default()
+    };
+    let mut a = VulkanApp::new(&window, settings);
 
     let mut framenumber = 0;
 
@@ -54,12 +74,26 @@ pub fn start() {
                 (VirtualKeyCode::D, ElementState::Pressed) => {
                     camera_pos[1] += CAMERA_SPEED;
                 }
+                //steps through the G-buffer debug views of the deferred path
+                (VirtualKeyCode::G, ElementState::Released) => {
+                    if let Some(view) = a.gbuffer_view() {
+                        a.set_gbuffer_view(view.next());
+                    }
+                }
                 _ => (),
             },
             _ => (),
         },
         Event::MainEventsCleared => {
             a.draw(framenumber, camera_pos);
+            if framenumber % 1000 == 0 {
+                if frame_stats {
+                    println!("frame {} {:?}", framenumber, a.frame_stats());
+                }
+                if memory_report {
+                    print!("{}", a.memory_stats());
+                }
+            }
             framenumber = framenumber + 1;
         }
         _ => (),